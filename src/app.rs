@@ -1,12 +1,35 @@
-use std::{path::Path, sync::mpsc};
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    net::{AddrParseError, IpAddr, SocketAddr, ToSocketAddrs},
+    num::ParseIntError,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    task::Poll,
+    time::{Duration, Instant},
+};
 
 use eframe::{egui, epi};
 use log::{error, info, warn};
 use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
 
-use crate::{record::Record, udp::Receiver, udp::Sender};
+use crate::{
+    error::AppError,
+    record::{
+        now_millis, self_test, ByteOrder, CsvError, Format, Formatted, JsonRecord, OnConflict,
+        ParseError, Record, RecordCodec, TimedRecord, Version, TIMESTAMP_LEN,
+    },
+    udp::{
+        PayloadLen, Receiver, ReceiverBuilder, SendReport, SendTiming, Sender, SenderBuilder,
+        ToUdp, TrafficCounts, DELIMITER_ESCAPE, UDP_MAX_PAYLOAD,
+    },
+};
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 /// Represents app modes
 pub enum Mode {
     Send,
@@ -19,11 +42,70 @@ impl Default for Mode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Action triggered from keyboard instead of a button.
+enum Shortcut {
+    /// Start sending or listening.
+    Run,
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Where records to send come from.
+pub enum Source {
+    Sqlite,
+    Csv,
+    /// Made up with [Record::generate], for load testing.
+    Generated,
+    /// Lines of standard input, read with [Record::load_lines].
+    Stdin,
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self::Sqlite
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What listener does with a packet it can't make sense of.
+enum CorruptionPolicy {
+    /// Log a warning and keep going.
+    Warn,
+    /// Fail on the first corrupted packet.
+    Stop,
+    /// Only count it.
+    Ignore,
+    /// Log a warning with hexdump of the packet, if there is one.
+    /// Default, as listener always did so.
+    DumpRaw,
+}
+
+impl Default for CorruptionPolicy {
+    fn default() -> Self {
+        Self::DumpRaw
+    }
+}
+
+/// What listener loop does about a corrupted packet,
+/// as decided by [CorruptionPolicy].
+#[derive(Debug, PartialEq)]
+enum CorruptionAction {
+    Ignore,
+    Warn(String),
+    Stop(String),
+}
+
 /// A type for control messages, sent by UI thread to
 /// Worker thread.
 pub enum ControlMessage {
     /// Signals the thread to gracefully shutdown.
     Stop,
+    /// Asks listener to stop showing received records and warnings
+    /// until [ControlMessage::Resume], while still counting packets.
+    Pause,
+    /// Undoes [ControlMessage::Pause].
+    Resume,
 }
 
 /// A type for messages worker threads can send to UI thread
@@ -36,14 +118,367 @@ pub enum StatusMessage {
     /// A non-fatal error occured in worker thread, and
     /// it wats us to notify the user about it.
     Warning(String),
+    /// Listener worker couldn't read or use a packet.
+    ListenerWarning(ListenerWarning),
     /// Worker thread is still running, but wants us to display
     /// a message to the user.
     Info(String),
+    /// Sender worker is about to send this many records.
+    Total(u32),
+    /// Sender worker handed this many records to socket so far.
+    Progress(u32),
+    /// Listener worker got a packet, which either held
+    /// a valid record or was corrupted.
+    Received { corrupted: bool },
+    /// Listener worker got a valid record outside of id filter.
+    Filtered,
+    /// Listener worker skipped this many more packets from
+    /// addresses other than the one it only accepts.
+    OtherSource(u32),
+    /// OS dropped this many packets in total since listener
+    /// started, because receive buffer was full.
+    OsDropped(u32),
+    /// Listener worker got a valid record to display.
+    Record(ReceivedRecord),
+    /// Running totals of listener in count-only mode.
+    Traffic(TrafficCounts),
+}
+
+/// Problem listener worker ran into, whose source and error
+/// are logged as fields of their own besides the message.
+pub struct ListenerWarning {
+    /// Shown to user, error and source included.
+    pub message: String,
+    /// Where packet came from, if it's known.
+    pub source: Option<SocketAddr>,
+    pub error: String,
+}
+
+/// Queue holding at most `capacity` items, which evicts
+/// the oldest one to make room for a new one.
+struct RingBuffer<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> RingBuffer<T> {
+    /// Makes empty buffer, holding at least one item.
+    fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn push(&mut self, item: T) {
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+    }
+
+    /// Iterates over items, oldest first.
+    fn iter(&self) -> std::collections::vec_deque::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes capacity, evicting oldest items that don't fit anymore.
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        let excess = self.items.len().saturating_sub(self.capacity);
+        self.items.drain(..excess);
+    }
+}
+
+/// Record shown in listen mode table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedRecord {
+    /// When it arrived, in milliseconds since UNIX epoch.
+    pub received_at: u64,
+    pub source: SocketAddr,
+    pub record: Record,
+    /// Payload as it arrived, headers included, since decoded
+    /// data may hide control characters.
+    pub raw: Vec<u8>,
+    /// How long it took to arrive if it was timestamped, in milliseconds.
+    /// Clocks may be out of sync, so it can be negative.
+    pub latency: Option<i64>,
+}
+
+impl ReceivedRecord {
+    /// Contents of data column, either decoded data or raw payload as hex.
+    fn shown_data(&self, hex: bool) -> String {
+        if hex {
+            hex_line(&self.raw)
+        } else {
+            self.record.data.clone()
+        }
+    }
+}
+
+/// Columns of received records table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Time,
+    Source,
+    Id,
+    Data,
+    Latency,
+}
+
+impl Column {
+    const ALL: [Column; 5] = [
+        Column::Time,
+        Column::Source,
+        Column::Id,
+        Column::Data,
+        Column::Latency,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Column::Time => "Time",
+            Column::Source => "Source",
+            Column::Id => "Id",
+            Column::Data => "Data",
+            Column::Latency => "Latency (ms)",
+        }
+    }
+}
+
+/// How received records table is sorted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SortOrder {
+    column: Column,
+    ascending: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self {
+            column: Column::Time,
+            ascending: true,
+        }
+    }
+}
+
+impl SortOrder {
+    /// Order after clicking `column` header, which
+    /// reverses direction if it's already sorted by.
+    fn clicked(self, column: Column) -> Self {
+        Self {
+            column,
+            ascending: column != self.column || !self.ascending,
+        }
+    }
+}
+
+/// Severity of a log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Info,
+    Warning,
+    Failure,
+}
+
+impl Level {
+    /// Marks lines of this level in log.
+    fn tag(self) -> &'static str {
+        match self {
+            Level::Info => "INFO",
+            Level::Warning => "WARN",
+            Level::Failure => "FAIL",
+        }
+    }
+}
+
+/// A single line of log, which keeps level of the message
+/// it came from, so that it can be filtered.
+#[derive(Debug, Clone, PartialEq)]
+struct LogLine {
+    level: Level,
+    text: String,
+}
+
+/// Appends log lines to a file, moving it aside to `<path>.1`
+/// once it would grow past `max_size` bytes, so that it doesn't
+/// fill up the disk. Lines are buffered until [RollingLog::flush].
+#[derive(Debug)]
+struct RollingLog {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Size of current file, buffered lines included.
+    size: u64,
+    max_size: u64,
+}
+
+impl RollingLog {
+    /// Opens file at `path` for appending, creating it if needed.
+    fn open(path: &Path, max_size: u64) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_owned(),
+            file: BufWriter::new(file),
+            size,
+            max_size,
+        })
+    }
+
+    /// Where previous file goes once current one fills up.
+    fn rotated_path(&self) -> PathBuf {
+        rotated_path(&self.path)
+    }
+
+    /// Appends `line`, rotating file first if it wouldn't fit.
+    /// Line longer than `max_size` still goes into a file of its own.
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Replaces previous file with current one and starts a new one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        std::fs::rename(&self.path, self.rotated_path())?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Where [RollingLog] at `path` moves its previous file.
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut path = path.to_owned().into_os_string();
+    path.push(".1");
+    path.into()
+}
+
+/// Levels of log lines shown to user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LevelFilter {
+    info: bool,
+    warning: bool,
+    failure: bool,
+}
+
+impl Default for LevelFilter {
+    fn default() -> Self {
+        Self {
+            info: true,
+            warning: true,
+            failure: true,
+        }
+    }
+}
+
+impl LevelFilter {
+    fn shows(&self, level: Level) -> bool {
+        match level {
+            Level::Info => self.info,
+            Level::Warning => self.warning,
+            Level::Failure => self.failure,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// Packet counts of current or last worker.
+struct Counters {
+    sent: u32,
+    total: u32,
+    received: u32,
+    filtered: u32,
+    other_source: u32,
+    os_dropped: u32,
+    corrupted: u32,
+    /// Totals of count-only listener.
+    traffic: TrafficCounts,
+}
+
+impl Counters {
+    /// Fraction of records sent, 0 if total is unknown.
+    fn progress(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.sent as f32 / self.total as f32
+        }
+    }
+}
+
+/// Worker side of status channel. Sending fails once UI thread
+/// is gone, which workers should take as a signal to stop.
+struct StatusSender(mpsc::Sender<StatusMessage>);
+
+impl StatusSender {
+    fn send(&self, message: StatusMessage) -> Result<(), ()> {
+        self.0.send(message).map_err(|_| ())
+    }
+
+    /// Reports failure of worker, which is about to exit anyway,
+    /// so it doesn't matter whether UI thread is still there.
+    fn fail(&self, message: String) {
+        self.send(StatusMessage::Failure(message)).ok();
+    }
+
+    /// Passes on result of an operation, reporting
+    /// failure described by `context` if it failed.
+    fn check<T, E: Into<AppError>>(&self, res: Result<T, E>, context: &str) -> Result<T, ()> {
+        res.map_err(|e| self.fail(format!("{}: {}", context, e.into())))
+    }
 }
 
 struct Task {
     control: mpsc::Sender<ControlMessage>,
     status: mpsc::Receiver<StatusMessage>,
+    /// When worker was started, to show how long it's been running.
+    started: Instant,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+/// Subset of [App] state that persists between runs.
+struct Settings {
+    hdpi: bool,
+    mode: Mode,
+    bind_addr: String,
+    tx_addr: String,
+    source: Source,
+    db_file: String,
+    log_file: String,
+    log_file_kb: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        App::default().settings()
+    }
 }
 
 /// This struct represents application state.
@@ -54,16 +489,134 @@ pub struct App {
     mode: Mode,
     /// Address we will bind to for transmission or receving.
     bind_addr: String,
-    /// Address we transmit to.
+    /// Bytes to send as a single datagram, in hex.
+    raw_hex: String,
+    /// Whether socket is bound with `SO_REUSEADDR`, to share port.
+    reuse_addr: bool,
+    /// Whether to bind to a free port if the one asked for is taken.
+    fallback_port: bool,
+    /// Comma-separated addresses we transmit to.
     tx_addr: String,
-    /// Path to database to read records from.
+    /// Kind of file to read records from.
+    source: Source,
+    /// Path to database or CSV file to read records from.
     db_file: String,
+    /// Number of records to make up for [Source::Generated].
+    generate_count: usize,
+    /// Data length of records made up for [Source::Generated].
+    generate_len: usize,
+    /// Whether saving generated records may replace an existing database.
+    overwrite_db: bool,
+    /// Smallest id of records to send, blank for no limit.
+    min_id: String,
+    /// Largest id of records to send, blank for no limit.
+    max_id: String,
+    /// Number of records to send at most, blank for no limit.
+    max_records: String,
+    /// Path to database to save received records to, blank to not save them.
+    out_db_file: String,
+    /// Whether received records overwrite saved ones with the same id.
+    overwrite: bool,
+    /// Smallest id of received records to log, blank for no limit.
+    filter_min_id: String,
+    /// Largest id of received records to log, blank for no limit.
+    filter_max_id: String,
+    /// Address to only accept packets from, blank to accept any.
+    source_filter: String,
+    /// Byte separating several records in a packet as hex, blank for none.
+    delimiter: String,
+    /// Whether listener keeps reading packets while paused, rather
+    /// than leaving them to queue up in OS receive buffer.
+    read_while_paused: bool,
+    /// Whether listener only counts packets, without decoding them.
+    count_only: bool,
+    /// Whether listener sends every packet back to where it came from.
+    echo: bool,
+    /// Whether running listener was asked to pause.
+    paused: bool,
+    /// Whether to send records as JSON instead of binary format.
+    json: bool,
+    /// Whether record ids are big-endian in binary format.
+    big_endian: bool,
+    /// Whether to send bare data without record ids in binary format.
+    omit_id: bool,
+    /// How to decode received records.
+    codec: RecordCodec,
+    /// Whether records with empty data count as corrupted,
+    /// as they can't be told apart from truncated ones.
+    reject_empty: bool,
+    /// What listener does with corrupted packets.
+    corruption: CorruptionPolicy,
+    /// Whether records carry send time, to measure latency.
+    timestamps: bool,
+    /// Size of the largest datagram to send or receive.
+    max_payload: usize,
+    /// Size of listener socket receive buffer in KiB, 0 to keep OS default.
+    recv_buffer_kb: u32,
+    /// Whether to split records larger than a datagram
+    /// into fragments.
+    fragment: bool,
+    /// Whether to append and validate checksums.
+    checksum: bool,
+    /// Whether to number datagrams to detect packet loss.
+    sequencing: bool,
+    /// Whether to pack several records into a single datagram.
+    batching: bool,
+    /// Whether to log payloads instead of sending them.
+    dry_run: bool,
+    /// Whether to skip records that fail to send instead of stopping.
+    best_effort: bool,
+    /// Whether to measure how long every socket write takes.
+    timing: bool,
+    /// Whether to check that destination is reachable before sending.
+    probe: bool,
+    /// Whether to start over from the first record after sending the last one,
+    /// until stopped.
+    loop_send: bool,
+    /// Send rate limit in packets per second, 0 for unlimited.
+    rate: u32,
+    /// Delay between packets in milliseconds, overrides `rate` unless 0.
+    delay_ms: u32,
+    /// How many times every packet is sent in a row.
+    copies: u32,
+    /// Whether sending to broadcast addresses is allowed.
+    broadcast: bool,
+    /// Time-to-live of sent datagrams, 0 to keep OS default.
+    ttl: u32,
     /// Wraps control and status channels for currently running worker thread.
     task: Option<Task>,
+    /// Reports outcome of a one-off action, such as self test or
+    /// sending a raw packet, while it runs on its own thread.
+    one_off: Option<mpsc::Receiver<StatusMessage>>,
     /// Whether previous worker finished successfully.
     status: Option<bool>,
-    /// Log displayed to user.
-    log: String,
+    /// Log displayed to user, including lines filtered out.
+    log: VecDeque<LogLine>,
+    /// Levels of log lines to display.
+    log_filter: LevelFilter,
+    /// Number of most recent lines to keep in log, 0 for unlimited.
+    max_log_lines: usize,
+    /// Whether to prefix log lines with time they were added at.
+    log_timestamps: bool,
+    /// File every log line is appended to, blank for none.
+    log_file: String,
+    /// Log file path as being typed, which only replaces `log_file`
+    /// on Enter, so that no file is created for every prefix of it.
+    log_file_input: String,
+    /// Size in KiB past which log file is rotated.
+    log_file_kb: u32,
+    /// Open while lines are written to `log_file`.
+    log_writer: Option<RollingLog>,
+    /// Latest records got by listener, oldest first.
+    received: RingBuffer<ReceivedRecord>,
+    /// How to display received records.
+    sort: SortOrder,
+    /// Whether data column shows raw payloads as hex instead of text.
+    data_as_hex: bool,
+    /// Packet counts displayed to user.
+    counters: Counters,
+    /// Triggered from keyboard during this frame.
+    shortcut: Option<Shortcut>,
 }
 
 impl Default for App {
@@ -72,11 +625,67 @@ impl Default for App {
             hdpi: true,
             mode: Mode::default(),
             bind_addr: "0.0.0.0:8142".to_owned(),
+            raw_hex: String::new(),
+            reuse_addr: false,
+            fallback_port: false,
             tx_addr: "".to_owned(),
+            source: Source::default(),
             db_file: "test/test.sqlite".to_owned(),
+            generate_count: 1000,
+            generate_len: 64,
+            overwrite_db: false,
+            min_id: String::new(),
+            max_id: String::new(),
+            max_records: String::new(),
+            out_db_file: String::new(),
+            overwrite: true,
+            filter_min_id: String::new(),
+            filter_max_id: String::new(),
+            source_filter: String::new(),
+            delimiter: String::new(),
+            read_while_paused: true,
+            count_only: false,
+            echo: false,
+            paused: false,
+            json: false,
+            big_endian: false,
+            omit_id: false,
+            codec: RecordCodec::default(),
+            reject_empty: false,
+            corruption: CorruptionPolicy::default(),
+            timestamps: false,
+            max_payload: UDP_MAX_PAYLOAD,
+            recv_buffer_kb: 0,
+            fragment: false,
+            checksum: false,
+            sequencing: false,
+            batching: false,
+            dry_run: false,
+            best_effort: false,
+            timing: false,
+            probe: false,
+            loop_send: false,
+            rate: 0,
+            delay_ms: 0,
+            copies: 1,
+            broadcast: false,
+            ttl: 0,
             task: None,
+            one_off: None,
             status: None,
-            log: String::new(),
+            log: VecDeque::new(),
+            log_filter: LevelFilter::default(),
+            max_log_lines: 10_000,
+            log_timestamps: true,
+            log_file: String::new(),
+            log_file_input: String::new(),
+            log_file_kb: 1024,
+            log_writer: None,
+            received: RingBuffer::new(DEFAULT_HISTORY),
+            sort: SortOrder::default(),
+            data_as_hex: false,
+            counters: Counters::default(),
+            shortcut: None,
         }
     }
 }
@@ -84,229 +693,2058 @@ impl Default for App {
 impl App {
     /// Controls UI and worker for [Mode::Send] mode.
     fn sender(&mut self, ui: &mut egui::Ui) {
-        ui.set_enabled(self.task.is_none());
-
-        ui.label("Bind to address");
-        ui.text_edit_singleline(&mut self.bind_addr)
-            .on_hover_text("Interface and port to bind to");
-        ui.label("Send to address");
-        ui.text_edit_singleline(&mut self.tx_addr)
-            .on_hover_text("Address and port to send to");
-        ui.label("Read data from");
-        ui.text_edit_singleline(&mut self.db_file)
-            .on_hover_text("sqlite file to read from");
+        let mut valid = false;
+        ui.wrap(|ui| {
+            ui.set_enabled(self.task.is_none());
+            valid = self.sender_settings(ui);
+        });
 
-        if self.task.is_some() {
-            ui.label("Running...");
+        if self.task.is_some() || self.status.is_some() {
+            let text = format!("Sent {}/{}", self.counters.sent, self.counters.total);
+            if self.counters.total > 0 {
+                progress_bar(ui, self.counters.progress());
+            }
+            ui.label(text);
+        }
+        if let Some(ref mut task) = self.task {
+            if ui.button("Stop").clicked() {
+                // Worker might have exited on its own already
+                task.control.send(ControlMessage::Stop).ok();
+            }
         } else {
-            if ui.button("Run").clicked() {
+            let clicked = ui.button("Run").on_hover_text("Shortcut: Enter").clicked();
+            if (clicked || self.shortcut == Some(Shortcut::Run)) && valid {
+                self.counters = Counters::default();
                 let (control_sender, control_receiver) = std::sync::mpsc::channel();
                 let (status_sender, status_receiver) = std::sync::mpsc::channel();
+                let status_sender = StatusSender(status_sender);
                 self.task = Some(Task {
                     control: control_sender,
                     status: status_receiver,
+                    started: Instant::now(),
                 });
 
                 let addr = self.bind_addr.clone();
+                let source = self.source;
                 let path_str = self.db_file.clone();
-                let dest = self.tx_addr.clone();
+                let generate_count = self.generate_count;
+                let generate_len = self.generate_len;
+                let dests: Vec<String> = split_destinations(&self.tx_addr)
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect();
+                let id_range = parse_id_range(&self.min_id, &self.max_id);
+                let max_records = parse_limit(&self.max_records);
+                let json = self.json;
+                let format = Format {
+                    version: if self.omit_id {
+                        Version::NoId
+                    } else {
+                        Version::V1
+                    },
+                    byte_order: self.byte_order(),
+                };
+                let timestamps = self.timestamps;
+                let max_payload = self.max_payload;
+                let fragment = self.fragment;
+                let checksum = self.checksum;
+                let sequencing = self.sequencing;
+                let batching = self.batching;
+                let dry_run = self.dry_run;
+                let best_effort = self.best_effort;
+                let timing = self.timing;
+                let probe = self.probe;
+                let loop_send = self.loop_send;
+                let rate = self.rate;
+                let delay_ms = self.delay_ms;
+                let copies = self.copies;
+                let broadcast = self.broadcast;
+                let ttl = self.ttl;
+                let reuse_addr = self.reuse_addr;
+                let fallback_port = self.fallback_port;
 
                 std::thread::spawn(move || -> Result<(), ()> {
-                    // Although we don't use it, take in case UI thread
-                    // tries to send us messages
-                    let _receiver = control_receiver;
-
-                    status_sender
-                        .send(StatusMessage::Info("Sending data...".into()))
-                        .unwrap();
+                    status_sender.send(StatusMessage::Info("Sending data...".into()))?;
 
-                    let mut udp_sender = Sender::new(addr).map_err(|e| {
+                    // Socket options are set after binding, so that their
+                    // failures aren't reported as failures to bind
+                    let builder = SenderBuilder::new().reuse_addr(reuse_addr);
+                    let builder = if delay_ms > 0 {
+                        builder.interval(Some(Duration::from_millis(delay_ms.into())))
+                    } else {
+                        builder.rate(Some(rate))
+                    };
+                    let mut udp_sender = if addr.trim().is_empty() {
                         status_sender
-                            .send(StatusMessage::Failure(format!(
-                                "Couldn't bind to buffer: {}",
-                                e
-                            )))
-                            .unwrap();
-                    })?;
+                            .check(builder.bind_ephemeral(), "Couldn't bind to any port")?
+                    } else {
+                        let bind = |addr: &str| builder.bind(addr);
+                        bind_socket(addr.trim(), fallback_port, bind, &status_sender)?
+                    };
+                    if let Ok(local) = udp_sender.local_addr() {
+                        status_sender
+                            .send(StatusMessage::Info(format!("Sending from {}", local)))?;
+                    }
+                    status_sender.check(
+                        udp_sender.set_max_payload(max_payload),
+                        "Invalid max payload",
+                    )?;
+                    udp_sender.set_fragmentation(fragment);
+                    udp_sender.set_checksum(checksum);
+                    udp_sender.set_sequencing(sequencing);
+                    udp_sender.set_batching(batching);
+                    udp_sender.set_dry_run(dry_run);
+                    udp_sender.set_best_effort(best_effort);
+                    udp_sender.set_timing(timing);
+                    udp_sender.set_copies(copies);
+                    status_sender.check(
+                        udp_sender.set_broadcast(broadcast),
+                        "Couldn't configure broadcast",
+                    )?;
+                    if ttl > 0 {
+                        status_sender.check(udp_sender.set_ttl(ttl), "Couldn't set TTL")?;
+                    }
 
                     let path = Path::new(&path_str);
-                    if !path.is_file() {
-                        status_sender
-                            .send(StatusMessage::Failure(format!(
-                                "No such file: {}",
-                                path_str
-                            )))
-                            .unwrap();
+                    if matches!(source, Source::Sqlite | Source::Csv) && !path.is_file() {
+                        status_sender.fail(format!("No such file: {}", path_str));
                         return Err(());
                     }
 
-                    let conn = Connection::open(path).map_err(|e| {
-                        status_sender
-                            .send(StatusMessage::Failure(format!("Couldn't open file: {}", e)))
-                            .unwrap();
-                    })?;
-
-                    let data = Record::load(conn).map_err(|e| {
-                        status_sender
-                            .send(StatusMessage::Failure(format!(
-                                "Couldn't load records from DB: {}",
-                                e
-                            )))
-                            .unwrap();
-                    })?;
-
-                    udp_sender.send(data.iter(), dest).map_err(|e| {
-                        status_sender
-                            .send(StatusMessage::Failure(format!("Error sending data: {}", e)))
-                            .unwrap();
+                    let id_range = id_range
+                        .map_err(|e| status_sender.fail(format!("Invalid id range: {}", e)))?;
+                    let max_records = max_records.map_err(|e| {
+                        status_sender.fail(format!("Invalid number of records: {}", e))
                     })?;
 
-                    status_sender
-                        .send(StatusMessage::Info("Done!".into()))
-                        .unwrap();
-                    status_sender.send(StatusMessage::Success).unwrap();
-                    Ok(())
-                });
-            }
-        }
-    }
-
-    /// Controls UI and worker for [Mode::Listen] mode.
-    fn listener(&mut self, ui: &mut egui::Ui) {
-        ui.label("Listen on address");
+                    // Makes records of a single pass, so they can be sent over and over
+                    type Records = Box<dyn Iterator<Item = rusqlite::Result<Record>>>;
+                    type MakeRecords<'a> = Box<dyn FnMut() -> Records + 'a>;
+                    let (mut make_records, total): (MakeRecords<'_>, _) = match source {
+                        Source::Sqlite => {
+                            let conn = status_sender
+                                .check(Connection::open(path), "Couldn't open file")?;
+                            let total = status_sender.check(
+                                Record::count_range(&conn, id_range.clone()),
+                                "Couldn't load records from DB",
+                            )?;
+                            // Later passes reopen database, picking up any changes
+                            let mut conn = Some(conn);
+                            let make = move || -> Records {
+                                match conn.take().map_or_else(|| Connection::open(path), Ok) {
+                                    Ok(conn) => {
+                                        Box::new(Record::load_iter(conn).range(id_range.clone()))
+                                    }
+                                    Err(e) => Box::new(std::iter::once(Err(e))),
+                                }
+                            };
+                            (Box::new(make), total)
+                        }
+                        Source::Csv => {
+                            let records = std::fs::File::open(path)
+                                .map_err(CsvError::from)
+                                .and_then(Record::load_csv)
+                                .map_err(|e| {
+                                    status_sender
+                                        .fail(format!("Couldn't load records from CSV: {}", e))
+                                })?;
+                            let records: Vec<_> = records
+                                .into_iter()
+                                .filter(|record| id_range.contains(&record.id))
+                                .collect();
+                            let total = records.len() as u32;
+                            let make = move || -> Records {
+                                Box::new(records.clone().into_iter().map(Ok))
+                            };
+                            (Box::new(make), total)
+                        }
+                        Source::Generated => {
+                            let records: Vec<_> = Record::generate(generate_count, generate_len)
+                                .into_iter()
+                                .filter(|record| id_range.contains(&record.id))
+                                .collect();
+                            let total = records.len() as u32;
+                            let make = move || -> Records {
+                                Box::new(records.clone().into_iter().map(Ok))
+                            };
+                            (Box::new(make), total)
+                        }
+                        Source::Stdin => {
+                            let stdin = std::io::stdin();
+                            let records = status_sender.check(
+                                Record::load_lines(stdin.lock()),
+                                "Couldn't read records from stdin",
+                            )?;
+                            let records: Vec<_> = records
+                                .into_iter()
+                                .filter(|record| id_range.contains(&record.id))
+                                .collect();
+                            let total = records.len() as u32;
+                            let make = move || -> Records {
+                                Box::new(records.clone().into_iter().map(Ok))
+                            };
+                            (Box::new(make), total)
+                        }
+                    };
 
-        ui.wrap(|ui| {
-            ui.set_enabled(self.task.is_none());
-            ui.text_edit_singleline(&mut self.bind_addr);
-        });
+                    let total = match max_records {
+                        Some(max) => total.min(max.min(u32::MAX as usize) as u32),
+                        None => total,
+                    };
+                    let mut first = true;
+                    let passes = Cell::new(0);
+                    let records = repeat_passes(
+                        || {
+                            if !first && !loop_send {
+                                return None;
+                            }
+                            first = false;
+                            let records = make_records();
+                            Some(match max_records {
+                                Some(max) => Box::new(records.take(max)),
+                                None => records,
+                            })
+                        },
+                        &passes,
+                    );
 
-        if let Some(ref mut task) = self.task {
-            if ui.button("Stop").clicked() {
-                task.control.send(ControlMessage::Stop).unwrap();
-            }
-        } else {
-            if ui.button("Listen").clicked() {
-                let (control_sender, control_receiver) = std::sync::mpsc::channel();
-                let (status_sender, status_receiver) = std::sync::mpsc::channel();
+                    for dest in dests.iter().filter(|_| probe && !dry_run) {
+                        match udp_sender.probe(dest.as_str(), PROBE_TIMEOUT) {
+                            Ok(true) => status_sender
+                                .send(StatusMessage::Info(format!("{} appears reachable", dest)))?,
+                            Ok(false) => status_sender.send(StatusMessage::Warning(format!(
+                                "{} appears unreachable (port closed?)",
+                                dest
+                            )))?,
+                            Err(e) => status_sender.send(StatusMessage::Warning(format!(
+                                "Couldn't check {}: {}",
+                                dest, e
+                            )))?,
+                        }
+                    }
 
-                self.task = Some(Task {
-                    control: control_sender,
-                    status: status_receiver,
-                });
+                    // Stream records straight from DB, stopping at first error
+                    let mut db_error = None;
+                    status_sender.send(StatusMessage::Total(total))?;
+                    let mut sent = 0;
+                    let mut pass = 0;
+                    let stopped = Cell::new(false);
+                    let records =
+                        records.scan((), |_, record| record.map_err(|e| db_error = Some(e)).ok());
+                    // Cut over-long records on a character boundary ourselves,
+                    // since sender would cut them anywhere
+                    let mut too_long = Vec::new();
+                    let limit = if fragment || json {
+                        None
+                    } else if timestamps {
+                        Some(udp_sender.item_capacity().saturating_sub(TIMESTAMP_LEN))
+                    } else {
+                        Some(udp_sender.item_capacity())
+                    };
+                    let records = records.map(|mut record| {
+                        if let Some(limit) = limit {
+                            if record.truncate(limit, format.version) {
+                                too_long.push(record.id);
+                            }
+                        }
+                        record
+                    });
+                    let data = until_stopped(records, &control_receiver, &stopped)
+                        // Stop early if UI thread is gone
+                        .take_while(|_| {
+                            // Progress starts over with every pass
+                            if passes.get() != pass {
+                                pass = passes.get();
+                                sent = 0;
+                            }
+                            sent += 1;
+                            status_sender.send(StatusMessage::Progress(sent)).is_ok()
+                        });
 
-                let addr = self.bind_addr.clone();
+                    if json && timestamps {
+                        status_sender.send(StatusMessage::Warning(
+                            "JSON records carry no timestamps, sending without them".into(),
+                        ))?;
+                    }
+                    let report = if json {
+                        send_to_dests(&mut udp_sender, data.map(JsonRecord), &dests)
+                    } else if timestamps {
+                        let data = data.map(|record| Formatted {
+                            item: TimedRecord::now(record),
+                            format,
+                        });
+                        send_to_dests(&mut udp_sender, data, &dests)
+                    } else {
+                        let data = data.map(|item| Formatted { item, format });
+                        send_to_dests(&mut udp_sender, data, &dests)
+                    };
+                    let report = report.map_err(|e| status_sender.fail(describe_send_error(&e)))?;
 
-                std::thread::spawn(move || -> Result<(), ()> {
-                    let mut udp_receiver: Receiver<Record> = Receiver::new(&addr).map_err(|e| {
-                        status_sender
-                            .send(StatusMessage::Failure(format!(
-                                "Couldn't bind to address: {}",
-                                e
-                            )))
-                            .unwrap()
-                    })?;
+                    for datagram in udp_sender.take_dry_run() {
+                        status_sender.send(StatusMessage::Info(hex_line(&datagram)))?;
+                    }
 
-                    status_sender
-                        .send(StatusMessage::Info(format!("Listening on {}...", &addr)))
-                        .unwrap();
+                    if let Some(e) = db_error {
+                        return status_sender.check(Err(e), "Couldn't load records from DB");
+                    }
 
-                    loop {
-                        let res = udp_receiver.next().expect("Never returns None");
-                        match res {
-                            Ok(record) => {
-                                let msg = format!("Got record [{} : {}]", record.id, record.data);
-                                status_sender.send(StatusMessage::Info(msg)).unwrap();
-                            }
-                            Err(crate::udp::Error::ParseError(_)) => {
-                                status_sender
-                                    .send(StatusMessage::Warning("Got corrupted packet".into()))
-                                    .unwrap();
-                            }
-                            Err(crate::udp::Error::Io(e)) => {
-                                if e.kind() != std::io::ErrorKind::TimedOut
-                                    && e.kind() != std::io::ErrorKind::WouldBlock
-                                {
-                                    let msg = format!(
-                                        "Error while reading from socket: {}",
-                                        e.to_string(),
-                                    );
-                                    status_sender.send(StatusMessage::Warning(msg)).unwrap();
-                                }
-                            }
-                        }
-                        if let Ok(ControlMessage::Stop) = control_receiver.try_recv() {
-                            status_sender
-                                .send(StatusMessage::Info("Stopped".into()))
-                                .unwrap();
-                            break;
-                        }
+                    if loop_send {
+                        status_sender.send(StatusMessage::Info(format!(
+                            "Completed {} passes",
+                            passes.get()
+                        )))?;
                     }
 
-                    status_sender.send(StatusMessage::Success).unwrap();
+                    if stopped.get() {
+                        status_sender.send(StatusMessage::Info(if loop_send {
+                            format!("Stopped after sending {} records", report.items_sent)
+                        } else {
+                            format!(
+                                "Stopped after sending {} of {} records",
+                                report.items_sent, total
+                            )
+                        }))?;
+                        status_sender.send(StatusMessage::Success)?;
+                        return Ok(());
+                    }
 
+                    if !too_long.is_empty() {
+                        status_sender.send(StatusMessage::Warning(format!(
+                            "{} records were too long and got truncated, ids: {}",
+                            too_long.len(),
+                            id_list(&too_long)
+                        )))?;
+                    }
+                    if report.truncated > 0 {
+                        status_sender.send(StatusMessage::Warning(format!(
+                            "{} records were too large and got truncated",
+                            report.truncated
+                        )))?;
+                    }
+                    if report.dropped > 0 {
+                        status_sender.send(StatusMessage::Warning(format!(
+                            "{} records were too large for a batch and weren't sent",
+                            report.dropped
+                        )))?;
+                    }
+                    if let Some(last) = report.errors.last() {
+                        status_sender.send(StatusMessage::Warning(format!(
+                            "{} records failed to send, last error: {:?}",
+                            report.failed, last
+                        )))?;
+                    }
+                    status_sender.send(StatusMessage::Info(format!(
+                        "Done! Sent {} records, {} bytes, digest {}",
+                        report.items_sent, report.bytes_sent, report.digest
+                    )))?;
+                    for dest in report.destinations.iter() {
+                        let line = format!(
+                            "{}: {} datagrams, {} bytes",
+                            dest.addr, dest.datagrams, dest.bytes_sent
+                        );
+                        status_sender.send(match dest.last_error {
+                            Some(e) => StatusMessage::Warning(format!(
+                                "{}, {} failed, last error: {:?}",
+                                line, dest.failed, e
+                            )),
+                            None => StatusMessage::Info(line),
+                        })?;
+                    }
+                    if let Some(timing) = report.timing.as_ref().filter(|t| t.writes > 0) {
+                        status_sender.send(StatusMessage::Info(timing_line(timing)))?;
+                    }
+                    let stats = udp_sender.stats();
+                    if stats.datagrams > 0 {
+                        status_sender.send(StatusMessage::Info(format!(
+                            "{} datagrams, sizes min {} / avg {:.1} / max {} bytes, {} errors",
+                            stats.datagrams,
+                            stats.min_payload,
+                            stats.avg_payload(),
+                            stats.max_payload,
+                            stats.errors
+                        )))?;
+                    }
+                    status_sender.send(StatusMessage::Success)?;
                     Ok(())
                 });
             }
         }
     }
-}
-
-impl epi::App for App {
-    fn name(&self) -> &str {
-        "UDP Test app"
-    }
 
-    fn update(&mut self, ctx: &egui::CtxRef, _frame: &mut epi::Frame<'_>) {
-        if self.hdpi {
-            ctx.set_pixels_per_point(2.0);
+    /// Shows send settings, returning whether addresses are valid.
+    fn sender_settings(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.label("Bind to address");
+        ui.text_edit_singleline(&mut self.bind_addr).on_hover_text(
+            "Local address and port to send from, leave blank for any free port. \
+                 Address picks interface packets leave from, port 0 any free port",
+        );
+        let bind_error = if self.bind_addr.trim().is_empty() {
+            None
         } else {
-            ctx.set_pixels_per_point(1.0);
+            validate_local_addr(&self.bind_addr).err()
+        };
+        if let Some(e) = bind_error {
+            ui.colored_label(egui::Color32::RED, e);
         }
-
-        egui::TopPanel::top("wrap_app_top_bar").show(ctx, |ui| {
-            ui.horizontal_wrapped(|ui| {
-                let style: egui::Style = (*ui.ctx().style()).clone();
-                let new_visuals = style.visuals.light_dark_small_toggle_button(ui);
-                if let Some(visuals) = new_visuals {
-                    ui.ctx().set_visuals(visuals);
-                }
-                ui.checkbox(&mut self.hdpi, "HiDPI");
-
+        self.bind_options(ui);
+        ui.label("Send to address");
+        ui.text_edit_singleline(&mut self.tx_addr)
+            .on_hover_text("Address and port to send to, separate several with commas");
+        let dests = split_destinations(&self.tx_addr);
+        let tx_error = if dests.is_empty() {
+            Some("Enter address to send to")
+        } else {
+            dests.into_iter().find_map(|dest| validate_addr(dest).err())
+        };
+        if let Some(e) = tx_error {
+            ui.colored_label(egui::Color32::RED, e);
+        }
+        ui.horizontal(|ui| {
+            ui.label("Read data from");
+            ui.selectable_value(&mut self.source, Source::Sqlite, "SQLite");
+            ui.selectable_value(&mut self.source, Source::Csv, "CSV");
+            ui.selectable_value(&mut self.source, Source::Generated, "Generate test data");
+            ui.selectable_value(&mut self.source, Source::Stdin, "stdin")
+                .on_hover_text("Send every line piped into the app as a record");
+        });
+        if self.source == Source::Generated {
+            ui.add(egui::Slider::usize(&mut self.generate_count, 1..=100_000).text("records"))
+                .on_hover_text("Number of records to generate, with ids from 0");
+            ui.add(egui::Slider::usize(&mut self.generate_len, 0..=65_000).text("data length"))
+                .on_hover_text("Bytes of data in every generated record");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.db_file)
+                    .on_hover_text("Database to save generated records to");
+                let exists = Path::new(&self.db_file).exists();
                 ui.wrap(|ui| {
-                    ui.set_enabled(self.task.is_none());
-                    ui.selectable_value(&mut self.mode, Mode::Send, "Send");
-                    ui.selectable_value(&mut self.mode, Mode::Listen, "Listen");
+                    ui.set_enabled((!exists || self.overwrite_db) && self.one_off.is_none());
+                    if ui
+                        .button("Save as SQLite")
+                        .on_hover_text("Save generated records to reuse them later")
+                        .clicked()
+                    {
+                        self.save_generated();
+                    }
                 });
+                ui.checkbox(&mut self.overwrite_db, "Overwrite")
+                    .on_hover_text("Replace records table if file already exists");
+            });
+        } else if self.source == Source::Stdin {
+            ui.label("Records are read until stdin is closed, with ids from 0");
+        } else {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.db_file)
+                    .on_hover_text("File to read records from");
+                if ui.button("Browse...").clicked() {
+                    let dialog = match self.source {
+                        Source::Sqlite => {
+                            rfd::FileDialog::new().add_filter("SQLite database", &["sqlite", "db"])
+                        }
+                        Source::Csv | Source::Generated | Source::Stdin => {
+                            rfd::FileDialog::new().add_filter("CSV", &["csv"])
+                        }
+                    };
+                    let path = dialog.pick_file();
+                    if let Some(path) = path {
+                        self.db_file = path.display().to_string();
+                    }
+                }
+                if ui
+                    .button("Preview")
+                    .on_hover_text("Load records and log the first few, without sending them")
+                    .clicked()
+                {
+                    self.preview();
+                }
             });
+            if !Path::new(&self.db_file).is_file() {
+                ui.colored_label(egui::Color32::RED, "No such file");
+            }
+        }
+        ui.label("Ids to send");
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.min_id).desired_width(80.0))
+                .on_hover_text("Smallest id, blank for no limit");
+            ui.label("to");
+            ui.add(egui::TextEdit::singleline(&mut self.max_id).desired_width(80.0))
+                .on_hover_text("Largest id, blank for no limit");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Max records");
+            ui.add(egui::TextEdit::singleline(&mut self.max_records).desired_width(80.0))
+                .on_hover_text("Send only this many first records, blank for all");
+        });
+        ui.checkbox(&mut self.json, "Send as JSON")
+            .on_hover_text("Encode records as JSON objects instead of binary");
+        ui.checkbox(&mut self.big_endian, "Big-endian ids")
+            .on_hover_text("Receiver must use the same byte order");
+        ui.checkbox(&mut self.omit_id, "Omit ids").on_hover_text(
+            "Send bare data for plain-text consumers, receiver must use Plain encoding",
+        );
+        ui.checkbox(&mut self.timestamps, "Timestamps")
+            .on_hover_text(
+                "Stamp binary records with send time rather than DB ts column, \
+                 receiver must enable it too",
+            );
+        ui.add(egui::Slider::usize(&mut self.max_payload, 64..=65_507).text("max payload"))
+            .on_hover_text("Largest datagram to send, in bytes");
+        ui.checkbox(&mut self.fragment, "Fragment large records")
+            .on_hover_text("Receiver must have fragmentation enabled too");
+        ui.checkbox(&mut self.checksum, "Checksums")
+            .on_hover_text("Receiver must have checksums enabled too");
+        ui.checkbox(&mut self.sequencing, "Sequence numbers")
+            .on_hover_text("Receiver must have sequencing enabled too");
+        ui.checkbox(&mut self.batching, "Batch small records")
+            .on_hover_text("Receiver must have batching enabled too");
+        ui.checkbox(&mut self.dry_run, "Dry run")
+            .on_hover_text("Log payloads as hex instead of sending them");
+        ui.checkbox(&mut self.best_effort, "Skip failed records")
+            .on_hover_text("Keep sending after a socket error instead of stopping");
+        ui.checkbox(&mut self.loop_send, "Loop")
+            .on_hover_text("Start over from the first record after the last one, until stopped");
+        ui.checkbox(&mut self.probe, "Check destination first")
+            .on_hover_text("Send an empty packet to see if destination port is closed");
+        ui.checkbox(&mut self.timing, "Measure send timing")
+            .on_hover_text("Log average and slowest socket writes, to spot stalls");
+        ui.add(egui::Slider::u32(&mut self.rate, 0..=10_000).text("packets/s"))
+            .on_hover_text("Rate limit, 0 for unlimited");
+        ui.add(egui::Slider::u32(&mut self.delay_ms, 0..=10_000).text("delay (ms)"))
+            .on_hover_text("Pause between packets, overrides packets/s unless 0");
+        ui.add(egui::Slider::u32(&mut self.copies, 1..=10).text("copies"))
+            .on_hover_text("Send every packet this many times in a row, to test deduplication");
+        ui.checkbox(&mut self.broadcast, "Broadcast")
+            .on_hover_text("Allow sending to broadcast addresses");
+        ui.add(egui::Slider::u32(&mut self.ttl, 0..=255).text("TTL"))
+            .on_hover_text("Time-to-live of sent packets, 0 for OS default");
+        let can_send_raw = bind_error.is_none() && tx_error.is_none() && self.one_off.is_none();
+        ui.collapsing("Raw packet", |ui| {
+            ui.add(egui::TextEdit::multiline(&mut self.raw_hex).hint_text("01 00 00 00 ff"))
+                .on_hover_text("Bytes to send as is in a single datagram, as hex");
+            match parse_hex(&self.raw_hex) {
+                Ok(bytes) => {
+                    ui.wrap(|ui| {
+                        ui.set_enabled(can_send_raw);
+                        if ui.button(format!("Send {} bytes", bytes.len())).clicked() {
+                            self.send_raw(bytes);
+                        }
+                    });
+                }
+                Err(e) => {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+            }
         });
 
-        if let Some(ref task) = self.task {
-            if let Ok(message) = task.status.try_recv() {
-                match message {
-                    StatusMessage::Success => {
-                        self.status = Some(true);
-                        self.task = None;
+        bind_error.is_none() && tx_error.is_none()
+    }
+
+    /// Sends `bytes` as a single datagram to every destination,
+    /// bypassing framing and encoding settings. Binding and resolving
+    /// destinations may take a while, so it's done on a worker thread
+    /// reporting back through `one_off` channel.
+    fn send_raw(&mut self, bytes: Vec<u8>) {
+        let dests: Vec<String> = split_destinations(&self.tx_addr)
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let addr = self.bind_addr.trim().to_owned();
+        let max_payload = self.max_payload;
+        let (status_sender, status_receiver) = mpsc::channel();
+        self.one_off = Some(status_receiver);
+        std::thread::spawn(move || {
+            let sent = format!("Sent {} bytes to {}", bytes.len(), dests.join(", "));
+            let sender = if addr.is_empty() {
+                Sender::new_ephemeral()
+            } else {
+                Sender::new(addr)
+            };
+            let res = sender.and_then(|mut sender| {
+                sender.set_max_payload(max_payload)?;
+                sender.send_to_all(std::iter::once(bytes), &dests)
+            });
+            let status = match res {
+                Ok(report) if report.truncated > 0 => StatusMessage::Warning(format!(
+                    "Packet was larger than max payload of {} bytes and got truncated",
+                    max_payload
+                )),
+                Ok(_) => StatusMessage::Info(sent),
+                Err(e) => StatusMessage::Warning(describe_send_error(&e)),
+            };
+            // UI may be gone by now
+            status_sender.send(status).ok();
+        });
+    }
+
+    /// Runs [self_test] on a worker thread, whose outcome
+    /// is picked up from `one_off` channel once it's done.
+    fn start_self_test(&mut self) {
+        let (status_sender, status_receiver) = mpsc::channel();
+        self.one_off = Some(status_receiver);
+        std::thread::spawn(move || {
+            let status = match self_test() {
+                Ok(report) if report.passed() => StatusMessage::Info(format!(
+                    "Self test passed: {} records came back intact",
+                    report.received
+                )),
+                Ok(report) => StatusMessage::Warning(format!(
+                    "Self test failed: {} of {} records came back intact, {} corrupted",
+                    report.received, report.sent, report.corrupted
+                )),
+                Err(e) => StatusMessage::Warning(format!("Self test failed: {}", e)),
+            };
+            // UI may be gone by now
+            status_sender.send(status).ok();
+        });
+    }
+
+    /// Saves records made up with current settings to `db_file` on a
+    /// worker thread, reporting back through `one_off` channel.
+    fn save_generated(&mut self) {
+        let (count, len) = (self.generate_count, self.generate_len);
+        let path = self.db_file.clone();
+        let (status_sender, status_receiver) = mpsc::channel();
+        self.one_off = Some(status_receiver);
+        std::thread::spawn(move || {
+            let records = Record::generate(count, len);
+            let status = match Record::create_db(&path, &records) {
+                Ok(()) => StatusMessage::Info(format!(
+                    "Saved {} generated records to {}",
+                    records.len(),
+                    path
+                )),
+                Err(e) => StatusMessage::Warning(format!("Couldn't save generated records: {}", e)),
+            };
+            // UI may be gone by now
+            status_sender.send(status).ok();
+        });
+    }
+
+    /// Starts worker reporting what sending would load from [App::db_file].
+    fn preview(&mut self) {
+        self.counters = Counters::default();
+        let (control_sender, _) = std::sync::mpsc::channel();
+        let (status_sender, status_receiver) = std::sync::mpsc::channel();
+        let status_sender = StatusSender(status_sender);
+        self.task = Some(Task {
+            control: control_sender,
+            status: status_receiver,
+            started: Instant::now(),
+        });
+        let source = self.source;
+        let path = self.db_file.clone();
+        std::thread::spawn(move || preview_records(source, &path, &status_sender));
+    }
+
+    /// Extracts settings to persist.
+    fn settings(&self) -> Settings {
+        Settings {
+            hdpi: self.hdpi,
+            mode: self.mode,
+            bind_addr: self.bind_addr.clone(),
+            tx_addr: self.tx_addr.clone(),
+            source: self.source,
+            db_file: self.db_file.clone(),
+            log_file: self.log_file.clone(),
+            log_file_kb: self.log_file_kb,
+        }
+    }
+
+    /// Restores persisted settings.
+    fn apply_settings(&mut self, settings: Settings) {
+        self.hdpi = settings.hdpi;
+        self.mode = settings.mode;
+        self.bind_addr = settings.bind_addr;
+        self.tx_addr = settings.tx_addr;
+        self.source = settings.source;
+        self.db_file = settings.db_file;
+        self.log_file_input = settings.log_file.clone();
+        self.log_file = settings.log_file;
+        self.log_file_kb = settings.log_file_kb;
+    }
+
+    /// Appends a message to log, tagged with its level
+    /// and timestamped if enabled.
+    fn push_log(&mut self, level: Level, message: &str) {
+        let mut tagged = format!("{} {}", level.tag(), message);
+        if self.log_timestamps {
+            tagged = timestamp_line(now_millis(), &tagged);
+        }
+        append_log(&mut self.log, level, &tagged, self.max_log_lines);
+        self.write_log_file(&tagged);
+    }
+
+    /// Appends line to log file if one is set, opening it on first use
+    /// or when path changes. Stops writing to it if that fails.
+    fn write_log_file(&mut self, line: &str) {
+        let path = Path::new(self.log_file.trim());
+        if path.as_os_str().is_empty() {
+            self.log_writer = None;
+            return;
+        }
+        let max_size = u64::from(self.log_file_kb) * 1024;
+        let writer = match self.log_writer.take() {
+            Some(writer) if writer.path == path => Ok(writer),
+            _ => RollingLog::open(path, max_size),
+        };
+        let res = writer.and_then(|mut writer| {
+            writer.max_size = max_size;
+            writer.write_line(line).map(|_| writer)
+        });
+        match res {
+            Ok(writer) => self.log_writer = Some(writer),
+            Err(e) => {
+                // Not written to file, since that's what failed
+                let status = format!(
+                    "Couldn't write log to {}, stopped writing it: {}",
+                    path.display(),
+                    e
+                );
+                warn!("{}", status);
+                self.log_file.clear();
+                self.log_file_input.clear();
+                append_log(&mut self.log, Level::Warning, &status, self.max_log_lines);
+            }
+        }
+    }
+
+    /// Writes out lines buffered for log file.
+    fn flush_log_file(&mut self) {
+        if let Some(ref mut writer) = self.log_writer {
+            if let Err(e) = writer.flush() {
+                warn!(
+                    path:% = writer.path.display(), error:% = e;
+                    "Couldn't write log to {}: {}", writer.path.display(), e
+                );
+            }
+        }
+    }
+
+    /// Shows the end of log left by previous session, if any,
+    /// taking rotated file first, since it holds older lines.
+    fn reload_log_file(&mut self) {
+        let path = PathBuf::from(self.log_file.trim());
+        if path.as_os_str().is_empty() {
+            return;
+        }
+        let mut reloaded = false;
+        for path in [rotated_path(&path), path.clone()].iter() {
+            match std::fs::read(path) {
+                Ok(contents) => {
+                    for line in String::from_utf8_lossy(&contents).lines() {
+                        append_log(&mut self.log, level_of(line), line, self.max_log_lines);
                     }
-                    StatusMessage::Failure(status) => {
-                        let msg = format!("{}\n", status);
-                        self.log.push_str(&msg);
-                        error!("{}", status);
-                        self.status = Some(false);
-                        self.task = None;
+                    reloaded = true;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    let status = format!("Couldn't reload log from {}: {}", path.display(), e);
+                    self.push_log(Level::Warning, &status);
+                }
+            }
+        }
+        if reloaded {
+            let status = format!("Reloaded log of previous session from {}", path.display());
+            self.push_log(Level::Info, &status);
+        }
+    }
+
+    /// Updates app state according to message from worker thread.
+    fn handle_status(&mut self, message: StatusMessage) {
+        match message {
+            StatusMessage::Success => {
+                self.status = Some(true);
+                self.task = None;
+            }
+            StatusMessage::Failure(status) => {
+                self.push_log(Level::Failure, &status);
+                error!("{}", status);
+                self.status = Some(false);
+                self.task = None;
+            }
+            StatusMessage::Warning(status) => {
+                self.push_log(Level::Warning, &status);
+                warn!("{}", status);
+            }
+            StatusMessage::ListenerWarning(warning) => {
+                self.push_log(Level::Warning, &warning.message);
+                match warning.source {
+                    Some(source) => warn!(
+                        src:% = source, error:% = warning.error;
+                        "{}", warning.message
+                    ),
+                    None => warn!(error:% = warning.error; "{}", warning.message),
+                }
+            }
+            StatusMessage::Info(status) => {
+                self.push_log(Level::Info, &status);
+                info!("{}", status);
+            }
+            StatusMessage::Total(total) => {
+                self.counters.sent = 0;
+                self.counters.total = total;
+            }
+            StatusMessage::Progress(sent) => {
+                self.counters.sent = sent;
+            }
+            StatusMessage::Filtered => {
+                self.counters.filtered += 1;
+            }
+            StatusMessage::OtherSource(count) => {
+                self.counters.other_source += count;
+            }
+            StatusMessage::OsDropped(total) => {
+                self.counters.os_dropped = total;
+            }
+            StatusMessage::Traffic(counts) => {
+                self.counters.traffic = counts;
+            }
+            StatusMessage::Record(received) => {
+                info!(
+                    id = received.record.id,
+                    src:% = received.source,
+                    bytes = received.raw.len();
+                    "Got record [{} : {}] from {}",
+                    received.record.id, received.record.data, received.source
+                );
+                self.received.push(received);
+            }
+            StatusMessage::Received { corrupted } => {
+                if corrupted {
+                    self.counters.corrupted += 1;
+                } else {
+                    self.counters.received += 1;
+                }
+            }
+        }
+    }
+
+    /// Shows received records as a table, which
+    /// can be sorted by clicking column headers.
+    fn received_table(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("{} records", self.received.len()));
+            if ui.button("Clear records").clicked() {
+                self.received.clear();
+            }
+            let mut capacity = self.received.capacity();
+            ui.add(egui::Slider::usize(&mut capacity, 1..=100_000).text("kept"))
+                .on_hover_text("Oldest records are dropped past this");
+            self.received.set_capacity(capacity);
+            ui.checkbox(&mut self.data_as_hex, "Data as hex")
+                .on_hover_text("Show whole packets as they arrived, headers included");
+        });
+        egui::ScrollArea::from_max_height(300.0)
+            .id_source("received")
+            .show(ui, |ui| {
+                egui::Grid::new("received").striped(true).show(ui, |ui| {
+                    for &column in Column::ALL.iter() {
+                        let sorted = self.sort.column == column;
+                        let title = match (sorted, self.sort.ascending) {
+                            (true, true) => format!("{} ^", column.title()),
+                            (true, false) => format!("{} v", column.title()),
+                            (false, _) => column.title().to_owned(),
+                        };
+                        if ui.selectable_label(sorted, title).clicked() {
+                            self.sort = self.sort.clicked(column);
+                        }
+                    }
+                    ui.end_row();
+                    for received in sorted_records(self.received.iter(), self.sort) {
+                        let time = time_of_day(received.received_at);
+                        ui.add(egui::Label::new(time).monospace());
+                        ui.label(received.source.to_string());
+                        ui.label(received.record.id.to_string());
+                        ui.label(received.shown_data(self.data_as_hex));
+                        ui.label(received.latency.map_or(String::new(), |l| l.to_string()));
+                        ui.end_row();
                     }
-                    StatusMessage::Warning(status) => {
-                        let msg = format!("{}\n", status);
-                        self.log.push_str(&msg);
-                        warn!("{}", status);
+                });
+            });
+    }
+
+    /// Byte order of record ids selected by user.
+    fn byte_order(&self) -> ByteOrder {
+        if self.big_endian {
+            ByteOrder::Big
+        } else {
+            ByteOrder::Little
+        }
+    }
+
+    /// Shows what to do if port to bind to is taken.
+    fn bind_options(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.reuse_addr, "Share port")
+                .on_hover_text("Let other sockets sharing it bind to the same port");
+            ui.checkbox(&mut self.fallback_port, "Fall back to free port")
+                .on_hover_text("Bind to any free port if this one is taken");
+        });
+    }
+
+    /// Controls UI and worker for [Mode::Listen] mode.
+    fn listener(&mut self, ui: &mut egui::Ui) {
+        ui.label("Listen on address");
+
+        ui.wrap(|ui| {
+            ui.set_enabled(self.task.is_none());
+            ui.text_edit_singleline(&mut self.bind_addr);
+            if let Err(e) = validate_addr(&self.bind_addr) {
+                ui.colored_label(egui::Color32::RED, e);
+            }
+            self.bind_options(ui);
+            ui.add(egui::Slider::usize(&mut self.max_payload, 64..=65_507).text("max payload"))
+                .on_hover_text("Largest datagram to receive, in bytes");
+            ui.add(
+                egui::Slider::u32(&mut self.recv_buffer_kb, 0..=16_384)
+                    .text("receive buffer (KiB)"),
+            )
+            .on_hover_text(
+                "Room for packets waiting to be read, 0 for OS default. \
+                    OS drops packets once it's full",
+            );
+            ui.checkbox(&mut self.fragment, "Reassemble fragments")
+                .on_hover_text("Sender must have fragmentation enabled too");
+            ui.checkbox(&mut self.checksum, "Validate checksums")
+                .on_hover_text("Sender must have checksums enabled too");
+            ui.checkbox(&mut self.sequencing, "Detect packet loss")
+                .on_hover_text("Sender must have sequencing enabled too");
+            ui.checkbox(&mut self.batching, "Unpack batches")
+                .on_hover_text("Sender must have batching enabled too");
+            let codec = &mut self.codec;
+            egui::combo_box_with_label(ui, "Encoding", codec_label(*codec), |ui| {
+                for &option in [
+                    RecordCodec::Binary,
+                    RecordCodec::LengthPrefixed,
+                    RecordCodec::Plain,
+                    RecordCodec::Json,
+                ]
+                .iter()
+                {
+                    ui.selectable_value(codec, option, codec_label(option));
+                }
+            })
+            .on_hover_text("How to decode received records");
+            ui.checkbox(&mut self.reject_empty, "Reject empty records")
+                .on_hover_text(
+                    "Count records without data as corrupted, \
+                     since they look the same as ones cut off after id",
+                );
+            let corruption = &mut self.corruption;
+            egui::combo_box_with_label(ui, "Corrupted packets", policy_label(*corruption), |ui| {
+                for &option in [
+                    CorruptionPolicy::Warn,
+                    CorruptionPolicy::DumpRaw,
+                    CorruptionPolicy::Ignore,
+                    CorruptionPolicy::Stop,
+                ]
+                .iter()
+                {
+                    ui.selectable_value(corruption, option, policy_label(option));
+                }
+            })
+            .on_hover_text("What to do with packets that fail to decode or validate");
+            ui.checkbox(&mut self.big_endian, "Big-endian ids")
+                .on_hover_text("Sender must use the same byte order");
+            ui.checkbox(&mut self.timestamps, "Timestamps")
+                .on_hover_text("Show latency of binary records, sender must enable timestamps too");
+            ui.label("Save records to");
+            ui.text_edit_singleline(&mut self.out_db_file)
+                .on_hover_text("sqlite file to save records to, blank to not save them");
+            ui.checkbox(&mut self.overwrite, "Overwrite duplicate ids")
+                .on_hover_text("Otherwise record saved first is kept");
+            ui.label("Ids to show");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.filter_min_id).desired_width(80.0))
+                    .on_hover_text("Smallest id, blank for no limit");
+                ui.label("to");
+                ui.add(egui::TextEdit::singleline(&mut self.filter_max_id).desired_width(80.0))
+                    .on_hover_text("Largest id, blank for no limit");
+            });
+            ui.label("Only from");
+            ui.text_edit_singleline(&mut self.source_filter)
+                .on_hover_text("IP address to accept packets from, blank for any");
+            ui.label("Split packets on byte");
+            ui.add(egui::TextEdit::singleline(&mut self.delimiter).desired_width(40.0))
+                .on_hover_text(
+                    "Hex byte separating several records in a packet, blank for one record \
+                     per packet. Backslash escapes it inside records",
+                );
+            ui.checkbox(&mut self.count_only, "Count only")
+                .on_hover_text(
+                    "Count packets and bytes without decoding records, \
+                 to measure throughput and loss",
+                );
+            ui.checkbox(&mut self.echo, "Echo").on_hover_text(
+                "Send every packet back to its sender as is, \
+                     so that it can measure round-trip time",
+            );
+            ui.checkbox(&mut self.read_while_paused, "Keep reading while paused")
+                .on_hover_text(
+                    "Packets arriving while paused are counted but not shown. Otherwise \
+                     they wait in OS buffer, which drops them once it fills up",
+                );
+        });
+        let id_filter = parse_id_range(&self.filter_min_id, &self.filter_max_id);
+        if let Err(ref e) = id_filter {
+            ui.colored_label(egui::Color32::RED, format!("Invalid id range: {}", e));
+        }
+        let source_filter = parse_source_filter(&self.source_filter);
+        if let Err(ref e) = source_filter {
+            ui.colored_label(egui::Color32::RED, format!("Invalid source address: {}", e));
+        }
+        let delimiter = parse_delimiter(&self.delimiter);
+        if let Err(ref e) = delimiter {
+            ui.colored_label(egui::Color32::RED, format!("Invalid delimiter: {}", e));
+        }
+
+        if (self.task.is_some() || self.status.is_some()) && self.count_only {
+            let traffic = self.counters.traffic;
+            ui.label(format!(
+                "Received {} packets, {} bytes, {} lost, {} out of order, {} corrupted",
+                traffic.items, traffic.bytes, traffic.lost, traffic.out_of_order, traffic.corrupted
+            ));
+        } else if self.task.is_some() || self.status.is_some() {
+            let mut text = format!(
+                "Received {} records, {} filtered out, {} corrupted",
+                self.counters.received, self.counters.filtered, self.counters.corrupted
+            );
+            if self.counters.other_source > 0 {
+                text += &format!(", {} from other addresses", self.counters.other_source);
+            }
+            if self.counters.os_dropped > 0 {
+                text += &format!(", {} dropped by OS", self.counters.os_dropped);
+            }
+            ui.label(text);
+        }
+        let pause_label = if self.paused { "Resume" } else { "Pause" };
+        let shortcut = self.shortcut;
+        if let Some(ref mut task) = self.task {
+            let mut pause_clicked = false;
+            ui.horizontal(|ui| {
+                let clicked = ui.button("Stop").on_hover_text("Shortcut: Space").clicked();
+                if clicked || shortcut == Some(Shortcut::Stop) {
+                    // Worker might have exited on its own already
+                    task.control.send(ControlMessage::Stop).ok();
+                }
+                pause_clicked = ui.button(pause_label).clicked();
+            });
+            if pause_clicked {
+                let message = if self.paused {
+                    ControlMessage::Resume
+                } else {
+                    ControlMessage::Pause
+                };
+                if task.control.send(message).is_ok() {
+                    self.paused = !self.paused;
+                    let line = if !self.paused {
+                        "Resumed"
+                    } else if self.read_while_paused {
+                        "Paused, packets are counted but not shown"
+                    } else {
+                        "Paused, packets wait in OS buffer"
+                    };
+                    self.push_log(Level::Info, line);
+                }
+            }
+        } else {
+            let clicked = ui
+                .button("Listen")
+                .on_hover_text("Shortcut: Space")
+                .clicked();
+            if (clicked || shortcut == Some(Shortcut::Run))
+                && validate_addr(&self.bind_addr).is_ok()
+            {
+                let (id_filter, source_filter, delimiter) =
+                    match (id_filter, source_filter, delimiter) {
+                        (Ok(id_filter), Ok(source_filter), Ok(delimiter)) => {
+                            (id_filter, source_filter, delimiter)
+                        }
+                        // Error is already shown above
+                        _ => return,
+                    };
+                self.counters = Counters::default();
+                self.paused = false;
+                let (control_sender, control_receiver) = std::sync::mpsc::channel();
+                let (status_sender, status_receiver) = std::sync::mpsc::channel();
+                let status_sender = StatusSender(status_sender);
+
+                self.task = Some(Task {
+                    control: control_sender,
+                    status: status_receiver,
+                    started: Instant::now(),
+                });
+
+                let addr = self.bind_addr.clone();
+                let max_payload = self.max_payload;
+                let recv_buffer_kb = self.recv_buffer_kb;
+                let fragment = self.fragment;
+                let checksum = self.checksum;
+                let sequencing = self.sequencing;
+                let batching = self.batching;
+                let byte_order = self.byte_order();
+                let codec = self.codec;
+                let reject_empty = self.reject_empty;
+                let timestamps = self.timestamps;
+                let out_path = self.out_db_file.clone();
+                let read_while_paused = self.read_while_paused;
+                let corruption = self.corruption;
+                let count_only = self.count_only;
+                let echo = self.echo;
+                let reuse_addr = self.reuse_addr;
+                let fallback_port = self.fallback_port;
+                let on_conflict = if self.overwrite {
+                    OnConflict::Replace
+                } else {
+                    OnConflict::Ignore
+                };
+
+                std::thread::spawn(move || -> Result<(), ()> {
+                    let out_db = if count_only || out_path.trim().is_empty() {
+                        None
+                    } else {
+                        let conn = status_sender.check(
+                            Connection::open(out_path.trim())
+                                .and_then(|conn| Record::create_table(&conn).map(|_| conn)),
+                            "Couldn't open output DB",
+                        )?;
+                        Some(conn)
+                    };
+
+                    // Receive raw bytes to decode records with selected byte order
+                    let mut builder = ReceiverBuilder::new()
+                        .timeout(Some(POLL_INTERVAL))
+                        .buffer_size(max_payload)
+                        .reuse_addr(reuse_addr)
+                        .source_filter(source_filter)
+                        .delimiter(delimiter);
+                    if recv_buffer_kb > 0 {
+                        builder = builder.recv_buffer_size(recv_buffer_kb as usize * 1024);
+                    }
+                    let bind = |addr: &str| builder.bind(addr);
+                    let mut udp_receiver: Receiver<Vec<u8>> =
+                        bind_socket(addr.trim(), fallback_port, bind, &status_sender)?;
+                    udp_receiver.set_fragmentation(fragment);
+                    udp_receiver.set_checksum(checksum);
+                    udp_receiver.set_sequencing(sequencing);
+                    udp_receiver.set_batching(batching);
+                    udp_receiver.set_echo(echo);
+                    if let Err(e) = udp_receiver.set_drop_tracking(true) {
+                        status_sender.send(StatusMessage::Info(format!(
+                            "Packets dropped by OS won't be counted: {}",
+                            e
+                        )))?;
+                    }
+                    status_sender
+                        .send(StatusMessage::Info(listening_line(&udp_receiver, &addr)))?;
+                    if count_only {
+                        return count_traffic(
+                            udp_receiver.with_items(),
+                            &control_receiver,
+                            read_while_paused,
+                            &status_sender,
+                        );
+                    }
+
+                    // Log and table are left alone while paused, counters keep going
+                    let paused = Cell::new(false);
+                    let show = |message: StatusMessage| -> Result<(), ()> {
+                        if paused.get() {
+                            Ok(())
+                        } else {
+                            status_sender.send(message)
+                        }
+                    };
+                    // Counts a corrupted packet and acts on it as configured
+                    let corrupted = |description: String,
+                                     source: Option<SocketAddr>,
+                                     error: String,
+                                     raw: Option<&[u8]>|
+                     -> Result<(), ()> {
+                        status_sender.send(StatusMessage::Received { corrupted: true })?;
+                        match corruption_action(corruption, description, raw) {
+                            CorruptionAction::Ignore => Ok(()),
+                            CorruptionAction::Warn(message) => {
+                                show(StatusMessage::ListenerWarning(ListenerWarning {
+                                    message,
+                                    source,
+                                    error,
+                                }))
+                            }
+                            CorruptionAction::Stop(msg) => {
+                                status_sender.fail(msg);
+                                Err(())
+                            }
+                        }
+                    };
+
+                    // Handles a single packet, returning false if none arrived in time
+                    let mut filtered = 0;
+                    let mut os_dropped = 0;
+                    let mut next_id: u32 = 0;
+                    let mut poll = |udp_receiver: &mut Receiver<Vec<u8>>| -> Result<bool, ()> {
+                        if paused.get() && !read_while_paused {
+                            std::thread::sleep(POLL_INTERVAL);
+                            return Ok(false);
+                        }
+                        let res = udp_receiver.try_next_with_source();
+                        if udp_receiver.filtered() > filtered {
+                            let skipped = udp_receiver.filtered() - filtered;
+                            filtered = udp_receiver.filtered();
+                            status_sender.send(StatusMessage::OtherSource(skipped as u32))?;
+                        }
+                        if udp_receiver.os_dropped() != os_dropped {
+                            os_dropped = udp_receiver.os_dropped();
+                            status_sender.send(StatusMessage::OsDropped(os_dropped))?;
+                        }
+                        let res = match res {
+                            Poll::Ready(res) => res,
+                            Poll::Pending => return Ok(false),
+                        };
+                        let sequence = udp_receiver.last_sequence();
+                        if sequence.lost_since_last > 0 {
+                            let msg = format!("Lost {} packets", sequence.lost_since_last);
+                            show(StatusMessage::Warning(msg))?;
+                        }
+                        if sequence.out_of_order {
+                            show(StatusMessage::Warning("Packet arrived out of order".into()))?;
+                        }
+                        match res {
+                            Ok((bytes, source)) => {
+                                let mut decoded = decode_received(
+                                    &bytes,
+                                    codec,
+                                    byte_order,
+                                    timestamps,
+                                    reject_empty,
+                                );
+                                // Plain records carry no id, so number them as they arrive
+                                if let (RecordCodec::Plain, Ok((record, _))) = (codec, &mut decoded)
+                                {
+                                    record.id = next_id;
+                                    next_id = next_id.wrapping_add(1);
+                                }
+                                if let (Some(conn), Ok((record, _))) = (&out_db, &decoded) {
+                                    if let Err(e) = record.insert(conn, on_conflict) {
+                                        show(StatusMessage::ListenerWarning(ListenerWarning {
+                                            message: format!("Couldn't save record: {}", e),
+                                            source: Some(source),
+                                            error: e.to_string(),
+                                        }))?;
+                                    }
+                                }
+                                match decoded {
+                                    Ok((record, _)) if !passes_filter(record.id, &id_filter) => {
+                                        status_sender.send(StatusMessage::Filtered)?;
+                                    }
+                                    Ok((record, timestamp)) => {
+                                        let received_at = now_millis();
+                                        show(StatusMessage::Record(ReceivedRecord {
+                                            received_at,
+                                            source,
+                                            record,
+                                            raw: bytes,
+                                            latency: timestamp.map(|timestamp| {
+                                                received_at as i64 - timestamp as i64
+                                            }),
+                                        }))?;
+                                        status_sender
+                                            .send(StatusMessage::Received { corrupted: false })?;
+                                    }
+                                    Err(e) => corrupted(
+                                        match e.position() {
+                                            Some(position) => format!(
+                                                "Got packet from {} corrupt at byte {}: {}",
+                                                source, position, e
+                                            ),
+                                            None => format!(
+                                                "Got corrupted packet from {}: {}",
+                                                source, e
+                                            ),
+                                        },
+                                        Some(source),
+                                        e.to_string(),
+                                        Some(&bytes),
+                                    )?,
+                                }
+                            }
+                            Err(crate::udp::Error::ParseError { source, .. }) => match source {},
+                            Err(e @ crate::udp::Error::ChecksumMismatch) => corrupted(
+                                "Got corrupted packet: checksum mismatch".into(),
+                                None,
+                                e.to_string(),
+                                None,
+                            )?,
+                            Err(e @ crate::udp::Error::MissingSequence) => corrupted(
+                                "Got packet without sequence number".into(),
+                                None,
+                                e.to_string(),
+                                None,
+                            )?,
+                            Err(e @ crate::udp::Error::BadFragment) => corrupted(
+                                "Got packet without fragment header".into(),
+                                None,
+                                e.to_string(),
+                                None,
+                            )?,
+                            Err(e @ crate::udp::Error::BadBatch) => {
+                                corrupted("Got malformed batch".into(), None, e.to_string(), None)?
+                            }
+                            Err(crate::udp::Error::Io(e)) => {
+                                show(StatusMessage::ListenerWarning(ListenerWarning {
+                                    message: format!(
+                                        "Error while reading from socket: {}",
+                                        e.to_string(),
+                                    ),
+                                    source: None,
+                                    error: e.to_string(),
+                                }))?;
+                            }
+                        }
+                        Ok(true)
+                    };
+
+                    poll_until_stopped(&control_receiver, &paused, || {
+                        poll(&mut udp_receiver).map(|_| ())
+                    })?;
+
+                    // Don't lose packets that arrived before Stop,
+                    // but give up if they keep coming
+                    if udp_receiver
+                        .set_timeout(Some(Duration::from_millis(1)))
+                        .is_ok()
+                    {
+                        let deadline = Instant::now() + POLL_INTERVAL;
+                        while Instant::now() < deadline && poll(&mut udp_receiver)? {}
+                    }
+                    status_sender.send(StatusMessage::Info(format!(
+                        "Stopped, received digest {}",
+                        udp_receiver.digest()
+                    )))?;
+
+                    status_sender.send(StatusMessage::Success)?;
+
+                    Ok(())
+                });
+            }
+        }
+    }
+}
+
+/// Draws a bar across available width, filled to `fraction` of it.
+fn progress_bar(ui: &mut egui::Ui, fraction: f32) {
+    let size = egui::vec2(ui.available_width(), ui.style().spacing.interact_size.y);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let mut filled = rect;
+    filled.max.x = rect.min.x + rect.width() * fraction.clamp(0.0, 1.0);
+    let visuals = &ui.style().visuals;
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, visuals.extreme_bg_color);
+    painter.rect_filled(filled, 2.0, visuals.selection.bg_fill);
+}
+
+/// Formats bytes as a single line of space-separated hex octets.
+fn hex_line(bytes: &[u8]) -> String {
+    let octets: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    octets.join(" ")
+}
+
+/// Formats bytes as lines of 16 hex octets followed by their ASCII
+/// representation, with non-printable characters replaced by dots.
+fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        if i > 0 {
+            dump.push('\n');
+        }
+        dump += &format!("{:08x} ", i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                dump.push(' ');
+            }
+            match line.get(j) {
+                Some(byte) => dump += &format!(" {:02x}", byte),
+                None => dump += "   ",
+            }
+        }
+        dump += "  |";
+        dump.extend(line.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push('|');
+    }
+    dump
+}
+
+/// Color of warnings in log, readable on both light and dark background.
+const WARNING_COLOR: egui::Color32 = egui::Color32::from_rgb(0xd0, 0x80, 0x00);
+
+/// How long listener worker may block on socket
+/// before checking for [ControlMessage::Stop].
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for destination to refuse a probe before sending.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+/// Number of received records kept for the table by default.
+const DEFAULT_HISTORY: usize = 5000;
+/// How often count-only listener reports its totals.
+const TRAFFIC_INTERVAL: Duration = Duration::from_millis(250);
+/// Number of records logged by [preview_records].
+const PREVIEW_RECORDS: usize = 5;
+/// Characters of record data logged by [preview_records].
+const PREVIEW_DATA_LEN: usize = 60;
+
+/// Calls `poll` until [ControlMessage::Stop] arrives, UI thread hangs up,
+/// or `poll` fails. Control channel is checked before each call, so `poll`
+/// should return within [POLL_INTERVAL] for Stop to be honored promptly.
+/// [ControlMessage::Pause] and [ControlMessage::Resume] set `paused`,
+/// leaving it up to `poll` what to do differently.
+fn poll_until_stopped(
+    control: &mpsc::Receiver<ControlMessage>,
+    paused: &Cell<bool>,
+    mut poll: impl FnMut() -> Result<(), ()>,
+) -> Result<(), ()> {
+    loop {
+        match control.try_recv() {
+            Ok(ControlMessage::Stop) | Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            Ok(ControlMessage::Pause) => paused.set(true),
+            Ok(ControlMessage::Resume) => paused.set(false),
+            Err(mpsc::TryRecvError::Empty) => poll()?,
+        }
+    }
+}
+
+/// Tells where `receiver` listens, with port picked by OS if `requested`
+/// address had port 0. Falls back to `requested` if socket can't tell.
+fn listening_line<T>(receiver: &Receiver<T>, requested: &str) -> String {
+    let local = receiver
+        .local_addr()
+        .map(|local| local.to_string())
+        .unwrap_or_else(|_| requested.to_owned());
+    format!("Listening on {}...", local)
+}
+
+/// Listener loop of count-only mode, which reports running totals every
+/// [TRAFFIC_INTERVAL], and once more when stopped.
+fn count_traffic(
+    mut receiver: Receiver<PayloadLen>,
+    control: &mpsc::Receiver<ControlMessage>,
+    read_while_paused: bool,
+    status_sender: &StatusSender,
+) -> Result<(), ()> {
+    let mut counts = TrafficCounts::default();
+    let mut reported = Instant::now();
+    let paused = Cell::new(false);
+    poll_until_stopped(control, &paused, || {
+        if paused.get() && !read_while_paused {
+            std::thread::sleep(POLL_INTERVAL);
+        } else if let Poll::Ready(Err(e)) = receiver.try_count(&mut counts) {
+            status_sender.send(StatusMessage::ListenerWarning(ListenerWarning {
+                message: format!("Error while reading from socket: {}", e),
+                source: None,
+                error: e.to_string(),
+            }))?;
+        }
+        if reported.elapsed() >= TRAFFIC_INTERVAL {
+            reported = Instant::now();
+            status_sender.send(StatusMessage::Traffic(counts))?;
+        }
+        Ok(())
+    })?;
+    status_sender.send(StatusMessage::Traffic(counts))?;
+    status_sender.send(StatusMessage::Info("Stopped".into()))?;
+    status_sender.send(StatusMessage::Success)
+}
+
+/// Yields items of `iter` until [ControlMessage::Stop] arrives,
+/// setting `stopped`, or UI thread hangs up. Control channel
+/// is checked before each item.
+fn until_stopped<'a, I>(
+    iter: I,
+    control: &'a mpsc::Receiver<ControlMessage>,
+    stopped: &'a Cell<bool>,
+) -> impl Iterator<Item = I::Item> + 'a
+where
+    I: Iterator + 'a,
+{
+    iter.take_while(move |_| match control.try_recv() {
+        Ok(ControlMessage::Stop) => {
+            stopped.set(true);
+            false
+        }
+        Err(mpsc::TryRecvError::Disconnected) => false,
+        // Sending can't be paused
+        Ok(ControlMessage::Pause) | Ok(ControlMessage::Resume) | Err(mpsc::TryRecvError::Empty) => {
+            true
+        }
+    })
+}
+
+/// Yields items of iterators made by `make` one after another, until it
+/// returns `None`, counting in `passes` every iterator that ran out.
+/// Gives up after a pass yielding nothing, so an empty source can't spin.
+fn repeat_passes<'a, I, F>(mut make: F, passes: &'a Cell<u32>) -> impl Iterator<Item = I::Item> + 'a
+where
+    I: Iterator + 'a,
+    F: FnMut() -> Option<I> + 'a,
+{
+    let mut current: Option<I> = None;
+    let mut yielded = true;
+    std::iter::from_fn(move || loop {
+        if let Some(item) = current.as_mut().and_then(Iterator::next) {
+            yielded = true;
+            return Some(item);
+        }
+        if current.take().is_some() {
+            passes.set(passes.get() + 1);
+        }
+        if !yielded {
+            return None;
+        }
+        yielded = false;
+        current = Some(make()?);
+    })
+}
+
+/// Checks that address entered by user looks like `host:port`,
+/// returning description of the problem otherwise. Host names
+/// are only resolved once worker starts.
+fn validate_addr(addr: &str) -> Result<(), &'static str> {
+    let addr = addr.trim();
+    if addr.is_empty() {
+        return Err("Address is empty");
+    }
+    if addr.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+    let colon = addr.rfind(':').ok_or("Missing port")?;
+    let (host, port) = (&addr[..colon], &addr[colon + 1..]);
+    if port.parse::<u16>().is_err() {
+        return Err("Invalid port");
+    }
+    if host.is_empty() {
+        return Err("Missing host");
+    }
+    // Anything with colons or brackets that didn't parse
+    // above is a malformed IPv6 address, not a host name
+    if host.contains(&[':', '[', ']'][..]) {
+        return Err("Invalid address");
+    }
+    Ok(())
+}
+
+/// Same as [validate_addr], but also checks that address
+/// could be one of this host's, which we can send from.
+fn validate_local_addr(addr: &str) -> Result<(), &'static str> {
+    validate_addr(addr)?;
+    match addr.trim().parse::<SocketAddr>().map(|addr| addr.ip()) {
+        Ok(ip) if ip.is_multicast() => Err("Can't send from a multicast address"),
+        Ok(IpAddr::V4(ip)) if ip.is_broadcast() => Err("Can't send from a broadcast address"),
+        _ => Ok(()),
+    }
+}
+
+/// Parses address to only accept packets from, blank meaning any.
+fn parse_source_filter(addr: &str) -> Result<Option<IpAddr>, AddrParseError> {
+    let addr = addr.trim();
+    if addr.is_empty() {
+        Ok(None)
+    } else {
+        addr.parse().map(Some)
+    }
+}
+
+/// Maps keys pressed during a frame to an action: Enter runs sender,
+/// Space starts or stops listener. Nothing fires while a widget has
+/// keyboard focus, or Enter while sender is already running.
+fn shortcut(
+    mode: Mode,
+    running: bool,
+    text_focused: bool,
+    enter: bool,
+    space: bool,
+) -> Option<Shortcut> {
+    match mode {
+        _ if text_focused => None,
+        Mode::Send if enter && !running => Some(Shortcut::Run),
+        Mode::Listen if space && running => Some(Shortcut::Stop),
+        Mode::Listen if space => Some(Shortcut::Run),
+        _ => None,
+    }
+}
+
+/// Parses hex byte separating records in a packet, blank meaning none.
+fn parse_delimiter(text: &str) -> Result<Option<u8>, String> {
+    match parse_hex(text)?[..] {
+        [] => Ok(None),
+        [DELIMITER_ESCAPE] => Err("Backslash escapes delimiters, pick another byte".to_owned()),
+        [byte] => Ok(Some(byte)),
+        _ => Err("Delimiter must be a single byte".to_owned()),
+    }
+}
+
+/// Parses bytes written as pairs of hex digits,
+/// which may be separated by whitespace.
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(c) = digits.iter().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' isn't a hex digit", c));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Ok((high.to_digit(16).unwrap() * 16 + low.to_digit(16).unwrap()) as u8),
+            _ => Err("Odd number of hex digits, every byte takes two".to_owned()),
+        })
+        .collect()
+}
+
+/// Splits comma-separated destination addresses, skipping blank ones.
+fn split_destinations(addrs: &str) -> Vec<&str> {
+    addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .collect()
+}
+
+/// Sends `iter` over connected socket if there's a single destination,
+/// so that closed port is noticed, or to every one of several otherwise.
+fn send_to_dests<I>(sender: &mut Sender, iter: I, dests: &[String]) -> std::io::Result<SendReport>
+where
+    I: IntoIterator,
+    I::Item: ToUdp,
+{
+    match dests {
+        [dest] => sender.send(iter, dest.as_str()),
+        dests => sender.send_to_all(iter, dests),
+    }
+}
+
+/// Loads records from file at `path` like sender would, and reports how
+/// many there are along with the first [PREVIEW_RECORDS] of them.
+fn preview_records(source: Source, path: &str, status_sender: &StatusSender) -> Result<(), ()> {
+    if !Path::new(path).is_file() {
+        status_sender.fail(format!("No such file: {}", path));
+        return Err(());
+    }
+    let (count, sample) = match source {
+        Source::Sqlite => {
+            let conn = status_sender.check(Connection::open(path), "Couldn't open file")?;
+            let count = status_sender.check(
+                Record::count_range(&conn, 0..=u32::MAX),
+                "Couldn't load records from DB",
+            )?;
+            let sample = status_sender.check(
+                Record::load_limited(conn, PREVIEW_RECORDS),
+                "Couldn't load records from DB",
+            )?;
+            (count, sample)
+        }
+        Source::Csv => {
+            let mut records = std::fs::File::open(path)
+                .map_err(CsvError::from)
+                .and_then(Record::load_csv)
+                .map_err(|e| {
+                    status_sender.fail(format!("Couldn't load records from CSV: {}", e))
+                })?;
+            let count = records.len() as u32;
+            records.truncate(PREVIEW_RECORDS);
+            (count, records)
+        }
+        Source::Generated | Source::Stdin => {
+            status_sender.fail("Only records read from a file can be previewed".into());
+            return Err(());
+        }
+    };
+    status_sender.send(StatusMessage::Info(format!(
+        "Loaded {} records from {}",
+        count, path
+    )))?;
+    for record in sample {
+        let mut data: String = record.data.chars().take(PREVIEW_DATA_LEN).collect();
+        if data.len() < record.data.len() {
+            data += "...";
+        }
+        status_sender.send(StatusMessage::Info(format!("[{} : {}]", record.id, data)))?;
+    }
+    status_sender.send(StatusMessage::Total(count))?;
+    status_sender.send(StatusMessage::Success)
+}
+
+/// Whether received record should be logged, given id range
+/// user is interested in.
+fn passes_filter(id: u32, filter: &RangeInclusive<u32>) -> bool {
+    filter.contains(&id)
+}
+
+/// Parses bounds of id range entered by user,
+/// treating blank ones as unbounded.
+fn parse_id_range(min: &str, max: &str) -> Result<RangeInclusive<u32>, ParseIntError> {
+    let parse = |bound: &str, default| match bound.trim() {
+        "" => Ok(default),
+        bound => bound.parse(),
+    };
+    Ok(parse(min, u32::MIN)?..=parse(max, u32::MAX)?)
+}
+
+/// Parses limit entered by user, treating blank one as no limit.
+fn parse_limit(limit: &str) -> Result<Option<usize>, ParseIntError> {
+    match limit.trim() {
+        "" => Ok(None),
+        limit => limit.parse().map(Some),
+    }
+}
+
+/// Appends every line of `message` to log, dropping oldest lines
+/// so that at most `max_lines` remain, unless it's 0.
+fn append_log(log: &mut VecDeque<LogLine>, level: Level, message: &str, max_lines: usize) {
+    log.extend(message.lines().map(|text| LogLine {
+        level,
+        text: text.to_owned(),
+    }));
+    if max_lines == 0 {
+        return;
+    }
+    let excess = log.len().saturating_sub(max_lines);
+    log.drain(..excess);
+}
+
+/// Names codec in listen mode settings.
+fn codec_label(codec: RecordCodec) -> &'static str {
+    match codec {
+        RecordCodec::Binary => "Binary",
+        RecordCodec::LengthPrefixed => "Length-prefixed",
+        RecordCodec::Plain => "Plain (no ids)",
+        RecordCodec::Json => "JSON",
+    }
+}
+
+/// Names corruption policy in listen mode settings.
+fn policy_label(policy: CorruptionPolicy) -> &'static str {
+    match policy {
+        CorruptionPolicy::Warn => "Warn without hexdump",
+        CorruptionPolicy::Stop => "Stop listening",
+        CorruptionPolicy::Ignore => "Ignore",
+        CorruptionPolicy::DumpRaw => "Warn with hexdump",
+    }
+}
+
+/// Decodes record received by listener, along with its send time
+/// if records carry one. With `reject_empty`, records without data
+/// are rejected whatever their codec, as they may have been cut off.
+fn decode_received(
+    bytes: &[u8],
+    codec: RecordCodec,
+    byte_order: ByteOrder,
+    timestamps: bool,
+    reject_empty: bool,
+) -> Result<(Record, Option<u64>), ParseError> {
+    let (record, timestamp) = match codec.format(byte_order) {
+        Some(format) if timestamps => TimedRecord::from_udp_with(bytes, format)
+            .map(|timed| (timed.record, Some(timed.timestamp)))?,
+        _ => (Record::from_udp_with_codec(bytes, codec, byte_order)?, None),
+    };
+    if reject_empty && record.data.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    Ok((record, timestamp))
+}
+
+/// Decides what to do about corrupted packet described by
+/// `description`, whose bytes are `raw` if they're at hand.
+fn corruption_action(
+    policy: CorruptionPolicy,
+    description: String,
+    raw: Option<&[u8]>,
+) -> CorruptionAction {
+    match (policy, raw) {
+        (CorruptionPolicy::Warn, _) | (CorruptionPolicy::DumpRaw, None) => {
+            CorruptionAction::Warn(description)
+        }
+        (CorruptionPolicy::DumpRaw, Some(raw)) => {
+            CorruptionAction::Warn(format!("{}\n{}", description, hexdump(raw)))
+        }
+        (CorruptionPolicy::Ignore, _) => CorruptionAction::Ignore,
+        (CorruptionPolicy::Stop, _) => CorruptionAction::Stop(description),
+    }
+}
+
+/// Orders records for display, keeping arrival order among equal ones.
+fn sorted_records<'a>(
+    records: impl IntoIterator<Item = &'a ReceivedRecord>,
+    order: SortOrder,
+) -> Vec<&'a ReceivedRecord> {
+    let mut sorted: Vec<_> = records.into_iter().collect();
+    let compare = |a: &ReceivedRecord, b: &ReceivedRecord| match order.column {
+        Column::Time => a.received_at.cmp(&b.received_at),
+        Column::Source => a.source.cmp(&b.source),
+        Column::Id => a.record.id.cmp(&b.record.id),
+        Column::Data => a.record.data.cmp(&b.record.data),
+        Column::Latency => a.latency.cmp(&b.latency),
+    };
+    // Stable sort with swapped arguments, since reversing
+    // its result would reverse equal records too
+    if order.ascending {
+        sorted.sort_by(|a, b| compare(a, b));
+    } else {
+        sorted.sort_by(|a, b| compare(b, a));
+    }
+    sorted
+}
+
+/// Joins log lines into text, regardless of their level.
+fn log_text<'a>(log: impl IntoIterator<Item = &'a LogLine>) -> String {
+    log.into_iter()
+        .map(|line| format!("{}\n", line.text))
+        .collect()
+}
+
+/// Binds socket to `addr` with `bind`. If port is taken and `fallback`
+/// is set, binds to a free port on the same interface instead.
+fn bind_socket<S>(
+    addr: &str,
+    fallback: bool,
+    bind: impl Fn(&str) -> std::io::Result<S>,
+    status_sender: &StatusSender,
+) -> Result<S, ()> {
+    let e = match bind(addr) {
+        Ok(sock) => return Ok(sock),
+        Err(e) => e,
+    };
+    let free = match addr
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+    {
+        Some(mut free) if fallback && e.kind() == std::io::ErrorKind::AddrInUse => {
+            free.set_port(0);
+            free.to_string()
+        }
+        _ => {
+            status_sender.fail(describe_bind_error(addr, &e));
+            return Err(());
+        }
+    };
+    status_sender.send(StatusMessage::Warning(format!(
+        "{} is already in use, falling back to a free port",
+        addr
+    )))?;
+    bind(&free).map_err(|e| status_sender.fail(describe_bind_error(&free, &e)))
+}
+
+/// Explains why binding to `addr` failed, suggesting
+/// a way out if port is taken.
+fn describe_bind_error(addr: &str, e: &std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::AddrInUse => format!(
+            "Address {} is already in use, maybe by another instance. Pick another \
+             port, or enable sharing it or falling back to a free one",
+            addr
+        ),
+        std::io::ErrorKind::AddrNotAvailable => format!(
+            "{} isn't assigned to any interface of this host, \
+             pick an address of the interface to use",
+            addr
+        ),
+        _ => format!("Couldn't bind to {}: {}", addr, e),
+    }
+}
+
+/// Explains why sending failed, spelling out errors
+/// whose OS description is unhelpful.
+fn describe_send_error(e: &std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::ConnectionRefused => {
+            "Destination refused connection (port closed?)".to_owned()
+        }
+        _ => format!("Error sending data: {}", e),
+    }
+}
+
+/// Summarizes socket write durations in microseconds.
+fn timing_line(timing: &SendTiming) -> String {
+    let slowest: Vec<_> = timing
+        .slowest
+        .iter()
+        .map(|slow| slow.as_micros().to_string())
+        .collect();
+    format!(
+        "{} writes took {} us on average, slowest: {} us",
+        timing.writes,
+        timing.average().as_micros(),
+        slowest.join(", ")
+    )
+}
+
+/// Lists up to 10 ids, noting how many more there are.
+fn id_list(ids: &[u32]) -> String {
+    const SHOWN: usize = 10;
+    let mut list = ids
+        .iter()
+        .take(SHOWN)
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if ids.len() > SHOWN {
+        list += &format!(" and {} more", ids.len() - SHOWN);
+    }
+    list
+}
+
+/// Prefixes `line` with UTC time of day of `millis` since UNIX epoch,
+/// as `HH:MM:SS.mmm`.
+fn timestamp_line(millis: u64, line: &str) -> String {
+    format!("{} {}", time_of_day(millis), line)
+}
+
+/// Animated line shown while worker runs, `elapsed` since it started.
+/// Relies on UI being redrawn continuously while it does.
+fn running_line(elapsed: Duration) -> String {
+    const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+    let frame = SPINNER[(elapsed.as_millis() / 250 % SPINNER.len() as u128) as usize];
+    format!("{} Running for {}", frame, format_elapsed(elapsed))
+}
+
+/// Formats duration as `m:ss`, or `h:mm:ss` past an hour.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+/// Formats UTC time of day of `millis` since UNIX epoch as `HH:MM:SS.mmm`.
+fn time_of_day(millis: u64) -> String {
+    let millis = millis % (24 * 60 * 60 * 1000);
+    let (secs, millis) = (millis / 1000, millis % 1000);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        millis
+    )
+}
+
+/// Recovers level of log line from its tag, which follows timestamp
+/// if there is one. Untagged lines, such as continuations of
+/// multi-line messages, are taken as info.
+fn level_of(line: &str) -> Level {
+    let levels = [Level::Info, Level::Warning, Level::Failure];
+    line.split_whitespace()
+        .take(2)
+        .find_map(|word| levels.iter().copied().find(|level| level.tag() == word))
+        .unwrap_or(Level::Info)
+}
+
+/// Writes log to file at `path`, replacing its contents.
+fn save_log(path: &Path, log: &str) -> std::io::Result<()> {
+    std::fs::write(path, log)
+}
+
+impl epi::App for App {
+    fn name(&self) -> &str {
+        "UDP Test app"
+    }
+
+    fn load(&mut self, storage: &dyn epi::Storage) {
+        if let Some(settings) = epi::get_value(storage, epi::APP_KEY) {
+            self.apply_settings(settings);
+            self.reload_log_file();
+        }
+    }
+
+    fn save(&mut self, storage: &mut dyn epi::Storage) {
+        epi::set_value(storage, epi::APP_KEY, &self.settings());
+    }
+
+    fn update(&mut self, ctx: &egui::CtxRef, _frame: &mut epi::Frame<'_>) {
+        if self.hdpi {
+            ctx.set_pixels_per_point(2.0);
+        } else {
+            ctx.set_pixels_per_point(1.0);
+        }
+
+        egui::TopPanel::top("wrap_app_top_bar").show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                let style: egui::Style = (*ui.ctx().style()).clone();
+                let new_visuals = style.visuals.light_dark_small_toggle_button(ui);
+                if let Some(visuals) = new_visuals {
+                    ui.ctx().set_visuals(visuals);
+                }
+                ui.checkbox(&mut self.hdpi, "HiDPI");
+
+                if ui.button("Save log").clicked() {
+                    let path = rfd::FileDialog::new()
+                        .add_filter("Text", &["txt", "log"])
+                        .save_file();
+                    if let Some(path) = path {
+                        if let Err(e) = save_log(&path, &log_text(&self.log)) {
+                            let status = format!("Couldn't save log to {}: {}", path.display(), e);
+                            self.push_log(Level::Warning, &status);
+                            warn!("{}", status);
+                        }
                     }
-                    StatusMessage::Info(status) => {
-                        let msg = format!("{}\n", status);
-                        self.log.push_str(&msg);
-                        info!("{}", status);
+                }
+
+                ui.wrap(|ui| {
+                    ui.set_enabled(self.one_off.is_none());
+                    if ui
+                        .button("Self test")
+                        .on_hover_text("Send a few records to ourselves over loopback")
+                        .clicked()
+                    {
+                        self.start_self_test();
                     }
+                });
+
+                ui.wrap(|ui| {
+                    ui.set_enabled(self.task.is_none());
+                    ui.selectable_value(&mut self.mode, Mode::Send, "Send");
+                    ui.selectable_value(&mut self.mode, Mode::Listen, "Listen");
+                });
+            });
+        });
+
+        if let Some(ref status) = self.one_off {
+            match status.try_recv() {
+                Ok(message) => {
+                    self.one_off = None;
+                    self.handle_status(message);
                 }
+                Err(mpsc::TryRecvError::Disconnected) => self.one_off = None,
+                Err(mpsc::TryRecvError::Empty) => ctx.request_repaint(),
             }
         }
+        if let Some(ref task) = self.task {
+            // Drain all pending messages, since progress updates can
+            // arrive much faster than frames are drawn
+            let messages: Vec<_> = task.status.try_iter().collect();
+            for message in messages {
+                self.handle_status(message);
+            }
+            // Keep redrawing to show progress even without user input
+            ctx.request_repaint();
+        }
+        // Once a frame, so that a crash loses little
+        self.flush_log_file();
+
+        // Keys typed into a text field are meant for it
+        let text_focused = ctx.wants_keyboard_input();
+        self.shortcut = shortcut(
+            self.mode,
+            self.task.is_some(),
+            text_focused,
+            ctx.input().key_pressed(egui::Key::Enter),
+            ctx.input().key_pressed(egui::Key::Space),
+        );
 
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.horizontal_wrapped(|ui| {
@@ -323,14 +2761,70 @@ impl epi::App for App {
                     };
                     egui::Frame::group(ui.style())
                         .stroke(stroke)
-                        .show(ui, |ui| match self.mode {
-                            Mode::Listen => self.listener(ui),
-                            Mode::Send => self.sender(ui),
+                        .show(ui, |ui| {
+                            match self.mode {
+                                Mode::Listen => self.listener(ui),
+                                Mode::Send => self.sender(ui),
+                            }
+                            if let Some(ref task) = self.task {
+                                ui.label(running_line(task.started.elapsed()));
+                            }
                         })
                 });
-                egui::ScrollArea::auto_sized().show(ui, |ui| {
-                    ui.set_enabled(false);
-                    ui.add(egui::TextEdit::multiline(&mut self.log).frame(false));
+                ui.vertical(|ui| {
+                    if self.mode == Mode::Listen {
+                        self.received_table(ui);
+                        ui.separator();
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Clear").clicked() {
+                            self.log.clear();
+                        }
+                        ui.add(
+                            egui::Slider::usize(&mut self.max_log_lines, 0..=100_000)
+                                .text("max lines"),
+                        )
+                        .on_hover_text("Oldest lines are dropped past this, 0 for unlimited");
+                        ui.checkbox(&mut self.log_timestamps, "Timestamps")
+                            .on_hover_text("Prefix new lines with UTC time they were added at");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Log file");
+                        let input = ui
+                            .add(
+                                egui::TextEdit::singleline(&mut self.log_file_input)
+                                    .hint_text("blank for none"),
+                            )
+                            .on_hover_text(
+                                "Append every new line to this file as it's logged, \
+                                 once confirmed with Enter",
+                            );
+                        if input.lost_kb_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                            self.log_file = self.log_file_input.trim().to_owned();
+                        }
+                        ui.add(
+                            egui::Slider::u32(&mut self.log_file_kb, 1..=100_000)
+                                .text("KiB per file"),
+                        )
+                        .on_hover_text("Past this, file is moved aside to <file>.1");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Show");
+                        ui.checkbox(&mut self.log_filter.info, "Info");
+                        ui.checkbox(&mut self.log_filter.warning, "Warnings");
+                        ui.checkbox(&mut self.log_filter.failure, "Failures");
+                    });
+                    let filter = self.log_filter;
+                    egui::ScrollArea::auto_sized().show(ui, |ui| {
+                        for line in self.log.iter().filter(|line| filter.shows(line.level)) {
+                            let label = egui::Label::new(&line.text).monospace();
+                            ui.add(match line.level {
+                                Level::Info => label,
+                                Level::Warning => label.text_color(WARNING_COLOR),
+                                Level::Failure => label.text_color(egui::Color32::RED),
+                            });
+                        }
+                    });
                 });
             });
         });
@@ -343,3 +2837,922 @@ pub fn run() -> ! {
     let app = App::default();
     eframe::run_native(Box::new(app));
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::app::*;
+
+    #[test]
+    fn id_range() {
+        assert_eq!(parse_id_range("", ""), Ok(0..=u32::MAX));
+        assert_eq!(parse_limit(" "), Ok(None));
+        assert_eq!(parse_limit("10 "), Ok(Some(10)));
+        assert!(parse_limit("-1").is_err());
+        assert_eq!(parse_id_range("10", " 20 "), Ok(10..=20));
+        assert_eq!(parse_id_range("", "20"), Ok(0..=20));
+        assert!(parse_id_range("-1", "").is_err());
+        assert!(parse_id_range("", "lots").is_err());
+        assert_eq!(parse_source_filter(" "), Ok(None));
+        assert_eq!(
+            parse_source_filter(" 10.0.0.1"),
+            Ok(Some(IpAddr::from([10, 0, 0, 1])))
+        );
+        assert!(parse_source_filter("10.0.0.1:8142").is_err());
+    }
+
+    #[test]
+    fn filter() {
+        let filter = parse_id_range("10", "20").unwrap();
+        assert!(!passes_filter(9, &filter));
+        assert!(passes_filter(10, &filter));
+        assert!(passes_filter(20, &filter));
+        assert!(!passes_filter(21, &filter));
+
+        let everything = parse_id_range("", "").unwrap();
+        assert!(passes_filter(0, &everything));
+        assert!(passes_filter(u32::MAX, &everything));
+
+        // Inverted range lets nothing through
+        let nothing = parse_id_range("20", "10").unwrap();
+        assert!(!passes_filter(15, &nothing));
+
+        let mut app = App::default();
+        app.handle_status(StatusMessage::Filtered);
+        app.handle_status(StatusMessage::OtherSource(3));
+        app.handle_status(StatusMessage::OsDropped(5));
+        app.handle_status(StatusMessage::OsDropped(7));
+        app.handle_status(StatusMessage::Received { corrupted: false });
+        assert_eq!(app.counters.filtered, 1);
+        assert_eq!(app.counters.other_source, 3);
+        assert_eq!(app.counters.os_dropped, 7);
+        assert_eq!(app.counters.received, 1);
+    }
+
+    #[test]
+    fn addr() {
+        for good in [
+            "0.0.0.0:8142",
+            " 127.0.0.1:0 ",
+            "[::]:8142",
+            "[fe80::1%2]:8142",
+            "localhost:8142",
+        ]
+        .iter()
+        {
+            assert_eq!(validate_addr(good), Ok(()), "{}", good);
+        }
+        assert_eq!(validate_addr(""), Err("Address is empty"));
+        assert_eq!(validate_addr("  "), Err("Address is empty"));
+        assert_eq!(validate_addr("localhost"), Err("Missing port"));
+        assert_eq!(validate_addr("0.0.0.0:99999"), Err("Invalid port"));
+        assert_eq!(validate_addr("0.0.0.0:"), Err("Invalid port"));
+        assert_eq!(validate_addr(":8142"), Err("Missing host"));
+        assert_eq!(validate_addr("::1:8142"), Err("Invalid address"));
+        assert_eq!(validate_addr("[::g]:8142"), Err("Invalid address"));
+        assert_eq!(validate_local_addr("192.168.1.10:0"), Ok(()));
+        assert_eq!(validate_local_addr("localhost:0"), Ok(()));
+        assert_eq!(validate_local_addr(":0"), Err("Missing host"));
+        assert_eq!(
+            validate_local_addr("239.1.2.3:0"),
+            Err("Can't send from a multicast address")
+        );
+        assert_eq!(
+            validate_local_addr("255.255.255.255:0"),
+            Err("Can't send from a broadcast address")
+        );
+
+        assert_eq!(
+            split_destinations(" 10.0.0.1:1, ,[::1]:2,"),
+            vec!["10.0.0.1:1", "[::1]:2"]
+        );
+        assert!(split_destinations(" , ").is_empty());
+    }
+
+    #[test]
+    fn settings() {
+        let app = App {
+            mode: Mode::Listen,
+            hdpi: false,
+            tx_addr: "127.0.0.1:8143".to_owned(),
+            log: vec![LogLine {
+                level: Level::Info,
+                text: "Stopped".to_owned(),
+            }]
+            .into(),
+            status: Some(false),
+            ..App::default()
+        };
+
+        let json = serde_json::to_string(&app.settings()).unwrap();
+        assert!(!json.contains("Stopped"));
+        let settings: Settings = serde_json::from_str(&json).unwrap();
+        assert_eq!(settings, app.settings());
+
+        let mut restored = App::default();
+        restored.apply_settings(settings);
+        assert_eq!(restored.settings(), app.settings());
+        assert!(restored.log.is_empty());
+        assert_eq!(restored.status, None);
+
+        // Settings saved by older versions lack some fields
+        let settings: Settings = serde_json::from_str(r#"{"tx_addr":"10.0.0.1:1"}"#).unwrap();
+        assert_eq!(settings.tx_addr, "10.0.0.1:1");
+        assert_eq!(settings.bind_addr, App::default().bind_addr);
+    }
+
+    #[test]
+    fn dry_run() {
+        let rx_sock = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx_sock
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_dry_run(true);
+        let records = [
+            Record {
+                id: 1,
+                data: "r".to_owned(),
+            },
+            Record {
+                id: 0x01020304,
+                data: String::new(),
+            },
+        ];
+        sender
+            .send(records.iter(), rx_sock.local_addr().unwrap())
+            .unwrap();
+
+        let lines: Vec<String> = sender
+            .take_dry_run()
+            .iter()
+            .map(|datagram| hex_line(datagram))
+            .collect();
+        assert_eq!(lines, vec!["01 00 00 00 72", "04 03 02 01"]);
+        assert!(rx_sock.recv_from(&mut [0_u8; 16]).is_err());
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(hexdump(&[]), "");
+        assert_eq!(
+            hexdump(b"\x01\0\0\0Hi there!\n\xc3\x28 ~\x7f"),
+            "00000000  01 00 00 00 48 69 20 74  68 65 72 65 21 0a c3 28  |....Hi there!..(|\n\
+             00000010  20 7e 7f                                          | ~.|"
+        );
+        // Short line is padded to keep ASCII column aligned
+        let dump = hexdump(&[0x41; 20]);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].find('|'), lines[1].find('|'));
+    }
+
+    #[test]
+    fn stop() {
+        let (control, control_receiver) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            let mut polls = 0;
+            // Simulate reads that always wait for the full timeout
+            poll_until_stopped(&control_receiver, &Cell::new(false), || {
+                polls += 1;
+                std::thread::sleep(POLL_INTERVAL);
+                Ok(())
+            })
+            .unwrap();
+            polls
+        });
+        std::thread::sleep(POLL_INTERVAL * 3 / 2);
+        let stopped = Instant::now();
+        control.send(ControlMessage::Stop).unwrap();
+        let polls = worker.join().unwrap();
+        assert!(
+            stopped.elapsed() < POLL_INTERVAL + POLL_INTERVAL / 2,
+            "{:?}",
+            stopped.elapsed()
+        );
+        assert!(polls >= 2);
+
+        // Worker doesn't outlive UI thread
+        let (control, control_receiver) = mpsc::channel();
+        drop(control);
+        let mut polls = 0;
+        let res = poll_until_stopped(&control_receiver, &Cell::new(false), || {
+            polls += 1;
+            Ok(())
+        });
+        assert_eq!(res, Ok(()));
+        assert_eq!(polls, 0);
+    }
+
+    #[test]
+    fn pause() {
+        let (control, control_receiver) = mpsc::channel();
+        let paused = Cell::new(false);
+        let mut seen = Vec::new();
+        control.send(ControlMessage::Pause).unwrap();
+        let res = poll_until_stopped(&control_receiver, &paused, || {
+            seen.push(paused.get());
+            let next = match seen.len() {
+                2 => ControlMessage::Resume,
+                3 => ControlMessage::Pause,
+                4 => ControlMessage::Stop,
+                _ => return Ok(()),
+            };
+            control.send(next).unwrap();
+            Ok(())
+        });
+        assert_eq!(res, Ok(()));
+        // Polling goes on while paused
+        assert_eq!(seen, vec![true, true, false, true]);
+        assert!(paused.get());
+    }
+
+    #[test]
+    fn stop_send() {
+        let (control, control_receiver) = mpsc::channel();
+        let stopped = Cell::new(false);
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_dry_run(true);
+
+        let records = (0..100).map(|id| {
+            if id == 10 {
+                control.send(ControlMessage::Stop).unwrap();
+            }
+            vec![id as u8]
+        });
+        let report = sender
+            .send(
+                until_stopped(records, &control_receiver, &stopped),
+                "127.0.0.1:9",
+            )
+            .unwrap();
+        // Record pulled along with Stop is not sent
+        assert_eq!(report.items_sent, 10);
+        assert_eq!(sender.take_dry_run().len(), 10);
+        assert!(stopped.get());
+
+        // Hang up isn't a Stop, but ends sending all the same
+        drop(control);
+        let stopped = Cell::new(false);
+        let sent = until_stopped(0..100, &control_receiver, &stopped).count();
+        assert_eq!(sent, 0);
+        assert!(!stopped.get());
+    }
+
+    #[test]
+    fn repeat() {
+        let (control, control_receiver) = mpsc::channel();
+        let stopped = Cell::new(false);
+        let passes = Cell::new(0);
+        let mut made = 0;
+        let records = repeat_passes(
+            || {
+                made += 1;
+                // Stop arrives as third pass begins
+                if made == 3 {
+                    control.send(ControlMessage::Stop).unwrap();
+                }
+                Some(0..3)
+            },
+            &passes,
+        );
+        let sent: Vec<_> = until_stopped(records, &control_receiver, &stopped).collect();
+        assert_eq!(sent, vec![0, 1, 2, 0, 1, 2]);
+        assert_eq!(passes.get(), 2);
+        assert!(stopped.get());
+
+        // Empty source doesn't loop forever
+        let passes = Cell::new(0);
+        assert_eq!(repeat_passes(|| Some(0..0), &passes).count(), 0);
+        assert_eq!(passes.get(), 1);
+
+        // Single pass when not looping
+        let passes = Cell::new(0);
+        let mut first = true;
+        let make = || Some(0..3).filter(|_| std::mem::replace(&mut first, false));
+        assert_eq!(repeat_passes(make, &passes).count(), 3);
+        assert_eq!(passes.get(), 1);
+    }
+
+    #[test]
+    fn shortcuts() {
+        assert_eq!(
+            shortcut(Mode::Send, false, false, true, false),
+            Some(Shortcut::Run)
+        );
+        assert_eq!(shortcut(Mode::Send, true, false, true, false), None);
+        assert_eq!(shortcut(Mode::Send, false, false, false, true), None);
+        assert_eq!(
+            shortcut(Mode::Listen, false, false, false, true),
+            Some(Shortcut::Run)
+        );
+        assert_eq!(
+            shortcut(Mode::Listen, true, false, false, true),
+            Some(Shortcut::Stop)
+        );
+        assert_eq!(shortcut(Mode::Listen, false, false, true, false), None);
+        // Typing into a field
+        assert_eq!(shortcut(Mode::Send, false, true, true, false), None);
+        assert_eq!(shortcut(Mode::Listen, true, true, false, true), None);
+    }
+
+    #[test]
+    fn delimiter_parsing() {
+        assert_eq!(parse_delimiter(" "), Ok(None));
+        assert_eq!(parse_delimiter("0a"), Ok(Some(b'\n')));
+        assert!(parse_delimiter("5c").is_err());
+        assert!(parse_delimiter("0a0d").is_err());
+        assert!(parse_delimiter("zz").is_err());
+    }
+
+    #[test]
+    fn hex_parsing() {
+        assert_eq!(parse_hex("01 00 00 00 ff"), Ok(vec![1, 0, 0, 0, 0xff]));
+        assert_eq!(parse_hex(" 0aFf\n10"), Ok(vec![0x0a, 0xff, 0x10]));
+        assert_eq!(parse_hex(""), Ok(vec![]));
+        assert_eq!(
+            parse_hex("01 0"),
+            Err("Odd number of hex digits, every byte takes two".to_owned())
+        );
+        assert_eq!(parse_hex("0g"), Err("'g' isn't a hex digit".to_owned()));
+        assert_eq!(parse_hex("0x01"), Err("'x' isn't a hex digit".to_owned()));
+        assert!(parse_hex("é0").is_err());
+    }
+
+    #[test]
+    fn bind_in_use() {
+        let taken = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap().to_string();
+        let in_use = std::net::UdpSocket::bind(&addr).unwrap_err();
+        assert_eq!(
+            describe_bind_error(&addr, &in_use),
+            format!(
+                "Address {} is already in use, maybe by another instance. Pick another \
+                 port, or enable sharing it or falling back to a free one",
+                addr
+            )
+        );
+        let unassigned = std::io::Error::from(std::io::ErrorKind::AddrNotAvailable);
+        assert_eq!(
+            describe_bind_error("192.0.2.1:0", &unassigned),
+            "192.0.2.1:0 isn't assigned to any interface of this host, \
+             pick an address of the interface to use"
+        );
+        let other = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(describe_bind_error(&addr, &other).starts_with("Couldn't bind to 127.0.0.1:"));
+
+        let (status, status_receiver) = mpsc::channel();
+        let status = StatusSender(status);
+        let bind = |addr: &str| std::net::UdpSocket::bind(addr);
+        assert!(bind_socket(&addr, false, bind, &status).is_err());
+        match status_receiver.try_recv() {
+            Ok(StatusMessage::Failure(message)) => assert!(message.contains("already in use")),
+            _ => panic!("Expected failure"),
+        }
+        let sock = bind_socket(&addr, true, bind, &status).unwrap();
+        assert_ne!(sock.local_addr().unwrap(), taken.local_addr().unwrap());
+        assert!(matches!(
+            status_receiver.try_recv(),
+            Ok(StatusMessage::Warning(_))
+        ));
+    }
+
+    #[test]
+    fn listening_on_any_port() {
+        let receiver: Receiver<Vec<u8>> = Receiver::new("127.0.0.1:0").unwrap();
+        let line = listening_line(&receiver, "127.0.0.1:0");
+        let port = receiver.local_addr().unwrap().port();
+        assert_ne!(port, 0);
+        assert_eq!(line, format!("Listening on 127.0.0.1:{}...", port));
+    }
+
+    #[test]
+    fn count_only() {
+        let receiver: Receiver<PayloadLen> = Receiver::new("127.0.0.1:0").unwrap();
+        let dest = receiver.local_addr().unwrap();
+        let (control, control_receiver) = mpsc::channel();
+        let (status, status_receiver) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            count_traffic(receiver, &control_receiver, true, &StatusSender(status))
+        });
+
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        let records = Record::generate(10, 16);
+        sender.send(records.iter(), dest).unwrap();
+        std::thread::sleep(TRAFFIC_INTERVAL * 2);
+        control.send(ControlMessage::Stop).unwrap();
+        assert_eq!(worker.join().unwrap(), Ok(()));
+
+        let totals: Vec<_> = status_receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                StatusMessage::Traffic(counts) => Some(counts),
+                _ => None,
+            })
+            .collect();
+        // Periodic reports, then final one
+        assert!(totals.len() >= 2);
+        assert_eq!(
+            totals.last(),
+            Some(&TrafficCounts {
+                items: 10,
+                bytes: 10 * 20,
+                ..TrafficCounts::default()
+            })
+        );
+    }
+
+    #[test]
+    fn preview() {
+        let preview = |source, path: &Path| {
+            let (status, status_receiver) = mpsc::channel();
+            let res = preview_records(source, &path.display().to_string(), &StatusSender(status));
+            let messages: Vec<_> = status_receiver
+                .try_iter()
+                .map(|message| match message {
+                    StatusMessage::Info(text) => text,
+                    StatusMessage::Total(total) => format!("Total {}", total),
+                    StatusMessage::Success => "Success".to_owned(),
+                    StatusMessage::Failure(text) => format!("Failure {}", text),
+                    _ => "Unexpected".to_owned(),
+                })
+                .collect();
+            (res, messages)
+        };
+        let db =
+            std::env::temp_dir().join(format!("udptest-preview-{}.sqlite", std::process::id()));
+        let mut records = Record::generate(8, 4);
+        records[1].data = "a".repeat(100);
+        Record::create_db(&db, &records).unwrap();
+
+        let (res, messages) = preview(Source::Sqlite, &db);
+        assert_eq!(res, Ok(()));
+        assert_eq!(messages.len(), 8);
+        assert_eq!(
+            messages[0],
+            format!("Loaded 8 records from {}", db.display())
+        );
+        assert_eq!(messages[1], format!("[0 : {}]", records[0].data));
+        assert_eq!(messages[2], format!("[1 : {}...]", "a".repeat(60)));
+        assert_eq!(messages[6..], ["Total 8", "Success"]);
+
+        // Corrupted DB fails without panicking
+        std::fs::write(&db, "not a database").unwrap();
+        let (res, messages) = preview(Source::Sqlite, &db);
+        assert_eq!(res, Err(()));
+        assert!(messages[0].starts_with("Failure Couldn't load records from DB"));
+        std::fs::remove_file(&db).unwrap();
+
+        let (res, messages) = preview(Source::Csv, &db);
+        assert_eq!(res, Err(()));
+        assert!(messages[0].starts_with("Failure No such file"));
+    }
+
+    #[test]
+    // Worker stops quietly once UI thread stops listening
+    fn hangup() {
+        let (_control, control_receiver) = mpsc::channel();
+        let (status, status_receiver) = mpsc::channel();
+        let status = StatusSender(status);
+        drop(status_receiver);
+        let worker = std::thread::spawn(move || {
+            let mut polls = 0;
+            let res = poll_until_stopped(&control_receiver, &Cell::new(false), || {
+                polls += 1;
+                status.send(StatusMessage::Received { corrupted: false })
+            });
+            status.fail("Unreachable".into());
+            (res, polls)
+        });
+        assert_eq!(worker.join().unwrap(), (Err(()), 1));
+    }
+
+    #[test]
+    fn counters() {
+        let mut app = App::default();
+        assert_eq!(app.counters.progress(), 0.0);
+        app.handle_status(StatusMessage::Total(4));
+        app.handle_status(StatusMessage::Progress(1));
+        app.handle_status(StatusMessage::Progress(2));
+        assert_eq!(app.counters.sent, 2);
+        assert_eq!(app.counters.total, 4);
+        assert_eq!(app.counters.progress(), 0.5);
+
+        for &corrupted in [false, true, false, false].iter() {
+            app.handle_status(StatusMessage::Received { corrupted });
+        }
+        assert_eq!(app.counters.received, 3);
+        assert_eq!(app.counters.corrupted, 1);
+        assert!(app.log.is_empty());
+
+        app.handle_status(StatusMessage::Info("Done!".into()));
+        app.handle_status(StatusMessage::Success);
+        assert_eq!(app.counters.sent, 2);
+        assert_eq!(app.status, Some(true));
+
+        // New send starts from scratch
+        app.handle_status(StatusMessage::Total(10));
+        assert_eq!(app.counters.sent, 0);
+        assert_eq!(app.counters.progress(), 0.0);
+    }
+
+    #[test]
+    fn log_cap() {
+        let mut log = VecDeque::new();
+        for i in 0..5 {
+            append_log(&mut log, Level::Info, &i.to_string(), 3);
+        }
+        assert_eq!(log_text(&log), "2\n3\n4\n");
+
+        // Lines that came in with embedded newlines count separately
+        append_log(&mut log, Level::Info, "5\n6", 3);
+        assert_eq!(log_text(&log), "4\n5\n6\n");
+
+        let mut log = VecDeque::new();
+        for i in 0..5 {
+            append_log(&mut log, Level::Info, &i.to_string(), 0);
+        }
+        assert_eq!(log.len(), 5);
+    }
+
+    #[test]
+    fn log_levels() {
+        let mut app = App {
+            log_timestamps: false,
+            ..App::default()
+        };
+        app.handle_status(StatusMessage::Info("Listening".into()));
+        app.handle_status(StatusMessage::ListenerWarning(ListenerWarning {
+            message: "Corrupted\n0000 ff".into(),
+            source: Some("127.0.0.1:8142".parse().unwrap()),
+            error: "record has no data".into(),
+        }));
+        app.handle_status(StatusMessage::Failure("Socket closed".into()));
+        assert_eq!(
+            log_text(&app.log),
+            "INFO Listening\nWARN Corrupted\n0000 ff\nFAIL Socket closed\n"
+        );
+        // Continuation lines keep level of their message
+        assert_eq!(app.log[2].level, Level::Warning);
+
+        let filter = LevelFilter {
+            warning: false,
+            ..LevelFilter::default()
+        };
+        let shown = app.log.iter().filter(|line| filter.shows(line.level));
+        assert_eq!(log_text(shown), "INFO Listening\nFAIL Socket closed\n");
+        let filter = LevelFilter {
+            info: false,
+            failure: false,
+            ..LevelFilter::default()
+        };
+        let shown = app.log.iter().filter(|line| filter.shows(line.level));
+        assert_eq!(log_text(shown), "WARN Corrupted\n0000 ff\n");
+        // Filtering doesn't touch the buffer
+        assert_eq!(app.log.len(), 4);
+    }
+
+    #[test]
+    fn decode_reject_empty() {
+        let format = RecordCodec::Binary.format(ByteOrder::Little).unwrap();
+        let timed = |data: &str| {
+            TimedRecord {
+                record: Record {
+                    id: 3,
+                    data: data.to_owned(),
+                },
+                timestamp: 1000,
+            }
+            .to_udp_with(format)
+        };
+        let decode = |bytes: &[u8], codec, timestamps, reject_empty| {
+            decode_received(bytes, codec, ByteOrder::Little, timestamps, reject_empty)
+        };
+
+        // Timestamped records are checked too
+        let empty = timed("");
+        assert_eq!(
+            decode(&empty, RecordCodec::Binary, true, false).map(|(r, ts)| (r.id, ts)),
+            Ok((3, Some(1000)))
+        );
+        assert_eq!(
+            decode(&empty, RecordCodec::Binary, true, true),
+            Err(ParseError::Empty)
+        );
+        let full = timed("x");
+        assert_eq!(
+            decode(&full, RecordCodec::Binary, true, true).map(|(r, _)| r.data),
+            Ok("x".to_owned())
+        );
+
+        // So are other codecs
+        let json = br#"{"id":3,"data":""}"#;
+        assert!(decode(json, RecordCodec::Json, false, false).is_ok());
+        assert_eq!(
+            decode(json, RecordCodec::Json, false, true),
+            Err(ParseError::Empty)
+        );
+        assert_eq!(
+            decode(&[3, 0, 0, 0], RecordCodec::Binary, false, true),
+            Err(ParseError::Empty)
+        );
+    }
+
+    #[test]
+    fn corruption_policy() {
+        let raw: &[u8] = b"\x00\x01";
+        let description = || "Got corrupted packet".to_owned();
+        assert_eq!(
+            corruption_action(CorruptionPolicy::Warn, description(), Some(raw)),
+            CorruptionAction::Warn("Got corrupted packet".into())
+        );
+        assert_eq!(
+            corruption_action(CorruptionPolicy::Stop, description(), Some(raw)),
+            CorruptionAction::Stop("Got corrupted packet".into())
+        );
+        assert_eq!(
+            corruption_action(CorruptionPolicy::Ignore, description(), Some(raw)),
+            CorruptionAction::Ignore
+        );
+        assert_eq!(
+            corruption_action(CorruptionPolicy::DumpRaw, description(), Some(raw)),
+            CorruptionAction::Warn(format!("Got corrupted packet\n{}", hexdump(raw)))
+        );
+        // Nothing to dump for errors found before decoding
+        assert_eq!(
+            corruption_action(CorruptionPolicy::DumpRaw, description(), None),
+            CorruptionAction::Warn("Got corrupted packet".into())
+        );
+        // Same output as before policies were configurable
+        assert_eq!(CorruptionPolicy::default(), CorruptionPolicy::DumpRaw);
+        assert_eq!(
+            corruption_action(CorruptionPolicy::default(), description(), Some(raw)),
+            CorruptionAction::Warn(format!("Got corrupted packet\n{}", hexdump(raw)))
+        );
+    }
+
+    #[test]
+    fn elapsed() {
+        assert_eq!(format_elapsed(Duration::from_millis(999)), "0:00");
+        assert_eq!(format_elapsed(Duration::from_secs(61)), "1:01");
+        assert_eq!(format_elapsed(Duration::from_secs(3599)), "59:59");
+        assert_eq!(format_elapsed(Duration::from_secs(3600)), "1:00:00");
+        assert_eq!(
+            format_elapsed(Duration::from_secs(100 * 3600 + 62)),
+            "100:01:02"
+        );
+
+        assert_eq!(running_line(Duration::from_secs(5)), "| Running for 0:05");
+        assert_eq!(
+            running_line(Duration::from_millis(5250)),
+            "/ Running for 0:05"
+        );
+    }
+
+    #[test]
+    fn timestamp() {
+        assert_eq!(timestamp_line(0, "Started"), "00:00:00.000 Started");
+        // 2021-03-01 13:05:09.042 UTC
+        let millis = 1_614_603_909_042;
+        assert_eq!(
+            timestamp_line(millis, "Got record"),
+            "13:05:09.042 Got record"
+        );
+        assert_eq!(
+            timestamp_line(millis - 13 * 3_600_000 - 42 - 1, "x"),
+            "00:05:08.999 x"
+        );
+
+        let mut app = App::default();
+        app.push_log(Level::Info, "With");
+        app.log_timestamps = false;
+        app.push_log(Level::Info, "Without");
+        assert_eq!(app.log[0].text.len(), "00:00:00.000 INFO With".len());
+        assert!(app.log[0].text.ends_with(" INFO With"));
+        assert_eq!(app.log[1].text, "INFO Without");
+    }
+
+    /// Record with given id, data and arrival time.
+    fn received(id: u32, data: &str, received_at: u64) -> ReceivedRecord {
+        ReceivedRecord {
+            received_at,
+            source: "127.0.0.1:8143".parse().unwrap(),
+            record: Record {
+                id,
+                data: data.to_owned(),
+            },
+            raw: [&id.to_le_bytes()[..], data.as_bytes()].concat(),
+            latency: None,
+        }
+    }
+
+    #[test]
+    fn received_data_as_hex() {
+        let record = received(1, "Hi\u{7}", 0);
+        assert_eq!(record.shown_data(false), "Hi\u{7}");
+        assert_eq!(record.shown_data(true), "01 00 00 00 48 69 07");
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut buffer = RingBuffer::new(3);
+        for i in 0..2 {
+            buffer.push(i);
+        }
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), [0, 1]);
+        // Oldest items go first
+        for i in 2..5 {
+            buffer.push(i);
+        }
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(buffer.len(), 3);
+
+        buffer.set_capacity(2);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), [3, 4]);
+        buffer.set_capacity(4);
+        buffer.push(5);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), [3, 4, 5]);
+
+        // Always room for the latest item
+        let mut buffer = RingBuffer::new(0);
+        assert_eq!(buffer.capacity(), 1);
+        buffer.push(0);
+        buffer.push(1);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), [1]);
+        buffer.clear();
+        assert_eq!(buffer.len(), 0);
+    }
+
+    #[test]
+    fn received_cap() {
+        // Log length doesn't limit history
+        let mut app = App {
+            max_log_lines: 1,
+            ..App::default()
+        };
+        assert_eq!(app.received.capacity(), DEFAULT_HISTORY);
+        app.received.set_capacity(2);
+        for id in 0..3 {
+            app.handle_status(StatusMessage::Record(received(id, "r", 0)));
+        }
+        let kept: Vec<_> = app.received.iter().cloned().collect();
+        assert_eq!(kept, [received(1, "r", 0), received(2, "r", 0)]);
+        assert!(app.log.is_empty());
+    }
+
+    #[test]
+    fn received_sort() {
+        let records = [
+            received(2, "b", 10),
+            received(1, "c", 20),
+            received(3, "a", 30),
+            received(2, "d", 40),
+        ];
+        let data = |order| -> Vec<&str> {
+            sorted_records(&records, order)
+                .iter()
+                .map(|r| r.record.data.as_str())
+                .collect()
+        };
+        let order = SortOrder::default();
+        assert_eq!(data(order), ["b", "c", "a", "d"]);
+        // Equal ids stay in arrival order either way
+        let order = order.clicked(Column::Id);
+        assert_eq!(data(order), ["c", "b", "d", "a"]);
+        let order = order.clicked(Column::Id);
+        assert!(!order.ascending);
+        assert_eq!(data(order), ["a", "b", "d", "c"]);
+        let order = order.clicked(Column::Data);
+        assert!(order.ascending);
+        assert_eq!(data(order), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn send_errors() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            describe_send_error(&refused),
+            "Destination refused connection (port closed?)"
+        );
+        let other = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        assert_eq!(describe_send_error(&other), "Error sending data: denied");
+    }
+
+    #[test]
+    fn timing() {
+        let mut timing = SendTiming::default();
+        for &us in [40, 10, 1000].iter() {
+            timing.add(Duration::from_micros(us));
+        }
+        assert_eq!(
+            timing_line(&timing),
+            "3 writes took 350 us on average, slowest: 1000, 40, 10 us"
+        );
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(id_list(&[3, 1, 4]), "3, 1, 4");
+        let ids: Vec<u32> = (1..=12).collect();
+        assert_eq!(id_list(&ids), "1, 2, 3, 4, 5, 6, 7, 8, 9, 10 and 2 more");
+    }
+
+    #[test]
+    fn rolling_log() {
+        let path = std::env::temp_dir().join(format!("udptest-rolling-{}.log", std::process::id()));
+        let mut writer = RollingLog::open(&path, 20).unwrap();
+        let rotated = writer.rotated_path();
+        // Two lines of 10 bytes fill it up exactly
+        writer.write_line("INFO 1234").unwrap();
+        writer.write_line("INFO 5678").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "INFO 1234\nINFO 5678\n"
+        );
+        assert!(!rotated.exists());
+
+        writer.write_line("WARN abc").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rotated).unwrap(),
+            "INFO 1234\nINFO 5678\n"
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "WARN abc\n");
+
+        // Reopened file picks up where it was
+        drop(writer);
+        let mut writer = RollingLog::open(&path, 20).unwrap();
+        assert_eq!(writer.size, 9);
+        writer.write_line("this line is too long to fit").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap(), "WARN abc\n");
+        writer.write_line("x").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rotated).unwrap(),
+            "this line is too long to fit\n"
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\n");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+
+    #[test]
+    fn log_file() {
+        let path = std::env::temp_dir().join(format!("udptest-app-{}.log", std::process::id()));
+        let mut app = App {
+            log_file: path.display().to_string(),
+            ..App::default()
+        };
+        app.push_log(Level::Warning, "Lost 2 packets");
+        app.log_timestamps = false;
+        app.push_log(Level::Info, "Stopped");
+        app.flush_log_file();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, format!("{}\nINFO Stopped\n", app.log[0].text));
+
+        // Lines moved aside by rotation come first
+        let rotated = rotated_path(&path);
+        std::fs::write(&rotated, "FAIL Older\n").unwrap();
+        let mut restored = App {
+            log_file: path.display().to_string(),
+            log_timestamps: false,
+            ..App::default()
+        };
+        restored.reload_log_file();
+        let levels: Vec<Level> = restored.log.iter().map(|line| line.level).collect();
+        assert_eq!(
+            levels,
+            [Level::Failure, Level::Warning, Level::Info, Level::Info]
+        );
+        assert_eq!(restored.log[0].text, "FAIL Older");
+        assert_eq!(restored.log[2].text, "INFO Stopped");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+
+        // Unwritable file is reported once and then left alone
+        let mut app = App {
+            log_file: std::env::temp_dir().display().to_string(),
+            ..App::default()
+        };
+        app.push_log(Level::Info, "Started");
+        assert!(app.log_file.is_empty());
+        assert_eq!(app.log.len(), 2);
+        assert_eq!(app.log[1].level, Level::Warning);
+    }
+
+    #[test]
+    fn save() {
+        let path = std::env::temp_dir().join(format!("udptest-log-{}.txt", std::process::id()));
+        let log = "Listening on 0.0.0.0:8142...\nGot record [1 : one] from 127.0.0.1:8143\n";
+        save_log(&path, log).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), log);
+        // Replaces previous contents instead of appending
+        save_log(&path, "Stopped\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Stopped\n");
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(save_log(&std::env::temp_dir(), log).is_err());
+    }
+}
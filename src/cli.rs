@@ -0,0 +1,636 @@
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+use std::{net::SocketAddr, path::Path};
+
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, MetricsServer};
+#[cfg(feature = "compression")]
+use crate::record::CompressedRecord;
+use crate::{
+    error::AppError,
+    record::{now_millis, ParseError, RawRecord, Record, Record64, TimedRecord},
+    udp::{Error, FromUdp, Receiver, Sender},
+};
+
+/// Printed when arguments can't be parsed.
+pub const USAGE: &str = "\
+Usage:
+    udptest                                     start GUI
+    udptest send [--bind ADDR] --to ADDR --db FILE [--loop] [--replay SPEED] [--id64 | --raw | --compress]
+    udptest listen --bind ADDR [--count N] [--json] [--metrics ADDR] [--id64 | --raw | --compress]";
+
+/// Which record type goes on the wire, picked with a flag on both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RecordKind {
+    /// [Record], with a 4-byte id and UTF-8 data.
+    #[default]
+    Text,
+    /// [Record64], with an 8-byte id, given by `--id64`.
+    Id64,
+    /// [RawRecord], with binary data printed as hex, given by `--raw`.
+    Raw,
+    /// [CompressedRecord], with deflated data, given by `--compress`.
+    #[cfg(feature = "compression")]
+    Compressed,
+}
+
+impl RecordKind {
+    /// Returns kind selected by `flag`, if it's one of theirs.
+    fn from_flag(flag: &str) -> Result<Option<Self>, String> {
+        match flag {
+            "--id64" => Ok(Some(RecordKind::Id64)),
+            "--raw" => Ok(Some(RecordKind::Raw)),
+            #[cfg(feature = "compression")]
+            "--compress" => Ok(Some(RecordKind::Compressed)),
+            #[cfg(not(feature = "compression"))]
+            "--compress" => Err("--compress needs building with --features compression".into()),
+            _ => Ok(None),
+        }
+    }
+
+    /// Parses `payload` as a record of this kind, into its id and data,
+    /// binary data being turned into hex digits.
+    fn parse(self, payload: &[u8]) -> Result<(u64, String), ParseError> {
+        match self {
+            RecordKind::Text => Record::from_udp(payload).map(|r| (r.id.into(), r.data)),
+            RecordKind::Id64 => Record64::from_udp(payload).map(|r| (r.id, r.data)),
+            RecordKind::Raw => RawRecord::from_udp(payload).map(|r| (r.id.into(), hex(&r.data))),
+            #[cfg(feature = "compression")]
+            RecordKind::Compressed => {
+                CompressedRecord::from_udp(payload).map(|r| (r.0.id.into(), r.0.data))
+            }
+        }
+    }
+}
+
+/// What to do without GUI, as given on command line.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Send all records of SQLite database at `db` to `to`,
+    /// from `bind` or any free port, over and over if `repeat` is set.
+    /// With `replay`, records are spaced out by their timestamps,
+    /// with gaps shortened that many times, which only [RecordKind::Text]
+    /// supports.
+    Send {
+        bind: Option<String>,
+        to: String,
+        db: String,
+        repeat: bool,
+        replay: Option<f64>,
+        kind: RecordKind,
+    },
+    /// Print records of `kind` received on `bind`, stopping after `count`
+    /// of them if it's given, as JSON lines if `json` is set.
+    /// With `metrics`, counters are served over HTTP on that address.
+    Listen {
+        bind: String,
+        count: Option<usize>,
+        json: bool,
+        metrics: Option<String>,
+        kind: RecordKind,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+/// Line printed by `listen --json` for every received packet.
+enum JsonLine<'a> {
+    Record {
+        /// Milliseconds since UNIX epoch it arrived at.
+        ts: u64,
+        src: SocketAddr,
+        id: u64,
+        data: &'a str,
+    },
+    Error {
+        error: &'static str,
+        /// Payload as hex digits.
+        raw: String,
+    },
+}
+
+impl JsonLine<'_> {
+    fn to_line(&self) -> String {
+        // Nothing here can fail to serialize
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Parses arguments following program name. Returns `None`
+/// if there are none, meaning GUI should be started.
+pub fn parse_args<I>(args: I) -> Result<Option<Command>, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let (mut bind, mut to, mut db, mut count) = (None, None, None, None);
+    let (mut replay, mut metrics) = (None, None);
+    let (mut repeat, mut json) = (false, false);
+    let mut kind = RecordKind::default();
+    while let Some(flag) = args.next() {
+        if let Some(selected) = RecordKind::from_flag(&flag)? {
+            if kind != RecordKind::default() {
+                return Err("Only one record kind can be given".to_owned());
+            }
+            kind = selected;
+            continue;
+        }
+        if flag == "--loop" {
+            repeat = true;
+            continue;
+        }
+        if flag == "--json" {
+            json = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value of {}", flag))?;
+        match flag.as_str() {
+            "--bind" => bind = Some(value),
+            "--to" => to = Some(value),
+            "--db" => db = Some(value),
+            "--count" => {
+                count = Some(
+                    value
+                        .parse()
+                        .map_err(|e| format!("Invalid --count: {}", e))?,
+                )
+            }
+            "--replay" => {
+                let speed: f64 = value
+                    .parse()
+                    .map_err(|e| format!("Invalid --replay: {}", e))?;
+                if !(speed > 0.0 && speed.is_finite()) {
+                    return Err("--replay must be a positive number".to_owned());
+                }
+                replay = Some(speed)
+            }
+            "--metrics" if cfg!(feature = "metrics") => metrics = Some(value),
+            "--metrics" => return Err("--metrics needs building with --features metrics".into()),
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+    let required = |value: Option<String>, flag| value.ok_or(format!("{} is required", flag));
+    match command.as_str() {
+        "send" if replay.is_some() && kind != RecordKind::Text => {
+            Err("--replay only works with text records".to_owned())
+        }
+        "send" if count.is_none() && !json && metrics.is_none() => Ok(Some(Command::Send {
+            bind,
+            to: required(to, "--to")?,
+            db: required(db, "--db")?,
+            repeat,
+            replay,
+            kind,
+        })),
+        "listen" if to.is_none() && db.is_none() && !repeat && replay.is_none() => {
+            Ok(Some(Command::Listen {
+                bind: required(bind, "--bind")?,
+                count,
+                json,
+                metrics,
+                kind,
+            }))
+        }
+        "send" | "listen" => Err(format!("Unexpected options for {}", command)),
+        _ => Err(format!("Unknown command {}", command)),
+    }
+}
+
+/// Runs `command` to completion, printing progress to stdout.
+pub fn run(command: Command) -> Result<(), AppError> {
+    match command {
+        Command::Send {
+            bind,
+            to,
+            db,
+            repeat,
+            replay,
+            kind,
+        } => {
+            let mut sender = match bind {
+                Some(bind) => Sender::new(bind)?,
+                None => Sender::new_ephemeral()?,
+            };
+            let mut passes = 0;
+            loop {
+                // Reloaded every pass, picking up any changes. Opening
+                // a missing file would create an empty database instead
+                if !Path::new(&db).is_file() {
+                    let message = format!("Database {} not found", db);
+                    return Err(std::io::Error::new(std::io::ErrorKind::NotFound, message).into());
+                }
+                let conn = Connection::open_with_flags(&db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+                let (report, loaded) = match (replay, kind) {
+                    (Some(speed), _) => {
+                        let records = TimedRecord::load(conn)?;
+                        let timed = records.iter().map(|timed| (timed.timestamp, &timed.record));
+                        (sender.send_timed(timed, to.as_str(), speed)?, records.len())
+                    }
+                    (None, RecordKind::Text) => {
+                        let records = Record::load(conn)?;
+                        (sender.send(records.iter(), to.as_str())?, records.len())
+                    }
+                    (None, RecordKind::Id64) => {
+                        let records = Record64::load(conn)?;
+                        (sender.send(records.iter(), to.as_str())?, records.len())
+                    }
+                    (None, RecordKind::Raw) => {
+                        let records = RawRecord::load(conn)?;
+                        (sender.send(records.iter(), to.as_str())?, records.len())
+                    }
+                    #[cfg(feature = "compression")]
+                    (None, RecordKind::Compressed) => {
+                        let records = Record::load(conn)?;
+                        let loaded = records.len();
+                        let compressed = records.into_iter().map(CompressedRecord);
+                        (sender.send(compressed, to.as_str())?, loaded)
+                    }
+                };
+                passes += 1;
+                println!(
+                    "Sent {} records, {} bytes, digest {}",
+                    report.items_sent, report.bytes_sent, report.digest
+                );
+                if report.truncated > 0 {
+                    println!(
+                        "{} records were too large and got truncated",
+                        report.truncated
+                    );
+                }
+                // Nothing to loop over in an empty database
+                if !repeat || loaded == 0 {
+                    break;
+                }
+                println!("Completed {} passes", passes);
+            }
+            Ok(())
+        }
+        Command::Listen {
+            bind,
+            count,
+            json,
+            metrics,
+            kind,
+        } => {
+            // Parsed here rather than by receiver, as kind is only known at runtime
+            let mut receiver: Receiver<Vec<u8>> = Receiver::with_timeout(bind, None)?;
+            // Keep stdout parseable
+            if json {
+                eprintln!("Listening on {}...", receiver.local_addr()?);
+            } else {
+                println!("Listening on {}...", receiver.local_addr()?);
+            }
+            #[cfg(feature = "metrics")]
+            let (counters, _server) = {
+                let counters = Arc::new(Metrics::default());
+                let server = match metrics {
+                    Some(addr) => {
+                        let server = MetricsServer::start(addr, counters.clone())?;
+                        eprintln!("Serving metrics on http://{}/metrics", server.local_addr());
+                        Some(server)
+                    }
+                    None => None,
+                };
+                (counters, server)
+            };
+            // Rejected by parse_args without the feature
+            #[cfg(not(feature = "metrics"))]
+            let _ = metrics;
+            let mut received = 0;
+            while count != Some(received) {
+                match receiver.next_with_source() {
+                    Some(Ok((payload, source))) => match kind.parse(&payload) {
+                        Ok((id, data)) => {
+                            #[cfg(feature = "metrics")]
+                            counters.received(payload.len());
+                            if json {
+                                println!("{}", record_line(id, &data, source, now_millis()));
+                            } else {
+                                println!("[{} : {}] from {}", id, data, source);
+                            }
+                            received += 1;
+                        }
+                        Err(e) => {
+                            #[cfg(feature = "metrics")]
+                            counters.corrupted();
+                            if json {
+                                println!("{}", parse_error_line(&payload))
+                            } else {
+                                let e = Error::ParseError {
+                                    source: e,
+                                    raw: payload,
+                                    src: source,
+                                };
+                                eprintln!("Got corrupted packet: {}", e)
+                            }
+                        }
+                    },
+                    Some(Err(Error::Io(e))) => return Err(e.into()),
+                    Some(Err(e)) => {
+                        #[cfg(feature = "metrics")]
+                        counters.corrupted();
+                        eprintln!("Got corrupted packet: {}", e)
+                    }
+                    // Can't time out without a timeout
+                    None => {}
+                }
+            }
+            if json {
+                eprintln!("Received digest {}", receiver.digest());
+            } else {
+                println!("Received digest {}", receiver.digest());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Formats record that arrived from `source` at `ts` as a JSON line.
+fn record_line(id: u64, data: &str, source: SocketAddr, ts: u64) -> String {
+    JsonLine::Record {
+        ts,
+        src: source,
+        id,
+        data,
+    }
+    .to_line()
+}
+
+/// Formats payload that didn't parse as a JSON line.
+fn parse_error_line(raw: &[u8]) -> String {
+    JsonLine::Error {
+        error: "parse",
+        raw: hex(raw),
+    }
+    .to_line()
+}
+
+/// Formats `bytes` as hex digits, two per byte.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{cli::*, record::OnConflict};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_args(args("")), Ok(None));
+        assert_eq!(
+            parse_args(args("send --to 127.0.0.1:8142 --db test.sqlite")),
+            Ok(Some(Command::Send {
+                bind: None,
+                to: "127.0.0.1:8142".to_owned(),
+                db: "test.sqlite".to_owned(),
+                repeat: false,
+                replay: None,
+                kind: RecordKind::Text,
+            }))
+        );
+        assert_eq!(
+            parse_args(args("send --loop --to 127.0.0.1:8142 --db test.sqlite")),
+            Ok(Some(Command::Send {
+                bind: None,
+                to: "127.0.0.1:8142".to_owned(),
+                db: "test.sqlite".to_owned(),
+                repeat: true,
+                replay: None,
+                kind: RecordKind::Text,
+            }))
+        );
+        assert_eq!(
+            parse_args(args(
+                "send --to 127.0.0.1:8142 --db test.sqlite --replay 2.5"
+            )),
+            Ok(Some(Command::Send {
+                bind: None,
+                to: "127.0.0.1:8142".to_owned(),
+                db: "test.sqlite".to_owned(),
+                repeat: false,
+                replay: Some(2.5),
+                kind: RecordKind::Text,
+            }))
+        );
+        assert_eq!(
+            parse_args(args("listen --count 3 --bind 0.0.0.0:8142")),
+            Ok(Some(Command::Listen {
+                bind: "0.0.0.0:8142".to_owned(),
+                count: Some(3),
+                json: false,
+                metrics: None,
+                kind: RecordKind::Text,
+            }))
+        );
+        assert_eq!(
+            parse_args(args("listen --json --bind 0.0.0.0:8142")),
+            Ok(Some(Command::Listen {
+                bind: "0.0.0.0:8142".to_owned(),
+                count: None,
+                json: true,
+                metrics: None,
+                kind: RecordKind::Text,
+            }))
+        );
+        if cfg!(feature = "metrics") {
+            assert_eq!(
+                parse_args(args("listen --bind 0.0.0.0:8142 --metrics 0.0.0.0:9100")),
+                Ok(Some(Command::Listen {
+                    bind: "0.0.0.0:8142".to_owned(),
+                    count: None,
+                    json: false,
+                    metrics: Some("0.0.0.0:9100".to_owned()),
+                    kind: RecordKind::Text,
+                }))
+            );
+        } else {
+            assert!(parse_args(args("listen --bind 0.0.0.0:8142 --metrics 0.0.0.0:9100")).is_err());
+        }
+        assert_eq!(
+            parse_args(args("listen --id64 --bind 0.0.0.0:8142")),
+            Ok(Some(Command::Listen {
+                bind: "0.0.0.0:8142".to_owned(),
+                count: None,
+                json: false,
+                metrics: None,
+                kind: RecordKind::Id64,
+            }))
+        );
+        assert!(parse_args(args(
+            "send --to 127.0.0.1:8142 --db x --metrics 0.0.0.0:9100"
+        ))
+        .is_err());
+        assert!(parse_args(args("send --to 127.0.0.1:8142 --db x --id64 --replay 1")).is_err());
+        assert!(parse_args(args("listen --bind 0.0.0.0:8142 --id64 --id64")).is_err());
+        assert!(parse_args(args("listen --bind 0.0.0.0:8142 --id64 --raw")).is_err());
+        assert!(parse_args(args("send --to 127.0.0.1:8142 --db x --raw --replay 1")).is_err());
+        if cfg!(feature = "compression") {
+            assert!(parse_args(args("listen --bind 0.0.0.0:8142 --compress")).is_ok());
+            assert!(parse_args(args("listen --bind 0.0.0.0:8142 --compress --id64")).is_err());
+        } else {
+            assert!(parse_args(args("listen --bind 0.0.0.0:8142 --compress")).is_err());
+        }
+        assert!(parse_args(args("send --to 127.0.0.1:8142")).is_err());
+        assert!(parse_args(args("listen")).is_err());
+        assert!(parse_args(args("listen --bind 0.0.0.0:8142 --db x")).is_err());
+        assert!(parse_args(args("listen --bind 0.0.0.0:8142 --loop")).is_err());
+        assert!(parse_args(args("listen --bind 0.0.0.0:8142 --replay 1")).is_err());
+        assert!(parse_args(args("send --to 127.0.0.1:8142 --db x --replay 0")).is_err());
+        assert!(parse_args(args("send --to 127.0.0.1:8142 --db x --replay x")).is_err());
+        assert!(parse_args(args("send --to 127.0.0.1:8142 --db x --json")).is_err());
+        assert!(parse_args(args("listen --bind")).is_err());
+        assert!(parse_args(args("listen --bind 0.0.0.0:8142 --count x")).is_err());
+        assert!(parse_args(args("serve --bind 0.0.0.0:8142")).is_err());
+    }
+
+    #[test]
+    fn send() {
+        let db = std::env::temp_dir().join(format!("udptest-cli-{}.sqlite", std::process::id()));
+        let records = Record::generate(5, 20);
+        Record::create_db(&db, &records).unwrap();
+
+        let mut receiver: Receiver<Record> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(1))).unwrap();
+        let line = format!(
+            "send --bind 127.0.0.1:0 --to {} --db {}",
+            receiver.local_addr().unwrap(),
+            db.display()
+        );
+        let command = parse_args(args(&line)).unwrap().unwrap();
+        run(command).unwrap();
+
+        for record in records.iter() {
+            assert_eq!(receiver.next().unwrap().unwrap(), *record);
+        }
+        std::fs::remove_file(&db).unwrap();
+    }
+
+    #[test]
+    fn send_id64() {
+        let db = std::env::temp_dir().join(format!("udptest-cli64-{}.sqlite", std::process::id()));
+        let conn = Connection::open(&db).unwrap();
+        Record::create_table(&conn).unwrap();
+        let records: Vec<Record64> = [u64::MAX, 1 << 40, 1]
+            .iter()
+            .map(|&id| Record64 {
+                id,
+                data: format!("record {}", id),
+            })
+            .collect();
+        for record in records.iter() {
+            record.insert(&conn, OnConflict::Abort).unwrap();
+        }
+        drop(conn);
+
+        let mut receiver: Receiver<Record64> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(1))).unwrap();
+        let line = format!(
+            "send --id64 --to {} --db {}",
+            receiver.local_addr().unwrap(),
+            db.display()
+        );
+        run(parse_args(args(&line)).unwrap().unwrap()).unwrap();
+
+        for record in records.iter().rev() {
+            assert_eq!(receiver.next().unwrap().unwrap(), *record);
+        }
+        std::fs::remove_file(&db).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn send_compressed() {
+        let db = std::env::temp_dir().join(format!("udptest-cliz-{}.sqlite", std::process::id()));
+        let records = Record::generate(3, 200);
+        Record::create_db(&db, &records).unwrap();
+
+        let mut receiver: Receiver<CompressedRecord> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(1))).unwrap();
+        let line = format!(
+            "send --compress --to {} --db {}",
+            receiver.local_addr().unwrap(),
+            db.display()
+        );
+        run(parse_args(args(&line)).unwrap().unwrap()).unwrap();
+
+        for record in records.iter() {
+            assert_eq!(receiver.next().unwrap().unwrap().0, *record);
+        }
+        std::fs::remove_file(&db).unwrap();
+    }
+
+    #[test]
+    fn record_kinds() {
+        let payload = [1, 0, 0, 0, 0xc3, 0x28];
+        assert!(RecordKind::Text.parse(&payload).is_err());
+        assert_eq!(RecordKind::Raw.parse(&payload), Ok((1, "c328".to_owned())));
+        assert_eq!(
+            RecordKind::Id64.parse(&[1, 0, 0, 0, 0, 0, 0, 1, b'a']),
+            Ok((1 << 56 | 1, "a".to_owned()))
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let record = Record {
+            id: 7,
+            data: "say \"hi\"".to_owned(),
+        };
+        let source = "127.0.0.1:8142".parse().unwrap();
+        assert_eq!(
+            record_line(record.id.into(), &record.data, source, 1_600_000_000_000),
+            r#"{"ts":1600000000000,"src":"127.0.0.1:8142","id":7,"data":"say \"hi\""}"#
+        );
+        assert_eq!(
+            parse_error_line(&[1, 0, 0, 0, 0xc3, 0x28]),
+            r#"{"error":"parse","raw":"01000000c328"}"#
+        );
+    }
+
+    #[test]
+    fn send_missing_db() {
+        let command = Command::Send {
+            bind: None,
+            to: "127.0.0.1:9".to_owned(),
+            db: "/nonexistent/dir/records.sqlite".to_owned(),
+            repeat: false,
+            replay: None,
+            kind: RecordKind::Text,
+        };
+        let e = run(command).unwrap_err();
+        assert!(matches!(e, AppError::Io(ref e) if e.kind() == std::io::ErrorKind::NotFound));
+        assert_eq!(
+            e.to_string(),
+            "Database /nonexistent/dir/records.sqlite not found"
+        );
+
+        // Existing directory must not get a database created in it
+        let db =
+            std::env::temp_dir().join(format!("udptest-missing-{}.sqlite", std::process::id()));
+        let command = Command::Send {
+            bind: None,
+            to: "127.0.0.1:9".to_owned(),
+            db: db.display().to_string(),
+            repeat: false,
+            replay: None,
+            kind: RecordKind::Text,
+        };
+        assert!(run(command).is_err());
+        assert!(!db.exists());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fmt, io};
+
+use crate::record::ParseError;
+
+/// Any error workers can run into, so that
+/// they can use `?` regardless of its origin.
+#[derive(Debug)]
+pub enum AppError {
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Io(e) => e.fmt(f),
+            AppError::Sqlite(e) => e.fmt(f),
+            AppError::Parse(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Io(e) => Some(e),
+            AppError::Sqlite(e) => Some(e),
+            AppError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(e: io::Error) -> Self {
+        AppError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        AppError::Sqlite(e)
+    }
+}
+
+impl From<ParseError> for AppError {
+    fn from(e: ParseError) -> Self {
+        AppError::Parse(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::error::AppError;
+    use crate::record::ParseError;
+
+    #[test]
+    fn from() {
+        let e: AppError = io::Error::from(io::ErrorKind::NotFound).into();
+        assert!(matches!(e, AppError::Io(ref e) if e.kind() == io::ErrorKind::NotFound));
+
+        let e: AppError = rusqlite::Error::QueryReturnedNoRows.into();
+        assert!(matches!(
+            e,
+            AppError::Sqlite(rusqlite::Error::QueryReturnedNoRows)
+        ));
+        assert_eq!(e.to_string(), "Query returned no rows");
+
+        let e: AppError = ParseError::Empty.into();
+        assert!(matches!(e, AppError::Parse(ParseError::Empty)));
+        assert_eq!(e.to_string(), "record has no data");
+    }
+
+    #[test]
+    fn question_mark() {
+        let open = || -> Result<(), AppError> {
+            rusqlite::Connection::open_in_memory()?
+                .execute("SELECT nonsense", rusqlite::params![])?;
+            Ok(())
+        };
+        assert!(matches!(open(), Err(AppError::Sqlite(_))));
+    }
+}
@@ -0,0 +1,12 @@
+#![warn(missing_debug_implementations, missing_docs, rust_2018_idioms)]
+//! Types for sending records, or any other data capable of
+//! presenting itself as bytes, through UDP and receiving them
+//! on the other end. The `udptest` app is built on top of them.
+
+/// Data format and DB transactions
+pub mod record;
+/// Async UDP transmission on Tokio
+#[cfg(feature = "async")]
+pub mod tokio;
+/// UDP transmission
+pub mod udp;
@@ -5,11 +5,28 @@
 
 /// GUI and piecing it all together
 mod app;
-/// Data format and DB transactions
-mod record;
-/// UDP transmission
-mod udp;
+/// Headless mode driven by command line arguments
+mod cli;
+/// Errors shared by all parts of the app
+mod error;
+/// Prometheus endpoint for listen mode
+#[cfg(feature = "metrics")]
+mod metrics;
+
+use udptest::{record, udp};
 
 fn main() {
-    app::run();
+    match cli::parse_args(std::env::args().skip(1)) {
+        Ok(None) => app::run(),
+        Ok(Some(command)) => {
+            if let Err(e) = cli::run(command) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    }
 }
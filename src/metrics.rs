@@ -0,0 +1,216 @@
+//! Counters of listen mode served in Prometheus text format
+//! at `/metrics`, for unattended monitoring. HTTP handling is
+//! bare minimum: one request per connection, headers ignored.
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use log::warn;
+
+/// How often server checks whether it should stop.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters updated by listener and read by [MetricsServer].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    received: AtomicU64,
+    corrupted: AtomicU64,
+    /// Size of intact records, headers included.
+    bytes: AtomicU64,
+}
+
+impl Metrics {
+    /// Counts a record that arrived intact, taking up `bytes`.
+    pub fn received(&self, bytes: usize) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a packet that couldn't be parsed.
+    pub fn corrupted(&self) {
+        self.corrupted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Formats counters in Prometheus text format.
+    pub fn render(&self) -> String {
+        let counters = [
+            (
+                "udptest_received_total",
+                "Records received intact.",
+                &self.received,
+            ),
+            (
+                "udptest_corrupted_total",
+                "Packets that couldn't be parsed.",
+                &self.corrupted,
+            ),
+            (
+                "udptest_received_bytes_total",
+                "Size of records received intact.",
+                &self.bytes,
+            ),
+        ];
+        let mut text = String::new();
+        for (name, help, value) in counters.iter() {
+            text += &format!(
+                "# HELP {} {}\n# TYPE {} counter\n{} {}\n",
+                name,
+                help,
+                name,
+                name,
+                value.load(Ordering::Relaxed)
+            );
+        }
+        text
+    }
+}
+
+/// Serves [Metrics] over HTTP on a background thread,
+/// until it's dropped.
+#[derive(Debug)]
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Binds to `addr` and starts serving `metrics`.
+    pub fn start<A: ToSocketAddrs>(addr: A, metrics: Arc<Metrics>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || serve(listener, &metrics, &stop))
+        };
+        Ok(Self {
+            local_addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the server is actually bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers requests one by one until `stop` is set.
+fn serve(listener: TcpListener, metrics: &Metrics, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = respond(stream, metrics) {
+                    warn!(error:% = e; "Couldn't answer metrics request: {}", e);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_INTERVAL),
+            Err(e) => {
+                warn!(error:% = e; "Couldn't accept metrics connection: {}", e);
+                std::thread::sleep(ACCEPT_INTERVAL)
+            }
+        }
+    }
+}
+
+/// Reads a single request and answers it, with metrics
+/// if it asks for `/metrics`, or 404 otherwise.
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    // Accepted stream inherits non-blocking mode on some platforms
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = BufReader::new(&stream);
+    let mut request_line = String::new();
+    request.read_line(&mut request_line)?;
+    // Headers must be read for client to get response intact,
+    // since closing socket with unread data resets connection
+    let mut header = String::new();
+    while request.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "Not found\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::metrics::*;
+
+    /// Sends GET request for `path` and returns the whole response.
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serve_metrics() {
+        let metrics = Arc::new(Metrics::default());
+        let server = MetricsServer::start("127.0.0.1:0", metrics.clone()).unwrap();
+        metrics.received(10);
+        metrics.received(20);
+        metrics.corrupted();
+
+        let response = get(server.local_addr(), "/metrics");
+        let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        let samples: Vec<(&str, &str)> = body
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.split(' ');
+                (parts.next().unwrap(), parts.next().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            [
+                ("udptest_received_total", "2"),
+                ("udptest_corrupted_total", "1"),
+                ("udptest_received_bytes_total", "30"),
+            ]
+        );
+        assert!(body.contains("# TYPE udptest_received_total counter\n"));
+
+        let response = get(server.local_addr(), "/");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
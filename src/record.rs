@@ -1,11 +1,24 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, Read, Write},
+    ops::RangeInclusive,
+    path::Path,
+    task::Poll,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use rusqlite::{self, params, Connection};
+use serde::{Deserialize, Serialize};
 
-use crate::udp::{FromUdp, ToUdp};
+use crate::udp::{self, FromUdp, Receiver, Sender, ToUdp};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 /// Some dummy data.
 pub struct Record {
+    /// Unique identifier of record.
     pub id: u32,
+    /// Payload of record.
     pub data: String,
 }
 
@@ -13,142 +26,2258 @@ pub struct Record {
 /// Represents errors that can occur while
 /// parsing [Record] from bytes.
 pub enum ParseError {
-    /// Got less that 4 bytes.
+    /// Got less bytes than header takes up.
     Incomplete(usize),
-    /// Failed to parse UTF-8 string.
-    Invalid(std::string::FromUtf8Error),
+    /// Failed to parse UTF-8 string, whose first invalid
+    /// byte is at `offset` from the start of buffer.
+    Invalid {
+        /// Position of first invalid byte.
+        offset: usize,
+        /// Why bytes aren't valid UTF-8.
+        source: std::string::FromUtf8Error,
+    },
+    /// Failed to parse JSON, with serde's description of the problem.
+    Json(String),
+    /// Length prefix doesn't match the amount of data that follows.
+    LengthMismatch {
+        /// Length stated by prefix.
+        declared: usize,
+        /// Length of data actually present.
+        actual: usize,
+    },
+    /// Record has no data, which strict parsing rejects.
+    Empty,
+    /// Compressed data couldn't be inflated, or compression
+    /// flag has unknown value.
+    #[cfg(feature = "compression")]
+    Decompress(String),
 }
 
-impl Record {
-    pub fn load(conn: Connection) -> rusqlite::Result<Vec<Self>> {
-        let mut query = conn.prepare("SELECT id, data FROM records")?;
-        let records = query.query_map(params![], |row| {
-            Ok(Record {
-                id: row.get(0)?,
-                data: row.get(1)?,
-            })
-        })?;
-        records.collect()
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Incomplete(len) => {
+                write!(f, "got {} bytes, fewer than header takes up", len)
+            }
+            ParseError::Invalid { source, .. } => write!(f, "data isn't valid UTF-8: {}", source),
+            ParseError::Json(reason) => write!(f, "invalid JSON: {}", reason),
+            ParseError::LengthMismatch { declared, actual } => write!(
+                f,
+                "length prefix says {} bytes, but {} follow",
+                declared, actual
+            ),
+            ParseError::Empty => write!(f, "record has no data"),
+            #[cfg(feature = "compression")]
+            ParseError::Decompress(reason) => write!(f, "can't decompress data: {}", reason),
+        }
     }
 }
 
-impl FromUdp for Record {
-    type Error = ParseError;
+impl ParseError {
+    /// Offset from the start of buffer at which parsing failed,
+    /// if it's known. Buffer ran out for [ParseError::Incomplete].
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            ParseError::Incomplete(len) => Some(*len),
+            ParseError::Invalid { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
 
-    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
-        if buf.len() < 4 {
-            return Err(ParseError::Incomplete(buf.len()));
+    /// Moves position of error `by` bytes further,
+    /// for buffers parsed after a header.
+    fn shifted(self, by: usize) -> Self {
+        match self {
+            ParseError::Incomplete(len) => ParseError::Incomplete(len + by),
+            ParseError::Invalid { offset, source } => ParseError::Invalid {
+                offset: offset + by,
+                source,
+            },
+            e => e,
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Invalid { source, .. } => Some(source),
+            _ => None,
         }
+    }
+}
 
-        let mut id = [0_u8; 4];
-        id.copy_from_slice(&buf[..4]);
-        let id = u32::from_le_bytes(id);
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Version of binary wire format of [Record].
+pub enum Version {
+    /// `[id:4][data]`, with data taking up the rest of datagram.
+    V1,
+    /// `[id:4][len:2][data:len]`, with big-endian length prefix
+    /// making the end of data explicit.
+    V2,
+    /// `[data]` only, for consumers expecting plain text. Parsed
+    /// records get id 0, it's up to receiver to number them.
+    NoId,
+}
 
-        Ok(Self {
-            id,
-            data: String::from_utf8(buf[4..].to_vec()).map_err(|e| ParseError::Invalid(e))?,
+impl Default for Version {
+    fn default() -> Self {
+        Self::V1
+    }
+}
+
+impl Version {
+    /// Number of bytes record id takes up on the wire.
+    pub fn id_len(self) -> usize {
+        match self {
+            Version::V1 | Version::V2 => 4,
+            Version::NoId => 0,
+        }
+    }
+
+    /// Number of bytes that go on the wire before record data.
+    pub fn header_len(self) -> usize {
+        match self {
+            Version::V2 => self.id_len() + 2,
+            Version::V1 | Version::NoId => self.id_len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Byte order of record id on the wire.
+pub enum ByteOrder {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+impl Default for ByteOrder {
+    fn default() -> Self {
+        Self::Little
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// Options of binary wire format of [Record].
+/// Defaults to what [ToUdp] and [FromUdp] implementations use.
+pub struct Format {
+    /// Layout of header preceding data.
+    pub version: Version,
+    /// Byte order of id and other header fields.
+    pub byte_order: ByteOrder,
+}
+
+impl From<Version> for Format {
+    fn from(version: Version) -> Self {
+        Self {
+            version,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<ByteOrder> for Format {
+    fn from(byte_order: ByteOrder) -> Self {
+        Self {
+            byte_order,
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Encodings of [Record] that can be selected at runtime.
+pub enum RecordCodec {
+    /// Binary format of [Version::V1].
+    Binary,
+    /// JSON object, as sent by [JsonRecord].
+    Json,
+    /// Binary format of [Version::V2].
+    LengthPrefixed,
+    /// Bare data of [Version::NoId].
+    Plain,
+}
+
+impl Default for RecordCodec {
+    fn default() -> Self {
+        Self::Binary
+    }
+}
+
+impl RecordCodec {
+    /// Binary format this codec uses with given byte order,
+    /// or `None` if it's not binary.
+    pub fn format(self, byte_order: ByteOrder) -> Option<Format> {
+        let version = match self {
+            RecordCodec::Binary => Version::V1,
+            RecordCodec::LengthPrefixed => Version::V2,
+            RecordCodec::Plain => Version::NoId,
+            RecordCodec::Json => return None,
+        };
+        Some(Format {
+            version,
+            byte_order,
         })
     }
 }
 
-impl ToUdp for Record {
-    fn to_udp(&self) -> Vec<u8> {
-        let id_bytes = self.id.to_le_bytes();
-        let str_bytes = self.data.as_bytes();
-        [&id_bytes, str_bytes].concat()
+#[derive(Debug)]
+/// Represents errors that can occur while
+/// reading [Record]s from CSV.
+pub enum CsvError {
+    /// Reading CSV failed.
+    Io(io::Error),
+    /// Row starting at given line is malformed.
+    Malformed {
+        /// Line row starts at, counting from 1.
+        line: usize,
+        /// What's wrong with row.
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Io(e) => e.fmt(f),
+            CsvError::Malformed { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use rusqlite::{params, Connection};
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> Self {
+        CsvError::Io(e)
+    }
+}
 
-    use crate::record::{ParseError, Record};
-    use crate::udp::FromUdp;
+#[derive(Debug, PartialEq)]
+/// Represents reasons a [Record] can't be sent as is.
+pub enum RecordError {
+    /// Serialized record takes up `len` bytes, more than `max`.
+    TooLong {
+        /// Id of record.
+        id: u32,
+        /// Size of serialized record.
+        len: usize,
+        /// Largest size allowed.
+        max: usize,
+    },
+}
 
-    #[test]
-    fn udp_incomplete() {
-        assert_eq!(Record::from_udp(&[0, 0]), Err(ParseError::Incomplete(2)))
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::TooLong { id, len, max } => write!(
+                f,
+                "record {} takes up {} bytes, at most {} allowed",
+                id, len, max
+            ),
+        }
     }
+}
 
-    #[test]
-    fn udp() {
-        assert_eq!(
-            Record::from_udp(&[1, 0, 0, 0, 'r' as u8]),
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What to do when saving a record whose id is already in DB.
+pub enum OnConflict {
+    /// Overwrite existing record.
+    Replace,
+    /// Keep existing record.
+    Ignore,
+    /// Fail with constraint violation.
+    Abort,
+}
+
+impl Default for OnConflict {
+    fn default() -> Self {
+        Self::Replace
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// Like [Record], but carries arbitrary bytes
+/// instead of a UTF-8 string.
+pub struct RawRecord {
+    /// Unique identifier of record.
+    pub id: u32,
+    /// Payload of record, not necessarily UTF-8.
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// Like [Record], but with a 64-bit id, which goes on the
+/// wire as 8 little-endian bytes.
+pub struct Record64 {
+    /// Unique identifier of record.
+    pub id: u64,
+    /// Payload of record.
+    pub data: String,
+}
+
+#[derive(Debug, PartialEq)]
+/// [Record] stamped with time it was sent at, to measure latency.
+/// Timestamp goes on the wire as 8 bytes right after id,
+/// in the same byte order.
+pub struct TimedRecord {
+    /// Record being sent.
+    pub record: Record,
+    /// Milliseconds since UNIX epoch.
+    pub timestamp: u64,
+}
+
+#[derive(Debug, PartialEq)]
+/// [Record] transmitted as a JSON object like
+/// `{"id":1,"data":"text"}` instead of binary format.
+pub struct JsonRecord(pub Record);
+
+#[derive(Debug, PartialEq)]
+/// [Record] or [TimedRecord] sent in given binary wire format instead of
+/// the default one. Lets [Sender](crate::udp::Sender) serialize it straight
+/// into its buffer, rather than sending a freshly allocated `Vec`.
+pub struct Formatted<T> {
+    /// Item being sent.
+    pub item: T,
+    /// Wire format to serialize item in.
+    pub format: Format,
+}
+
+#[cfg(feature = "compression")]
+#[derive(Debug, PartialEq)]
+/// [Record] whose data is deflated on the wire. A flag byte after
+/// id tells whether it is, as data that doesn't shrink is sent as is.
+pub struct CompressedRecord(pub Record);
+
+/// How many rows [RecordIter] fetches at once.
+const BATCH_SIZE: u32 = 256;
+
+/// Streams records from DB in batches ordered by id,
+/// so that whole table doesn't have to fit in memory.
+#[derive(Debug)]
+pub struct RecordIter {
+    conn: Connection,
+    /// Id of the last fetched record.
+    last_id: i64,
+    /// Largest id to fetch.
+    max_id: i64,
+    batch: VecDeque<Record>,
+    /// Set once table is exhausted or an error occured.
+    done: bool,
+}
+
+impl RecordIter {
+    /// Restricts iterator to records with ids in `range`.
+    pub fn range(mut self, range: RangeInclusive<u32>) -> Self {
+        self.last_id = i64::from(*range.start()) - 1;
+        self.max_id = (*range.end()).into();
+        self
+    }
+
+    fn fetch(&mut self) -> rusqlite::Result<()> {
+        let mut query = self.conn.prepare(
+            "SELECT id, data FROM records WHERE id > ?1 AND id <= ?2 ORDER BY id LIMIT ?3",
+        )?;
+        let records = query.query_map(params![self.last_id, self.max_id, BATCH_SIZE], |row| {
             Ok(Record {
-                id: 1,
-                data: "r".to_owned()
+                id: row.get(0)?,
+                data: row.get(1)?,
             })
-        )
+        })?;
+        for record in records {
+            self.batch.push_back(record?);
+        }
+        Ok(())
     }
+}
 
-    #[test]
-    fn udp_non_utf() {
-        match Record::from_udp(&[1, 0, 0, 0, 0xc3, 0x28]) {
-            Err(ParseError::Invalid(_)) => {}
-            Err(e) => {
-                panic!(e)
-            }
-            Ok(record) => {
-                panic!(
-                    "Incorrectly parsed record from invalid utf-8: {:#?}",
-                    record
-                )
+impl Iterator for RecordIter {
+    type Item = rusqlite::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.batch.is_empty() && !self.done {
+            if let Err(e) = self.fetch() {
+                self.done = true;
+                return Some(Err(e));
             }
+            self.done = self.batch.len() < BATCH_SIZE as usize;
         }
+        let record = self.batch.pop_front()?;
+        self.last_id = record.id.into();
+        Some(Ok(record))
     }
+}
 
-    #[test]
-    fn load() {
-        let conn = Connection::open_in_memory().unwrap();
+/// Quotes SQL identifier, so that it can be safely
+/// interpolated into a query. Uses backticks, since SQLite
+/// treats unknown double-quoted identifiers as string literals.
+fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+impl Record {
+    /// Loads all records from `records` table, ordered by id.
+    pub fn load(conn: Connection) -> rusqlite::Result<Vec<Self>> {
+        Self::load_from(conn, "records", "id", "data")
+    }
+
+    /// Same as [Record::load], but reads `id_col` and `data_col`
+    /// columns of `table` instead of the default ones, ordering by `id_col`.
+    pub fn load_from(
+        conn: Connection,
+        table: &str,
+        id_col: &str,
+        data_col: &str,
+    ) -> rusqlite::Result<Vec<Self>> {
+        let id_col = quote_identifier(id_col);
+        let mut query = conn.prepare(&format!(
+            "SELECT {}, {} FROM {} ORDER BY {}",
+            id_col,
+            quote_identifier(data_col),
+            quote_identifier(table),
+            id_col
+        ))?;
+        let records = query.query_map(params![], |row| {
+            Ok(Record {
+                id: row.get(0)?,
+                data: row.get(1)?,
+            })
+        })?;
+        records.collect()
+    }
 
+    /// Loads at most `limit` records with smallest ids, ordered by id,
+    /// to peek at a large table without reading all of it.
+    pub fn load_limited(conn: Connection, limit: usize) -> rusqlite::Result<Vec<Self>> {
+        // SQLite limits are signed, and negative ones mean no limit
+        let limit = limit.min(i64::MAX as usize) as i64;
+        let mut query = conn.prepare("SELECT id, data FROM records ORDER BY id LIMIT ?1")?;
+        let records = query.query_map(params![limit], |row| {
+            Ok(Record {
+                id: row.get(0)?,
+                data: row.get(1)?,
+            })
+        })?;
+        records.collect()
+    }
+
+    /// Loads records with ids between `min_id` and `max_id` inclusive,
+    /// ordered by id.
+    pub fn load_range(conn: Connection, min_id: u32, max_id: u32) -> rusqlite::Result<Vec<Self>> {
+        Self::load_iter(conn).range(min_id..=max_id).collect()
+    }
+
+    /// Counts records with ids in `range`.
+    pub fn count_range(conn: &Connection, range: RangeInclusive<u32>) -> rusqlite::Result<u32> {
+        conn.query_row(
+            "SELECT COUNT(*) FROM records WHERE id BETWEEN ?1 AND ?2",
+            params![range.start(), range.end()],
+            |row| row.get(0),
+        )
+    }
+
+    /// Creates `records` table [Record::load] reads from,
+    /// unless it already exists.
+    pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
         conn.execute(
-            "CREATE TABLE records (
+            "CREATE TABLE IF NOT EXISTS records (
                        id INTEGER PRIMARY KEY,
                        data TEXT NOT NULL
                 )",
             params![],
-        )
-        .unwrap();
+        )?;
+        Ok(())
+    }
 
-        let mut records: Vec<Record> = vec![
-            (0, "Record"),
-            (u32::MAX, "other"),
-            (42, "ᚻᛖ ᚳᚹᚫᚦ"),
-            (256, "░░▒▒▓▓██"),
-            (1732454, "HTML tags lea͠ki̧n͘g fr̶ǫm ̡yo​͟ur eye͢s̸ ̛l̕ik͏e liq​uid pain"),
-        ]
-        .into_iter()
-        .map(|(id, data)| {
-            let data = data.to_owned();
-            conn.execute("INSERT INTO records VALUES (?1, ?2)", params![&id, &data])
-                .unwrap();
-            Record { id, data }
+    /// Inserts record into `records` table. Returns `false`
+    /// if it was skipped because of [OnConflict::Ignore].
+    pub fn insert(&self, conn: &Connection, on_conflict: OnConflict) -> rusqlite::Result<bool> {
+        let query = match on_conflict {
+            OnConflict::Replace => "INSERT OR REPLACE INTO records (id, data) VALUES (?1, ?2)",
+            OnConflict::Ignore => "INSERT OR IGNORE INTO records (id, data) VALUES (?1, ?2)",
+            OnConflict::Abort => "INSERT INTO records (id, data) VALUES (?1, ?2)",
+        };
+        let inserted = conn
+            .prepare_cached(query)?
+            .execute(params![self.id, self.data])?;
+        Ok(inserted > 0)
+    }
+
+    /// Inserts records into `records` table, creating it if needed.
+    /// Either all records get saved, or none if an error occurs.
+    pub fn save(
+        conn: &mut Connection,
+        records: &[Record],
+        on_conflict: OnConflict,
+    ) -> rusqlite::Result<()> {
+        let tx = conn.transaction()?;
+        Self::create_table(&tx)?;
+        for record in records {
+            record.insert(&tx, on_conflict)?;
+        }
+        tx.commit()
+    }
+
+    /// Saves records to database at `path`, creating it if needed.
+    /// Existing `records` table is replaced, while any other
+    /// tables are left alone. Either all records get saved, or
+    /// database is left unchanged if an error occurs.
+    pub fn create_db<P: AsRef<Path>>(path: P, records: &[Record]) -> rusqlite::Result<()> {
+        let mut conn = Connection::open(path)?;
+        let tx = conn.transaction()?;
+        tx.execute("DROP TABLE IF EXISTS records", params![])?;
+        Self::create_table(&tx)?;
+        for record in records {
+            record.insert(&tx, OnConflict::Abort)?;
+        }
+        tx.commit()
+    }
+
+    /// Makes up `count` records with ids from 0 and `data_len` ASCII
+    /// letters of data each, shifted by id so that records differ.
+    /// Useful for load testing without a database at hand.
+    pub fn generate(count: usize, data_len: usize) -> Vec<Self> {
+        (0..count)
+            .map(|id| Record {
+                id: id as u32,
+                data: (0..data_len)
+                    .map(|i| (b'a' + ((id + i) % 26) as u8) as char)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Reads every line of `reader` as data of a record,
+    /// numbering records from 0 in order they appear in.
+    pub fn load_lines<R: BufRead>(reader: R) -> io::Result<Vec<Self>> {
+        reader
+            .lines()
+            .enumerate()
+            .map(|(id, line)| {
+                Ok(Record {
+                    id: id as u32,
+                    data: line?,
+                })
+            })
+            .collect()
+    }
+
+    /// Reads records from CSV with `id,data` rows, in order they
+    /// appear in. Header row with these names is optional.
+    pub fn load_csv<R: Read>(mut reader: R) -> Result<Vec<Self>, CsvError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        let mut rows = parse_csv(&text)?.into_iter().peekable();
+        if let Some((_, header)) = rows.peek() {
+            if header.len() == 2 && header[0].trim() == "id" && header[1].trim() == "data" {
+                rows.next();
+            }
+        }
+
+        rows.map(|(line, mut row)| {
+            if row.len() != 2 {
+                return Err(CsvError::Malformed {
+                    line,
+                    reason: "expected 2 fields",
+                });
+            }
+            let data = row.pop().unwrap();
+            let id = row[0].trim().parse().map_err(|_| CsvError::Malformed {
+                line,
+                reason: "invalid id",
+            })?;
+            Ok(Record { id, data })
         })
-        .collect();
-        records.sort_by_key(|r| r.id);
-        let mut loaded = Record::load(conn).unwrap();
-        loaded.sort_by_key(|r| r.id);
+        .collect()
+    }
 
-        assert_eq!(loaded, records);
+    /// Writes records as CSV with `id,data` header,
+    /// readable by [Record::load_csv].
+    pub fn write_csv<W: Write>(records: &[Record], mut writer: W) -> io::Result<()> {
+        writeln!(writer, "id,data")?;
+        for record in records {
+            writeln!(writer, "{},{}", record.id, quote_csv(&record.data))?;
+        }
+        writer.flush()
     }
 
-    #[test]
-    fn load_no_table() {
-        let conn = Connection::open_in_memory().unwrap();
-        assert!(Record::load(conn).is_err());
+    /// Lazy version of [Record::load], yielding records ordered by id.
+    pub fn load_iter(conn: Connection) -> RecordIter {
+        RecordIter {
+            conn,
+            last_id: i64::MIN,
+            max_id: i64::MAX,
+            batch: VecDeque::new(),
+            done: false,
+        }
     }
+}
 
-    #[test]
-    fn load_no_fields() {
-        let conn = Connection::open_in_memory().unwrap();
-        conn.execute(
-            "CREATE TABLE records (
-                       id INTEGER PRIMARY KEY
-                )",
-            params![],
-        )
-        .unwrap();
-        assert!(Record::load(conn).is_err());
+/// Splits CSV text into rows of fields, as described in RFC 4180,
+/// along with line each row starts at. Blank lines are skipped.
+fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, CsvError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    // Whether current field started with a quote, and whether we're still inside it
+    let (mut quoted, mut in_quotes) = (false, false);
+    let (mut line, mut row_line) = (1, 1);
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                in_quotes = true;
+            }
+            ',' => {
+                row.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                if quoted || !field.is_empty() || !row.is_empty() {
+                    row.push(std::mem::take(&mut field));
+                    rows.push((row_line, std::mem::take(&mut row)));
+                }
+                quoted = false;
+                line += 1;
+                row_line = line;
+            }
+            '"' => {
+                return Err(CsvError::Malformed {
+                    line,
+                    reason: "quote inside unquoted field",
+                })
+            }
+            _ if quoted => {
+                return Err(CsvError::Malformed {
+                    line,
+                    reason: "text after closing quote",
+                })
+            }
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(CsvError::Malformed {
+            line: row_line,
+            reason: "unterminated quote",
+        });
+    }
+    if quoted || !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push((row_line, row));
+    }
+    Ok(rows)
+}
+
+/// Quotes CSV field if it contains separators or quotes.
+fn quote_csv(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Current time in milliseconds since UNIX epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Splits buffer into id and the rest of data.
+fn split_id(buf: &[u8], order: ByteOrder) -> Result<(u32, &[u8]), ParseError> {
+    if buf.len() < 4 {
+        return Err(ParseError::Incomplete(buf.len()));
+    }
+
+    let mut id = [0_u8; 4];
+    id.copy_from_slice(&buf[..4]);
+    let id = match order {
+        ByteOrder::Little => u32::from_le_bytes(id),
+        ByteOrder::Big => u32::from_be_bytes(id),
+    };
+    Ok((id, &buf[4..]))
+}
+
+/// Parses UTF-8 data that follows `header_len` bytes of header.
+fn parse_data(data: &[u8], header_len: usize) -> Result<String, ParseError> {
+    String::from_utf8(data.to_vec()).map_err(|source| ParseError::Invalid {
+        offset: header_len + source.utf8_error().valid_up_to(),
+        source,
+    })
+}
+
+/// Length of [Version::V2] length prefix.
+const LENGTH_PREFIX_LEN: usize = 2;
+
+/// Splits off [Version::V2] length prefix, checking that
+/// it matches the length of the rest of buffer.
+fn split_length(buf: &[u8]) -> Result<&[u8], ParseError> {
+    if buf.len() < LENGTH_PREFIX_LEN {
+        return Err(ParseError::Incomplete(4 + buf.len()));
+    }
+    let (len, data) = buf.split_at(LENGTH_PREFIX_LEN);
+    let declared = u16::from_be_bytes([len[0], len[1]]) as usize;
+    if declared != data.len() {
+        return Err(ParseError::LengthMismatch {
+            declared,
+            actual: data.len(),
+        });
+    }
+    Ok(data)
+}
+
+impl Record {
+    /// Serializes record using given wire format, or any part of it,
+    /// such as [Version] or [ByteOrder]. With [Version::V2], data longer
+    /// than 65535 bytes is truncated on a character boundary.
+    pub fn to_udp_with<F: Into<Format>>(&self, format: F) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.to_udp_into_with(&mut buf, format);
+        buf
+    }
+
+    /// Same as [Record::to_udp_with], appending to `buf`.
+    pub fn to_udp_into_with<F: Into<Format>>(&self, buf: &mut Vec<u8>, format: F) {
+        let format = format.into();
+        let id_bytes = match format.byte_order {
+            ByteOrder::Little => self.id.to_le_bytes(),
+            ByteOrder::Big => self.id.to_be_bytes(),
+        };
+        let str_bytes = self.data.as_bytes();
+        buf.extend_from_slice(&id_bytes[..format.version.id_len()]);
+        match format.version {
+            Version::V1 | Version::NoId => buf.extend_from_slice(str_bytes),
+            Version::V2 => {
+                let mut len = str_bytes.len().min(u16::MAX as usize);
+                while !self.data.is_char_boundary(len) {
+                    len -= 1;
+                }
+                buf.extend_from_slice(&(len as u16).to_be_bytes());
+                buf.extend_from_slice(&str_bytes[..len]);
+            }
+        }
+    }
+
+    /// Parses record encoded with given wire format.
+    pub fn from_udp_with<F: Into<Format>>(buf: &[u8], format: F) -> Result<Self, ParseError> {
+        let format = format.into();
+        let (id, data) = match format.version {
+            Version::V1 => split_id(buf, format.byte_order)?,
+            Version::V2 => {
+                let (id, buf) = split_id(buf, format.byte_order)?;
+                (id, split_length(buf)?)
+            }
+            Version::NoId => (0, buf),
+        };
+
+        Ok(Self {
+            id,
+            data: parse_data(data, buf.len() - data.len())?,
+        })
+    }
+
+    /// Parses record encoded with given codec. `byte_order`
+    /// only applies to binary codecs.
+    pub fn from_udp_with_codec(
+        buf: &[u8],
+        codec: RecordCodec,
+        byte_order: ByteOrder,
+    ) -> Result<Self, ParseError> {
+        match codec.format(byte_order) {
+            Some(format) => Self::from_udp_with(buf, format),
+            None => JsonRecord::from_udp(buf).map(|json| json.0),
+        }
+    }
+
+    /// Same as [Record::from_udp_with], but rejects records with empty
+    /// data. With [Version::V1], a lone id can't be told apart from
+    /// a larger record truncated right after it.
+    pub fn from_udp_strict_with<F: Into<Format>>(
+        buf: &[u8],
+        format: F,
+    ) -> Result<Self, ParseError> {
+        let record = Self::from_udp_with(buf, format)?;
+        if record.data.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        Ok(record)
+    }
+
+    /// Checks that record serialized with `version`
+    /// fits into `max_payload` bytes.
+    pub fn validate(&self, max_payload: usize, version: Version) -> Result<(), RecordError> {
+        let len = version.header_len() + self.data.len();
+        if len > max_payload {
+            return Err(RecordError::TooLong {
+                id: self.id,
+                len,
+                max: max_payload,
+            });
+        }
+        Ok(())
+    }
+
+    /// Shortens data on a character boundary, so that record serialized
+    /// with `version` fits into `max_payload` bytes, unlike truncation
+    /// done by [Sender](crate::udp::Sender), which may split a character.
+    /// Returns whether anything was cut off.
+    pub fn truncate(&mut self, max_payload: usize, version: Version) -> bool {
+        if self.validate(max_payload, version).is_ok() {
+            return false;
+        }
+        let mut len = max_payload.saturating_sub(version.header_len());
+        while !self.data.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.data.truncate(len);
+        true
+    }
+
+    /// Same as [Record::from_udp_strict_with], using default format.
+    pub fn from_udp_strict(buf: &[u8]) -> Result<Self, ParseError> {
+        Self::from_udp_strict_with(buf, Format::default())
+    }
+}
+
+impl FromUdp for Record {
+    type Error = ParseError;
+
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_udp_with(buf, Format::default())
+    }
+}
+
+impl ToUdp for Record {
+    fn to_udp(&self) -> Vec<u8> {
+        self.to_udp_with(Format::default())
+    }
+
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        self.to_udp_into_with(buf, Format::default())
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        Some(self.id.into())
+    }
+}
+
+impl RawRecord {
+    /// Loads records from `records` table, whose `data`
+    /// column holds BLOBs, ordered by id.
+    pub fn load(conn: Connection) -> rusqlite::Result<Vec<Self>> {
+        let mut query = conn.prepare("SELECT id, data FROM records ORDER BY id")?;
+        let records = query.query_map(params![], |row| {
+            Ok(RawRecord {
+                id: row.get(0)?,
+                data: row.get(1)?,
+            })
+        })?;
+        records.collect()
+    }
+}
+
+impl FromUdp for RawRecord {
+    type Error = ParseError;
+
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        let (id, data) = split_id(buf, ByteOrder::Little)?;
+        Ok(Self {
+            id,
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl ToUdp for RawRecord {
+    fn to_udp(&self) -> Vec<u8> {
+        [&self.id.to_le_bytes()[..], &self.data].concat()
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        Some(self.id.into())
+    }
+}
+
+/// Length of [Record64] id.
+const ID64_LEN: usize = 8;
+
+impl Record64 {
+    /// Loads records from `records` table. SQLite integers are signed,
+    /// so ids above [i64::MAX] are stored as negative numbers, which
+    /// [Record64::insert] takes care of. Records come ordered by id,
+    /// with those negative ones last.
+    pub fn load(conn: Connection) -> rusqlite::Result<Vec<Self>> {
+        let mut query = conn.prepare("SELECT id, data FROM records ORDER BY id < 0, id")?;
+        let records = query.query_map(params![], |row| {
+            Ok(Record64 {
+                id: row.get::<_, i64>(0)? as u64,
+                data: row.get(1)?,
+            })
+        })?;
+        records.collect()
+    }
+
+    /// Same as [Record::insert], table is created with [Record::create_table].
+    pub fn insert(&self, conn: &Connection, on_conflict: OnConflict) -> rusqlite::Result<bool> {
+        let query = match on_conflict {
+            OnConflict::Replace => "INSERT OR REPLACE INTO records (id, data) VALUES (?1, ?2)",
+            OnConflict::Ignore => "INSERT OR IGNORE INTO records (id, data) VALUES (?1, ?2)",
+            OnConflict::Abort => "INSERT INTO records (id, data) VALUES (?1, ?2)",
+        };
+        let inserted = conn
+            .prepare_cached(query)?
+            .execute(params![self.id as i64, self.data])?;
+        Ok(inserted > 0)
+    }
+}
+
+impl FromUdp for Record64 {
+    type Error = ParseError;
+
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        if buf.len() < ID64_LEN {
+            return Err(ParseError::Incomplete(buf.len()));
+        }
+        let mut id = [0_u8; ID64_LEN];
+        id.copy_from_slice(&buf[..ID64_LEN]);
+        Ok(Self {
+            id: u64::from_le_bytes(id),
+            data: parse_data(&buf[ID64_LEN..], ID64_LEN)?,
+        })
+    }
+}
+
+impl ToUdp for Record64 {
+    fn to_udp(&self) -> Vec<u8> {
+        [&self.id.to_le_bytes()[..], self.data.as_bytes()].concat()
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        Some(self.id)
+    }
+}
+
+/// Length of [TimedRecord] timestamp.
+pub const TIMESTAMP_LEN: usize = 8;
+
+impl TimedRecord {
+    /// Stamps record with current time.
+    pub fn now(record: Record) -> Self {
+        Self {
+            record,
+            timestamp: now_millis(),
+        }
+    }
+
+    /// Loads records from `records` table ordered by time, taking
+    /// timestamps from optional `ts` column. Records without one are
+    /// stamped with current time, so they come last.
+    pub fn load(conn: Connection) -> rusqlite::Result<Vec<Self>> {
+        let has_ts = conn.prepare("SELECT ts FROM records LIMIT 0").is_ok();
+        let mut query = conn.prepare(if has_ts {
+            "SELECT id, data, ts FROM records ORDER BY ts IS NULL, ts, id"
+        } else {
+            "SELECT id, data, NULL FROM records ORDER BY id"
+        })?;
+        let now = now_millis();
+        let records = query.query_map(params![], |row| {
+            let timestamp: Option<i64> = row.get(2)?;
+            Ok(TimedRecord {
+                record: Record {
+                    id: row.get(0)?,
+                    data: row.get(1)?,
+                },
+                timestamp: timestamp.map_or(now, |ts| ts.max(0) as u64),
+            })
+        })?;
+        records.collect()
+    }
+
+    /// Same as [Record::to_udp_with], with timestamp inserted after id.
+    pub fn to_udp_with<F: Into<Format>>(&self, format: F) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.to_udp_into_with(&mut buf, format);
+        buf
+    }
+
+    /// Same as [TimedRecord::to_udp_with], appending to `buf`.
+    pub fn to_udp_into_with<F: Into<Format>>(&self, buf: &mut Vec<u8>, format: F) {
+        let format = format.into();
+        let ts_bytes = match format.byte_order {
+            ByteOrder::Little => self.timestamp.to_le_bytes(),
+            ByteOrder::Big => self.timestamp.to_be_bytes(),
+        };
+        let ts_start = buf.len() + format.version.id_len();
+        self.record.to_udp_into_with(buf, format);
+        buf.splice(ts_start..ts_start, ts_bytes.iter().copied());
+    }
+
+    /// Parses record encoded with given wire format.
+    pub fn from_udp_with<F: Into<Format>>(buf: &[u8], format: F) -> Result<Self, ParseError> {
+        let format = format.into();
+        let id_len = format.version.id_len();
+        if buf.len() < id_len + TIMESTAMP_LEN {
+            return Err(ParseError::Incomplete(buf.len()));
+        }
+
+        let mut ts = [0_u8; TIMESTAMP_LEN];
+        ts.copy_from_slice(&buf[id_len..id_len + TIMESTAMP_LEN]);
+        let timestamp = match format.byte_order {
+            ByteOrder::Little => u64::from_le_bytes(ts),
+            ByteOrder::Big => u64::from_be_bytes(ts),
+        };
+        let rest = [&buf[..id_len], &buf[id_len + TIMESTAMP_LEN..]].concat();
+        // Positions past id are off by timestamp we cut out
+        let record = Record::from_udp_with(&rest, format).map_err(|e| match e.position() {
+            Some(position) if position >= id_len => e.shifted(TIMESTAMP_LEN),
+            _ => e,
+        })?;
+        Ok(Self { record, timestamp })
+    }
+}
+
+impl FromUdp for TimedRecord {
+    type Error = ParseError;
+
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_udp_with(buf, Format::default())
+    }
+}
+
+impl ToUdp for TimedRecord {
+    fn to_udp(&self) -> Vec<u8> {
+        self.to_udp_with(Format::default())
+    }
+
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        self.to_udp_into_with(buf, Format::default())
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        self.record.item_id()
+    }
+}
+
+impl ToUdp for Formatted<Record> {
+    fn to_udp(&self) -> Vec<u8> {
+        self.item.to_udp_with(self.format)
+    }
+
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        self.item.to_udp_into_with(buf, self.format)
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        self.item.item_id()
+    }
+}
+
+impl ToUdp for Formatted<TimedRecord> {
+    fn to_udp(&self) -> Vec<u8> {
+        self.item.to_udp_with(self.format)
+    }
+
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        self.item.to_udp_into_with(buf, self.format)
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        self.item.item_id()
+    }
+}
+
+impl FromUdp for JsonRecord {
+    type Error = ParseError;
+
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        serde_json::from_slice(buf)
+            .map(JsonRecord)
+            .map_err(|e| ParseError::Json(e.to_string()))
+    }
+}
+
+impl ToUdp for JsonRecord {
+    fn to_udp(&self) -> Vec<u8> {
+        serde_json::to_vec(&self.0).expect("Record is always serializable")
+    }
+
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        serde_json::to_writer(buf, &self.0).expect("Record is always serializable")
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        self.0.item_id()
+    }
+}
+
+/// [CompressedRecord] flag for data sent as is.
+#[cfg(feature = "compression")]
+const FLAG_RAW: u8 = 0;
+/// [CompressedRecord] flag for deflated data.
+#[cfg(feature = "compression")]
+const FLAG_DEFLATED: u8 = 1;
+/// Most bytes [CompressedRecord] data is allowed to inflate to,
+/// so that a small datagram can't exhaust memory.
+#[cfg(feature = "compression")]
+const MAX_INFLATED_LEN: usize = 16 * 1024 * 1024;
+
+#[cfg(feature = "compression")]
+impl FromUdp for CompressedRecord {
+    type Error = ParseError;
+
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        let (id, rest) = split_id(buf, ByteOrder::Little)?;
+        let (flag, data) = match rest.split_first() {
+            Some((flag, data)) => (*flag, data),
+            None => return Err(ParseError::Incomplete(buf.len())),
+        };
+        let data = match flag {
+            FLAG_RAW => parse_data(data, 5)?,
+            FLAG_DEFLATED => {
+                let inflated =
+                    miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_INFLATED_LEN)
+                        .map_err(|status| ParseError::Decompress(format!("{:?}", status)))?;
+                // Offsets within inflated data mean nothing on the wire
+                String::from_utf8(inflated)
+                    .map_err(|e| ParseError::Decompress(e.utf8_error().to_string()))?
+            }
+            flag => return Err(ParseError::Decompress(format!("unknown flag {}", flag))),
+        };
+        Ok(Self(Record { id, data }))
+    }
+}
+
+#[cfg(feature = "compression")]
+impl ToUdp for CompressedRecord {
+    fn to_udp(&self) -> Vec<u8> {
+        let data = self.0.data.as_bytes();
+        let deflated = miniz_oxide::deflate::compress_to_vec(data, 6);
+        let (flag, data) = if deflated.len() < data.len() {
+            (FLAG_DEFLATED, &deflated[..])
+        } else {
+            (FLAG_RAW, data)
+        };
+        [&self.0.id.to_le_bytes()[..], &[flag], data].concat()
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        self.0.item_id()
+    }
+}
+
+/// Number of records exchanged by [self_test].
+const SELF_TEST_RECORDS: u32 = 16;
+
+/// Outcome of [self_test].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SelfTestReport {
+    /// Number of records sent.
+    pub sent: usize,
+    /// Number of records that came back intact.
+    pub received: usize,
+    /// Number of records that came back different from what was sent.
+    pub corrupted: usize,
+}
+
+impl SelfTestReport {
+    /// Whether every record came back intact.
+    pub fn passed(&self) -> bool {
+        self.received == self.sent
+    }
+}
+
+/// Sends a few synthetic records over loopback to a [Receiver]
+/// bound on a free port, and checks what comes back.
+/// Records that don't arrive before the default timeout count as lost.
+pub fn self_test() -> Result<SelfTestReport, udp::Error<ParseError>> {
+    let mut receiver = Receiver::<Record>::new("127.0.0.1:0")?;
+    let dest = receiver.local_addr()?;
+    let mut sender = Sender::new("127.0.0.1:0")?;
+
+    let records: Vec<_> = (0..SELF_TEST_RECORDS)
+        .map(|id| Record {
+            id,
+            data: format!("Self test record #{}", id),
+        })
+        .collect();
+    let sent = sender.send(records.iter(), dest)?;
+
+    let mut report = SelfTestReport {
+        sent: sent.items_sent,
+        ..SelfTestReport::default()
+    };
+    for expected in &records {
+        match receiver.try_next() {
+            Poll::Ready(Ok(record)) if record == *expected => report.received += 1,
+            Poll::Ready(Ok(_)) | Poll::Ready(Err(udp::Error::ParseError { .. })) => {
+                report.corrupted += 1
+            }
+            Poll::Ready(Err(e)) => return Err(e),
+            Poll::Pending => break,
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{params, Connection};
+
+    use crate::record::{
+        now_millis, self_test, ByteOrder, CsvError, Format, Formatted, JsonRecord, OnConflict,
+        ParseError, RawRecord, Record, Record64, RecordCodec, RecordError, TimedRecord, Version,
+        SELF_TEST_RECORDS,
+    };
+    use crate::udp::{FromUdp, ToUdp};
+
+    #[test]
+    fn udp_incomplete() {
+        assert_eq!(Record::from_udp(&[0, 0]), Err(ParseError::Incomplete(2)))
+    }
+
+    #[test]
+    fn parse_error_display() {
+        use std::error::Error;
+
+        let invalid = Record::from_udp(&[0, 0, 0, 0, 0xff]).unwrap_err();
+        assert_eq!(
+            invalid.to_string(),
+            "data isn't valid UTF-8: invalid utf-8 sequence of 1 bytes from index 0"
+        );
+        assert!(invalid.source().is_some());
+
+        let cases = [
+            (
+                ParseError::Incomplete(3),
+                "got 3 bytes, fewer than header takes up",
+            ),
+            (
+                ParseError::Json("EOF while parsing".to_owned()),
+                "invalid JSON: EOF while parsing",
+            ),
+            (
+                ParseError::LengthMismatch {
+                    declared: 5,
+                    actual: 2,
+                },
+                "length prefix says 5 bytes, but 2 follow",
+            ),
+            (ParseError::Empty, "record has no data"),
+        ];
+        for (e, text) in cases.iter() {
+            assert_eq!(e.to_string(), *text);
+            assert!(e.source().is_none());
+        }
+    }
+
+    #[test]
+    fn udp_empty() {
+        let empty = Record {
+            id: 3,
+            data: String::new(),
+        };
+        assert_eq!(Record::from_udp(&[3, 0, 0, 0]), Ok(empty));
+        assert_eq!(Record::from_udp(&[3, 0, 0]), Err(ParseError::Incomplete(3)));
+
+        assert_eq!(
+            Record::from_udp_strict(&[3, 0, 0, 0]),
+            Err(ParseError::Empty)
+        );
+        assert_eq!(
+            Record::from_udp_strict(&[3, 0, 0]),
+            Err(ParseError::Incomplete(3))
+        );
+        assert_eq!(
+            Record::from_udp_strict_with(&[3, 0, 0, 0, 0, 0], Version::V2),
+            Err(ParseError::Empty)
+        );
+        assert_eq!(
+            Record::from_udp_strict(&[3, 0, 0, 0, b'x']).map(|record| record.data),
+            Ok("x".to_owned())
+        );
+    }
+
+    #[test]
+    fn udp() {
+        assert_eq!(
+            Record::from_udp(&[1, 0, 0, 0, b'r']),
+            Ok(Record {
+                id: 1,
+                data: "r".to_owned()
+            })
+        )
+    }
+
+    #[test]
+    fn udp_non_utf() {
+        match Record::from_udp(&[1, 0, 0, 0, 0xc3, 0x28]) {
+            Err(ParseError::Invalid { offset: 4, .. }) => {}
+            Err(e) => {
+                panic!(e)
+            }
+            Ok(record) => {
+                panic!(
+                    "Incorrectly parsed record from invalid utf-8: {:#?}",
+                    record
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn load() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute(
+            "CREATE TABLE records (
+                       id INTEGER PRIMARY KEY,
+                       data TEXT NOT NULL
+                )",
+            params![],
+        )
+        .unwrap();
+
+        let mut records: Vec<Record> = vec![
+            (0, "Record"),
+            (u32::MAX, "other"),
+            (42, "ᚻᛖ ᚳᚹᚫᚦ"),
+            (256, "░░▒▒▓▓██"),
+            (1732454, "HTML tags lea͠ki̧n͘g fr̶ǫm ̡yo​͟ur eye͢s̸ ̛l̕ik͏e liq​uid pain"),
+        ]
+        .into_iter()
+        .map(|(id, data)| {
+            let data = data.to_owned();
+            conn.execute("INSERT INTO records VALUES (?1, ?2)", params![&id, &data])
+                .unwrap();
+            Record { id, data }
+        })
+        .collect();
+        records.sort_by_key(|r| r.id);
+
+        assert_eq!(Record::load(conn).unwrap(), records);
+    }
+
+    #[test]
+    fn load_no_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert!(Record::load(conn).is_err());
+    }
+
+    #[test]
+    fn load_no_fields() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE records (
+                       id INTEGER PRIMARY KEY
+                )",
+            params![],
+        )
+        .unwrap();
+        assert!(Record::load(conn).is_err());
+    }
+
+    #[test]
+    fn insert() {
+        let conn = Connection::open_in_memory().unwrap();
+        Record::create_table(&conn).unwrap();
+        // Existing table is left alone
+        Record::create_table(&conn).unwrap();
+
+        let record = |data: &str| Record {
+            id: 1,
+            data: data.to_owned(),
+        };
+        assert_eq!(record("a").insert(&conn, OnConflict::Abort), Ok(true));
+        assert!(record("b").insert(&conn, OnConflict::Abort).is_err());
+        assert_eq!(record("c").insert(&conn, OnConflict::Ignore), Ok(false));
+        assert_eq!(Record::load_range(conn, 0, 10).unwrap(), vec![record("a")]);
+
+        let conn = Connection::open_in_memory().unwrap();
+        Record::create_table(&conn).unwrap();
+        assert_eq!(record("a").insert(&conn, OnConflict::Replace), Ok(true));
+        assert_eq!(record("d").insert(&conn, OnConflict::Replace), Ok(true));
+        assert_eq!(Record::load(conn).unwrap(), vec![record("d")]);
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let records: Vec<Record> = (0..10)
+            .map(|id| Record {
+                id,
+                data: format!("record {}", id),
+            })
+            .collect();
+        let mut conn = Connection::open_in_memory().unwrap();
+        Record::save(&mut conn, &records, OnConflict::default()).unwrap();
+        let loaded = Record::load_range(conn, 0, u32::MAX).unwrap();
+        assert_eq!(loaded, records);
+
+        let mut copy = Connection::open_in_memory().unwrap();
+        Record::save(&mut copy, &loaded, OnConflict::default()).unwrap();
+        assert_eq!(Record::load_range(copy, 0, u32::MAX).unwrap(), records);
+
+        // Failed save leaves no partial data behind
+        let mut conn = Connection::open_in_memory().unwrap();
+        Record::create_table(&conn).unwrap();
+        let duplicated = [loaded[0].clone(), loaded[1].clone(), loaded[0].clone()];
+        assert!(Record::save(&mut conn, &duplicated, OnConflict::Abort).is_err());
+        assert_eq!(Record::count_range(&conn, 0..=u32::MAX), Ok(0));
+    }
+
+    #[test]
+    fn csv() {
+        let records = vec![
+            Record {
+                id: 1,
+                data: "plain".to_owned(),
+            },
+            Record {
+                id: 2,
+                data: "commas, \"quotes\", and\nnewlines\r\n".to_owned(),
+            },
+            Record {
+                id: 3,
+                data: String::new(),
+            },
+            Record {
+                id: u32::MAX,
+                data: "\"".to_owned(),
+            },
+        ];
+        let mut csv = Vec::new();
+        Record::write_csv(&records, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.clone()).unwrap(),
+            "id,data\n1,plain\n2,\"commas, \"\"quotes\"\", and\nnewlines\r\n\"\n3,\n4294967295,\"\"\"\"\n"
+        );
+        assert_eq!(Record::load_csv(&csv[..]).unwrap(), records);
+    }
+
+    #[test]
+    fn csv_lenient() {
+        // No header, CRLF line endings, blank lines and no trailing newline
+        let csv = "1,one\r\n\r\n2,\"t,wo\"\r\n 3 ,three";
+        let ids: Vec<(u32, String)> = Record::load_csv(csv.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.id, r.data))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                (1, "one".to_owned()),
+                (2, "t,wo".to_owned()),
+                (3, "three".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_malformed() {
+        for (csv, line, reason) in [
+            ("id,data\n1,a\n2\n", 3, "expected 2 fields"),
+            ("1,a,b\n", 1, "expected 2 fields"),
+            ("-1,a\n", 1, "invalid id"),
+            ("1,\"a\nb\n2,c\n", 1, "unterminated quote"),
+            ("1,\"a\nb\"x\n", 2, "text after closing quote"),
+            ("1,a\"b\n", 1, "quote inside unquoted field"),
+        ]
+        .iter()
+        {
+            match Record::load_csv(csv.as_bytes()) {
+                Err(CsvError::Malformed {
+                    line: got_line,
+                    reason: got_reason,
+                }) => assert_eq!((got_line, got_reason), (*line, *reason), "{:?}", csv),
+                other => panic!("Expected error for {:?}, got {:?}", csv, other),
+            }
+        }
+        assert!(matches!(
+            Record::load_csv(&[b'1', b',', 0xc3, 0x28][..]),
+            Err(CsvError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn load_iter() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE records (
+                       id INTEGER PRIMARY KEY,
+                       data TEXT NOT NULL
+                )",
+            params![],
+        )
+        .unwrap();
+        // Insert in reverse to make sure we don't rely on insertion order
+        for id in (0..1000_u32).rev() {
+            conn.execute(
+                "INSERT INTO records VALUES (?1, ?2)",
+                params![&id, &id.to_string()],
+            )
+            .unwrap();
+        }
+
+        let loaded: Vec<Record> = Record::load_iter(conn).map(Result::unwrap).collect();
+        assert_eq!(loaded.len(), 1000);
+        for (i, record) in loaded.iter().enumerate() {
+            assert_eq!(record.id, i as u32);
+            assert_eq!(record.data, i.to_string());
+        }
+    }
+
+    #[test]
+    fn load_iter_no_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut records = Record::load_iter(conn);
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn load_from() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE packets (
+                       seq INTEGER PRIMARY KEY,
+                       payload TEXT NOT NULL
+                )",
+            params![],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO packets VALUES (7, 'seven')", params![])
+            .unwrap();
+
+        assert_eq!(
+            Record::load_from(conn, "packets", "seq", "payload").unwrap(),
+            vec![Record {
+                id: 7,
+                data: "seven".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn load_from_ordered() {
+        // Without a primary key, rows are stored in insertion order
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE log (n INTEGER, line TEXT)", params![])
+            .unwrap();
+        for n in [3, 1, 2].iter() {
+            conn.execute("INSERT INTO log VALUES (?1, 'line')", params![n])
+                .unwrap();
+        }
+
+        let ids: Vec<u32> = Record::load_from(conn, "log", "n", "line")
+            .unwrap()
+            .iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn load_from_quoted() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE `we\"i``rd` (id INTEGER, data TEXT)",
+            params![],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO `we\"i``rd` VALUES (1, 'one')", params![])
+            .unwrap();
+        assert_eq!(
+            Record::load_from(conn, "we\"i`rd", "id", "data").unwrap(),
+            vec![Record {
+                id: 1,
+                data: "one".to_owned()
+            }]
+        );
+
+        // Quote ends up inside identifier instead of terminating it
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE records (id INTEGER, data TEXT)", params![])
+            .unwrap();
+        assert!(Record::load_from(conn, "records` --", "id", "data").is_err());
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE records (id INTEGER, data TEXT)", params![])
+            .unwrap();
+        assert!(Record::load_from(conn, "records", "id", "1 AS id, data").is_err());
+    }
+
+    #[test]
+    fn load_range() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE records (
+                       id INTEGER PRIMARY KEY,
+                       data TEXT NOT NULL
+                )",
+            params![],
+        )
+        .unwrap();
+        for id in 1..=100_u32 {
+            conn.execute("INSERT INTO records VALUES (?1, 'data')", params![&id])
+                .unwrap();
+        }
+
+        assert_eq!(Record::count_range(&conn, 10..=20), Ok(11));
+        assert_eq!(Record::count_range(&conn, 0..=u32::MAX), Ok(100));
+        assert_eq!(Record::count_range(&conn, 200..=300), Ok(0));
+
+        let ids: Vec<u32> = Record::load_range(conn, 10, 20)
+            .unwrap()
+            .iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, (10..=20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn load_limited() {
+        let table = || {
+            let conn = Connection::open_in_memory().unwrap();
+            Record::create_table(&conn).unwrap();
+            // Insert in reverse, so that limit must come after ordering
+            for id in (1..=50_u32).rev() {
+                conn.execute("INSERT INTO records VALUES (?1, 'data')", params![&id])
+                    .unwrap();
+            }
+            conn
+        };
+
+        let ids: Vec<u32> = Record::load_limited(table(), 10)
+            .unwrap()
+            .iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, (1..=10).collect::<Vec<_>>());
+        assert_eq!(Record::load_limited(table(), 0).unwrap(), []);
+        assert_eq!(Record::load_limited(table(), usize::MAX).unwrap().len(), 50);
+    }
+
+    #[test]
+    fn json() {
+        let record = JsonRecord(Record {
+            id: 42,
+            data: "\"quoted\" ᚻᛖ".to_owned(),
+        });
+        let bytes = record.to_udp();
+        assert_eq!(
+            String::from_utf8(bytes.clone()).unwrap(),
+            r#"{"id":42,"data":"\"quoted\" ᚻᛖ"}"#
+        );
+        assert_eq!(JsonRecord::from_udp(&bytes), Ok(record));
+    }
+
+    #[test]
+    fn codecs() {
+        let record = Record {
+            id: 0x0102,
+            data: "same".to_owned(),
+        };
+        let encoded = [
+            (RecordCodec::Binary, record.to_udp()),
+            (RecordCodec::Json, JsonRecord(record.clone()).to_udp()),
+            (RecordCodec::LengthPrefixed, record.to_udp_with(Version::V2)),
+        ];
+        for (codec, bytes) in encoded.iter() {
+            assert_eq!(
+                Record::from_udp_with_codec(bytes, *codec, ByteOrder::Little).as_ref(),
+                Ok(&record)
+            );
+        }
+        let plain = record.to_udp_with(Version::NoId);
+        assert_eq!(
+            Record::from_udp_with_codec(&plain, RecordCodec::Plain, ByteOrder::Little)
+                .unwrap()
+                .data,
+            record.data
+        );
+
+        let big = record.to_udp_with(Format {
+            version: Version::V2,
+            byte_order: ByteOrder::Big,
+        });
+        assert_eq!(
+            Record::from_udp_with_codec(&big, RecordCodec::LengthPrefixed, ByteOrder::Big),
+            Ok(record)
+        );
+
+        // Failures are reported by the codec that was selected
+        let binary = encoded[0].1.clone();
+        match Record::from_udp_with_codec(&binary, RecordCodec::Json, ByteOrder::Little) {
+            Err(ParseError::Json(_)) => {}
+            other => panic!("Expected JSON error, got {:?}", other),
+        }
+        assert_eq!(
+            Record::from_udp_with_codec(&binary, RecordCodec::LengthPrefixed, ByteOrder::Little),
+            Err(ParseError::LengthMismatch {
+                declared: u16::from_be_bytes([b's', b'a']) as usize,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            Record::from_udp_with_codec(&[1], RecordCodec::Binary, ByteOrder::Little),
+            Err(ParseError::Incomplete(1))
+        );
+    }
+
+    #[test]
+    fn json_malformed() {
+        for input in [&b"{\"id\":1"[..], b"{\"id\":-1,\"data\":\"\"}", b"[]", b""].iter() {
+            match JsonRecord::from_udp(input) {
+                Err(ParseError::Json(_)) => {}
+                other => panic!("Expected JSON error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_error_position() {
+        let position = |res: Result<Record, ParseError>| res.unwrap_err().position();
+        let bad = [1, 0, 0, 0, b'o', b'k', 0xc3, 0x28];
+        assert_eq!(position(Record::from_udp(&bad)), Some(6));
+        assert_eq!(
+            position(Record::from_udp_with(
+                &[1, 0, 0, 0, 0, 3, b'a', 0xff, b'b'],
+                Version::V2
+            )),
+            Some(7)
+        );
+        assert_eq!(
+            position(Record::from_udp_with(&[b'a', 0xff], Version::NoId)),
+            Some(1)
+        );
+        assert_eq!(position(Record::from_udp(&[1, 0])), Some(2));
+        assert_eq!(
+            position(Record::from_udp_with(
+                &[1, 0, 0, 0, 0, 3, b'a'],
+                Version::V2
+            )),
+            None
+        );
+        assert_eq!(JsonRecord::from_udp(b"{").unwrap_err().position(), None);
+
+        let timed = [&[1, 0, 0, 0][..], &[0; 8], &[b'a', 0xff]].concat();
+        assert_eq!(
+            TimedRecord::from_udp(&timed).unwrap_err().position(),
+            Some(13)
+        );
+        let record64 = [&[1, 0, 0, 0, 0, 0, 0, 0][..], &[0xff]].concat();
+        assert_eq!(
+            Record64::from_udp(&record64).unwrap_err().position(),
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn raw_udp() {
+        let record = RawRecord {
+            id: 3,
+            data: vec![0xc3, 0x28],
+        };
+        let bytes = record.to_udp();
+        assert_eq!(bytes, vec![3, 0, 0, 0, 0xc3, 0x28]);
+        assert!(Record::from_udp(&bytes).is_err());
+        assert_eq!(RawRecord::from_udp(&bytes), Ok(record));
+        assert_eq!(RawRecord::from_udp(&[0]), Err(ParseError::Incomplete(1)));
+    }
+
+    #[test]
+    fn raw_load() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE records (
+                       id INTEGER PRIMARY KEY,
+                       data BLOB NOT NULL
+                )",
+            params![],
+        )
+        .unwrap();
+        let data = vec![0xc3, 0x28, 0, 0xff];
+        conn.execute("INSERT INTO records VALUES (2, ?1)", params![&[7_u8][..]])
+            .unwrap();
+        conn.execute("INSERT INTO records VALUES (1, ?1)", params![&data])
+            .unwrap();
+
+        assert_eq!(
+            RawRecord::load(conn).unwrap(),
+            vec![
+                RawRecord { id: 1, data },
+                RawRecord {
+                    id: 2,
+                    data: vec![7]
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn record64_udp() {
+        let record = Record64 {
+            id: 0x0102030405060708,
+            data: "ab".to_owned(),
+        };
+        let bytes = record.to_udp();
+        assert_eq!(bytes, vec![8, 7, 6, 5, 4, 3, 2, 1, b'a', b'b']);
+        assert_eq!(Record64::from_udp(&bytes), Ok(record));
+        // 32-bit record would take upper half of id for data
+        assert_eq!(Record::from_udp(&bytes).unwrap().id, 0x05060708);
+        assert_eq!(
+            Record64::from_udp(&bytes[..7]),
+            Err(ParseError::Incomplete(7))
+        );
+        assert!(Record64::from_udp(&[0, 0, 0, 0, 0, 0, 0, 0, 0xc3, 0x28]).is_err());
+    }
+
+    #[test]
+    fn record64_db() {
+        let conn = Connection::open_in_memory().unwrap();
+        Record::create_table(&conn).unwrap();
+        let records = vec![
+            Record64 {
+                id: 1 << 40,
+                data: "large".to_owned(),
+            },
+            Record64 {
+                id: u64::MAX - 1,
+                data: "huge".to_owned(),
+            },
+            Record64 {
+                id: 3,
+                data: "small".to_owned(),
+            },
+        ];
+        for record in records.iter() {
+            assert!(record.insert(&conn, OnConflict::Abort).unwrap());
+        }
+        let expected = vec![records[2].clone(), records[0].clone(), records[1].clone()];
+        assert_eq!(Record64::load(conn).unwrap(), expected);
+    }
+
+    #[test]
+    fn udp_v2() {
+        let record = Record {
+            id: 1,
+            data: "ab".to_owned(),
+        };
+        let bytes = record.to_udp_with(Version::V2);
+        assert_eq!(bytes, vec![1, 0, 0, 0, 0, 2, b'a', b'b']);
+        assert_eq!(Record::from_udp_with(&bytes, Version::V2), Ok(record));
+        // Old parser sees length prefix as part of data
+        assert_eq!(
+            Record::from_udp_with(&bytes, Version::V1).unwrap().data,
+            "\0\u{2}ab"
+        );
+    }
+
+    #[test]
+    fn udp_v2_short() {
+        assert_eq!(
+            Record::from_udp_with(&[1, 0, 0], Version::V2),
+            Err(ParseError::Incomplete(3))
+        );
+        assert_eq!(
+            Record::from_udp_with(&[1, 0, 0, 0, 0], Version::V2),
+            Err(ParseError::Incomplete(5))
+        );
+    }
+
+    #[test]
+    fn udp_v2_lying_length() {
+        assert_eq!(
+            Record::from_udp_with(&[1, 0, 0, 0, 0, 3, b'a', b'b'], Version::V2),
+            Err(ParseError::LengthMismatch {
+                declared: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            Record::from_udp_with(&[1, 0, 0, 0, 0, 1, b'a', b'b'], Version::V2),
+            Err(ParseError::LengthMismatch {
+                declared: 1,
+                actual: 2
+            })
+        );
+    }
+
+    #[test]
+    fn udp_v2_truncated() {
+        let record = Record {
+            id: 1,
+            data: "ж".repeat(40_000),
+        };
+        let bytes = record.to_udp_with(Version::V2);
+        let parsed = Record::from_udp_with(&bytes, Version::V2).unwrap();
+        assert_eq!(parsed.data.len(), u16::MAX as usize - 1);
+    }
+
+    #[test]
+    fn udp_no_id() {
+        let record = Record {
+            id: 7,
+            data: "plain".to_owned(),
+        };
+        let bytes = record.to_udp_with(Version::NoId);
+        assert_eq!(bytes, b"plain".to_vec());
+        // Id doesn't survive the trip
+        assert_eq!(
+            Record::from_udp_with(&bytes, Version::NoId),
+            Ok(Record {
+                id: 0,
+                data: "plain".to_owned()
+            })
+        );
+        assert_eq!(Record::from_udp_with(&[], Version::NoId).unwrap().data, "");
+
+        let timed = TimedRecord {
+            record: Record {
+                id: 0,
+                data: "t".to_owned(),
+            },
+            timestamp: 0x0102030405060708,
+        };
+        let bytes = timed.to_udp_with(Version::NoId);
+        assert_eq!(bytes, vec![8, 7, 6, 5, 4, 3, 2, 1, b't']);
+        assert_eq!(TimedRecord::from_udp_with(&bytes, Version::NoId), Ok(timed));
+    }
+
+    #[test]
+    fn udp_into() {
+        let record = Record {
+            id: 7,
+            data: "buffered".to_owned(),
+        };
+        let mut buf = Vec::new();
+        record.to_udp_into(&mut buf);
+        assert_eq!(buf, record.to_udp());
+
+        // Appends rather than overwrites
+        let mut buf = vec![0xff];
+        record.to_udp_into_with(&mut buf, Version::V2);
+        assert_eq!(buf[0], 0xff);
+        assert_eq!(buf[1..], record.to_udp_with(Version::V2)[..]);
+    }
+
+    #[test]
+    fn create_db() {
+        let path =
+            std::env::temp_dir().join(format!("udptest-create-{}.sqlite", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let records = Record::generate(500, 16);
+        Record::create_db(&path, &records).unwrap();
+        let loaded = Record::load(Connection::open(&path).unwrap()).unwrap();
+        assert_eq!(loaded, records);
+
+        // Replaces previous contents
+        let records = Record::generate(3, 4);
+        Record::create_db(&path, &records).unwrap();
+        let loaded = Record::load(Connection::open(&path).unwrap()).unwrap();
+        assert_eq!(loaded, records);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_lines() {
+        let text = "first\n\nᚻᛖ ᚳᚹᚫᚦ\r\nlast";
+        let records = Record::load_lines(text.as_bytes()).unwrap();
+        let expected: Vec<_> = ["first", "", "ᚻᛖ ᚳᚹᚫᚦ", "last"]
+            .iter()
+            .enumerate()
+            .map(|(id, data)| Record {
+                id: id as u32,
+                data: data.to_string(),
+            })
+            .collect();
+        assert_eq!(records, expected);
+
+        assert_eq!(Record::load_lines(&b""[..]).unwrap(), []);
+        let invalid = Record::load_lines(&b"ok\n\xff\n"[..]).unwrap_err();
+        assert_eq!(invalid.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn generate() {
+        let records = Record::generate(30, 100);
+        assert_eq!(records.len(), 30);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record.id, i as u32);
+            assert_eq!(record.data.len(), 100);
+        }
+        assert_eq!(&records[1].data[..3], "bcd");
+        assert!(Record::generate(0, 10).is_empty());
+        assert_eq!(Record::generate(1, 0)[0].data, "");
+    }
+
+    #[test]
+    fn validate() {
+        let mut record = Record {
+            id: 9,
+            data: "abcdef".to_owned(),
+        };
+        assert_eq!(record.validate(10, Version::V1), Ok(()));
+        assert_eq!(
+            record.validate(8, Version::V1),
+            Err(RecordError::TooLong {
+                id: 9,
+                len: 10,
+                max: 8
+            })
+        );
+        assert_eq!(record.validate(6, Version::NoId), Ok(()));
+        assert_eq!(
+            record.validate(10, Version::V2),
+            Err(RecordError::TooLong {
+                id: 9,
+                len: 12,
+                max: 10
+            })
+        );
+        assert!(!record.truncate(10, Version::V1));
+        assert!(record.truncate(8, Version::V1));
+        assert_eq!(record.data, "abcd");
+        assert_eq!(record.validate(8, Version::V1), Ok(()));
+
+        assert!(record.truncate(3, Version::NoId));
+        assert_eq!(record.data, "abc");
+        assert_eq!(record.to_udp_with(Version::NoId).len(), 3);
+        assert!(record.truncate(8, Version::V2));
+        assert_eq!(record.data, "ab");
+        assert_eq!(record.to_udp_with(Version::V2).len(), 8);
+    }
+
+    #[test]
+    fn truncate_char_boundary() {
+        // Two bytes per character
+        let mut record = Record {
+            id: 1,
+            data: "жжж".to_owned(),
+        };
+        assert!(record.truncate(4 + 5, Version::V1));
+        assert_eq!(record.data, "жж");
+        let bytes = record.to_udp();
+        assert_eq!(Record::from_udp(&bytes), Ok(record));
+    }
+
+    #[test]
+    fn byte_order() {
+        let record = Record {
+            id: 0x01020304,
+            data: "d".to_owned(),
+        };
+        let little = record.to_udp_with(ByteOrder::Little);
+        let big = record.to_udp_with(ByteOrder::Big);
+        assert_eq!(little[..4], [4, 3, 2, 1]);
+        assert_eq!(big[..4], [1, 2, 3, 4]);
+        assert_eq!(little, record.to_udp());
+
+        assert_eq!(
+            Record::from_udp_with(&little, ByteOrder::Little).as_ref(),
+            Ok(&record)
+        );
+        assert_eq!(
+            Record::from_udp_with(&big, ByteOrder::Big).as_ref(),
+            Ok(&record)
+        );
+        assert_eq!(
+            Record::from_udp_with(&big, ByteOrder::Little).unwrap().id,
+            0x04030201
+        );
+
+        let format = Format {
+            version: Version::V2,
+            byte_order: ByteOrder::Big,
+        };
+        let bytes = record.to_udp_with(format);
+        assert_eq!(bytes, vec![1, 2, 3, 4, 0, 1, b'd']);
+        assert_eq!(Record::from_udp_with(&bytes, format), Ok(record));
+    }
+
+    #[test]
+    fn timed_udp() {
+        let record = TimedRecord {
+            record: Record {
+                id: 1,
+                data: "t".to_owned(),
+            },
+            timestamp: 0x0102030405060708,
+        };
+        let bytes = record.to_udp();
+        assert_eq!(bytes, vec![1, 0, 0, 0, 8, 7, 6, 5, 4, 3, 2, 1, b't']);
+        assert_eq!(TimedRecord::from_udp(&bytes).as_ref(), Ok(&record));
+
+        let format = Format {
+            version: Version::V2,
+            byte_order: ByteOrder::Big,
+        };
+        let bytes = record.to_udp_with(format);
+        assert_eq!(bytes, vec![0, 0, 0, 1, 1, 2, 3, 4, 5, 6, 7, 8, 0, 1, b't']);
+
+        // Appended after whatever buffer already holds
+        let formatted = Formatted {
+            item: record,
+            format,
+        };
+        let mut buf = vec![9];
+        formatted.to_udp_into(&mut buf);
+        assert_eq!(buf[0], 9);
+        assert_eq!(&buf[1..], &bytes[..]);
+        assert_eq!(formatted.item_id(), Some(1));
+        let record = formatted.item;
+        assert_eq!(TimedRecord::from_udp_with(&bytes, format), Ok(record));
+
+        assert_eq!(
+            TimedRecord::from_udp(&[1, 0, 0, 0, 0]),
+            Err(ParseError::Incomplete(5))
+        );
+        assert_eq!(
+            TimedRecord::from_udp_with(&bytes[..13], Version::V2),
+            Err(ParseError::Incomplete(13))
+        );
+    }
+
+    #[test]
+    fn timed_load() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE records (
+                       id INTEGER PRIMARY KEY,
+                       data TEXT NOT NULL,
+                       ts INTEGER
+                )",
+            params![],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO records VALUES (1, 'a', 1000)", params![])
+            .unwrap();
+        conn.execute("INSERT INTO records VALUES (2, 'b', NULL)", params![])
+            .unwrap();
+        conn.execute("INSERT INTO records VALUES (3, 'c', 500)", params![])
+            .unwrap();
+        conn.execute("INSERT INTO records VALUES (4, 'd', 500)", params![])
+            .unwrap();
+
+        let before = now_millis();
+        let loaded = TimedRecord::load(conn).unwrap();
+        let ids: Vec<u32> = loaded.iter().map(|timed| timed.record.id).collect();
+        assert_eq!(ids, [3, 4, 1, 2]);
+        assert_eq!(loaded[0].timestamp, 500);
+        assert_eq!(loaded[2].timestamp, 1000);
+        assert!(loaded[3].timestamp >= before && loaded[3].timestamp <= now_millis());
+    }
+
+    #[test]
+    fn timed_load_no_column() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE records (
+                       id INTEGER PRIMARY KEY,
+                       data TEXT NOT NULL
+                )",
+            params![],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO records VALUES (1, 'a')", params![])
+            .unwrap();
+
+        let before = now_millis();
+        let loaded = TimedRecord::load(conn).unwrap();
+        assert_eq!(
+            loaded[0].record,
+            Record {
+                id: 1,
+                data: "a".to_owned()
+            }
+        );
+        assert!(loaded[0].timestamp >= before && loaded[0].timestamp <= now_millis());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_udp() {
+        use crate::record::CompressedRecord;
+
+        let repetitive = CompressedRecord(Record {
+            id: 7,
+            data: "abcd".repeat(100),
+        });
+        let bytes = repetitive.to_udp();
+        assert_eq!(&bytes[..5], &[7, 0, 0, 0, 1]);
+        assert!(bytes.len() < repetitive.0.data.len());
+        assert_eq!(CompressedRecord::from_udp(&bytes), Ok(repetitive));
+
+        // Too short to shrink
+        let short = CompressedRecord(Record {
+            id: 8,
+            data: "hi".to_owned(),
+        });
+        let bytes = short.to_udp();
+        assert_eq!(bytes, [8, 0, 0, 0, 0, b'h', b'i']);
+        assert_eq!(CompressedRecord::from_udp(&bytes), Ok(short));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_corrupted() {
+        use crate::record::CompressedRecord;
+
+        let record = CompressedRecord(Record {
+            id: 7,
+            data: "abcd".repeat(100),
+        });
+        let mut bytes = record.to_udp();
+        bytes.truncate(bytes.len() / 2);
+        assert!(matches!(
+            CompressedRecord::from_udp(&bytes),
+            Err(ParseError::Decompress(_))
+        ));
+        // Not a deflate stream at all
+        assert!(matches!(
+            CompressedRecord::from_udp(&[7, 0, 0, 0, 1, 0xff, 0xff, 0xff]),
+            Err(ParseError::Decompress(_))
+        ));
+        assert!(matches!(
+            CompressedRecord::from_udp(&[7, 0, 0, 0, 2, b'h', b'i']),
+            Err(ParseError::Decompress(_))
+        ));
+        assert_eq!(
+            CompressedRecord::from_udp(&[7, 0, 0, 0]),
+            Err(ParseError::Incomplete(4))
+        );
+    }
+
+    #[test]
+    fn loopback_self_test() {
+        let report = self_test().unwrap();
+        assert_eq!(report.sent, SELF_TEST_RECORDS as usize);
+        assert_eq!(report.corrupted, 0);
+        assert!(report.passed());
     }
 }
@@ -0,0 +1,315 @@
+//! Async counterparts of [Sender] and [Receiver] for use
+//! with Tokio. They share framing logic and configuration
+//! with blocking ones, so they can talk to each other.
+use std::{
+    fmt,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use ::tokio::{
+    io::ReadBuf,
+    net::{lookup_host, ToSocketAddrs, UdpSocket},
+    time::sleep,
+};
+use futures_core::Stream;
+
+use crate::udp::{
+    resolve_for, schedule, Digest, Error, FromUdpSource, Receiver, SendReport, Sender,
+    SequenceInfo, ToUdp, WithSource,
+};
+
+/// Sends items like [Sender], without blocking the runtime.
+#[derive(Debug)]
+pub struct AsyncSender {
+    sock: UdpSocket,
+    /// Prepares datagrams in dry run mode, which are then sent by us.
+    inner: Sender,
+    /// Minimal interval between datagrams, if rate is limited.
+    interval: Option<std::time::Duration>,
+    /// When the next datagram is allowed to go out.
+    next_send: Option<Instant>,
+}
+
+impl AsyncSender {
+    /// Binds to `addr`. Must be called within Tokio runtime.
+    pub async fn new<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        let std_sock = UdpSocket::bind(addr).await?.into_std()?;
+        let sock = UdpSocket::from_std(std_sock.try_clone()?)?;
+        let mut inner = Sender::from_socket(std_sock);
+        inner.set_dry_run(true);
+        Ok(Self {
+            sock,
+            inner,
+            interval: None,
+            next_send: None,
+        })
+    }
+
+    /// Address the socket is actually bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    /// Same as [Sender::set_max_payload].
+    pub fn set_max_payload(&mut self, max_payload: usize) -> std::io::Result<()> {
+        self.inner.set_max_payload(max_payload)
+    }
+
+    /// Same as [Sender::set_fragmentation].
+    pub fn set_fragmentation(&mut self, on: bool) {
+        self.inner.set_fragmentation(on);
+    }
+
+    /// Same as [Sender::set_checksum].
+    pub fn set_checksum(&mut self, on: bool) {
+        self.inner.set_checksum(on);
+    }
+
+    /// Same as [Sender::set_sequencing].
+    pub fn set_sequencing(&mut self, on: bool) {
+        self.inner.set_sequencing(on);
+    }
+
+    /// Same as [Sender::set_batching].
+    pub fn set_batching(&mut self, on: bool) {
+        self.inner.set_batching(on);
+    }
+
+    /// Same as [Sender::set_interval].
+    pub fn set_interval(&mut self, interval: Option<std::time::Duration>) {
+        self.interval = interval.filter(|interval| *interval > std::time::Duration::from_secs(0));
+        self.next_send = None;
+    }
+
+    /// Sends every item of `iter` to `dest`, without connecting socket.
+    pub async fn send<I, A>(&mut self, iter: I, dest: A) -> std::io::Result<SendReport>
+    where
+        I: IntoIterator,
+        I::Item: ToUdp,
+        A: ToSocketAddrs,
+    {
+        let candidates: Vec<_> = lookup_host(dest).await?.collect();
+        let dest = resolve_for(self.sock.local_addr()?, &candidates[..])?;
+        self.inner.discard_batch();
+        self.inner.take_dry_run();
+        let mut report = SendReport::default();
+        for item in iter {
+            self.inner.send_item(&item, &mut report)?;
+            self.transmit(dest).await?;
+        }
+        self.inner.flush_batch(&mut report)?;
+        self.transmit(dest).await?;
+        Ok(report)
+    }
+
+    /// Sends datagrams prepared by `inner`, respecting rate limit.
+    async fn transmit(&mut self, dest: SocketAddr) -> std::io::Result<()> {
+        for datagram in self.inner.take_dry_run() {
+            if let Some(interval) = self.interval {
+                let (wait, next_send) = schedule(Instant::now(), self.next_send, interval);
+                self.next_send = Some(next_send);
+                sleep(wait).await;
+            }
+            self.sock.send_to(&datagram, dest).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives items like [Receiver], as a [Stream] that never ends.
+/// Use [::tokio::time::timeout] to stop waiting.
+pub struct AsyncReceiver<T> {
+    sock: UdpSocket,
+    /// Turns datagrams read by us into items.
+    inner: Receiver<T>,
+}
+
+// Nothing is pinned structurally
+impl<T> Unpin for AsyncReceiver<T> {}
+
+impl<T> fmt::Debug for AsyncReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncReceiver")
+            .field("sock", &self.sock)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<T> AsyncReceiver<T> {
+    /// Binds to `addr`. Must be called within Tokio runtime.
+    pub async fn new<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        let std_sock = UdpSocket::bind(addr).await?.into_std()?;
+        let sock = UdpSocket::from_std(std_sock.try_clone()?)?;
+        Ok(Self {
+            sock,
+            inner: Receiver::from_socket(std_sock),
+        })
+    }
+
+    /// Address the socket is actually bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    /// Same as [Receiver::set_max_payload].
+    pub fn set_max_payload(&mut self, max_payload: usize) -> std::io::Result<()> {
+        self.inner.set_max_payload(max_payload)
+    }
+
+    /// Same as [Receiver::set_fragmentation].
+    pub fn set_fragmentation(&mut self, on: bool) {
+        self.inner.set_fragmentation(on);
+    }
+
+    /// Same as [Receiver::set_checksum].
+    pub fn set_checksum(&mut self, on: bool) {
+        self.inner.set_checksum(on);
+    }
+
+    /// Same as [Receiver::set_sequencing].
+    pub fn set_sequencing(&mut self, on: bool) {
+        self.inner.set_sequencing(on);
+    }
+
+    /// Same as [Receiver::set_batching].
+    pub fn set_batching(&mut self, on: bool) {
+        self.inner.set_batching(on);
+    }
+
+    /// Same as [Receiver::set_delimiter].
+    pub fn set_delimiter(&mut self, delimiter: Option<u8>) -> std::io::Result<()> {
+        self.inner.set_delimiter(delimiter)
+    }
+
+    /// Same as [Receiver::set_source_filter].
+    pub fn set_source_filter(&mut self, source: Option<std::net::IpAddr>) {
+        self.inner.set_source_filter(source);
+    }
+
+    /// Same as [Receiver::filtered].
+    pub fn filtered(&self) -> u64 {
+        self.inner.filtered()
+    }
+
+    /// Same as [Receiver::last_sequence].
+    pub fn last_sequence(&self) -> SequenceInfo {
+        self.inner.last_sequence()
+    }
+
+    /// Same as [Receiver::digest].
+    pub fn digest(&self) -> &Digest {
+        self.inner.digest()
+    }
+}
+
+impl<T> AsyncReceiver<T>
+where
+    T: FromUdpSource,
+{
+    /// Waits for the next item and returns it along
+    /// with the address it came from.
+    pub async fn next_with_source(&mut self) -> WithSource<T> {
+        std::future::poll_fn(|cx| self.poll_next_with_source(cx)).await
+    }
+
+    fn poll_next_with_source(&mut self, cx: &mut Context<'_>) -> Poll<WithSource<T>> {
+        let sock = &self.sock;
+        self.inner.poll_next_with(|_, buf| {
+            let mut buf = ReadBuf::new(buf);
+            sock.poll_recv_from(cx, &mut buf)
+                .map_ok(|src| (buf.filled().len(), src))
+        })
+    }
+}
+
+impl<T> Stream for AsyncReceiver<T>
+where
+    T: FromUdpSource,
+{
+    type Item = Result<T, Error<T::Error>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .poll_next_with_source(cx)
+            .map(|res| Some(res.map(|(val, _)| val)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ::tokio::time::timeout;
+
+    use crate::record::Record;
+    use crate::tokio::*;
+
+    /// Records growing by `step` bytes.
+    fn records(step: usize) -> Vec<Record> {
+        (0..10)
+            .map(|id| Record {
+                id,
+                data: "a".repeat(id as usize * step),
+            })
+            .collect()
+    }
+
+    #[::tokio::test]
+    async fn round_trip() {
+        let mut receiver: AsyncReceiver<Record> = AsyncReceiver::new("127.0.0.1:0").await.unwrap();
+        let dest = receiver.local_addr().unwrap();
+        let mut sender = AsyncSender::new("127.0.0.1:0").await.unwrap();
+        let src = sender.local_addr().unwrap();
+
+        let data = records(40);
+        let report = sender.send(data.iter(), dest).await.unwrap();
+        assert_eq!(report.items_sent, data.len());
+        assert_eq!(report.digest.items(), data.len() as u64);
+
+        for record in data.iter() {
+            let (received, from) = timeout(Duration::from_secs(1), receiver.next_with_source())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&received, record);
+            assert_eq!(from, src);
+        }
+        assert_eq!(receiver.digest(), &report.digest);
+    }
+
+    #[::tokio::test]
+    async fn round_trip_framed() {
+        let mut receiver: AsyncReceiver<Record> = AsyncReceiver::new("127.0.0.1:0").await.unwrap();
+        let dest = receiver.local_addr().unwrap();
+        let mut sender = AsyncSender::new("127.0.0.1:0").await.unwrap();
+        sender.set_max_payload(256).unwrap();
+        sender.set_fragmentation(true);
+        sender.set_checksum(true);
+        sender.set_sequencing(true);
+        sender.set_interval(Some(Duration::from_millis(1)));
+        receiver.set_max_payload(256).unwrap();
+        receiver.set_fragmentation(true);
+        receiver.set_checksum(true);
+        receiver.set_sequencing(true);
+
+        // Large enough to need fragmentation
+        let data = records(100);
+        sender.send(data.iter(), dest).await.unwrap();
+
+        let mut stream = Pin::new(&mut receiver);
+        for record in data.iter() {
+            let next = std::future::poll_fn(|cx| stream.as_mut().poll_next(cx));
+            let received = timeout(Duration::from_secs(1), next)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(&received, record);
+        }
+        assert_eq!(receiver.last_sequence(), SequenceInfo::default());
+    }
+}
@@ -1,27 +1,131 @@
+//! This module provides traits and types for sending and receiving
+//! arbitrary data capable of presenting itself as a buffer of bytes
+//! through UDP.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    task::Poll,
+    time::{Duration, Instant},
+};
 use std::{marker::PhantomData, net::UdpSocket};
-///! This module provides traits and types for sending and receiving
-///! arbitrary data capable of presenting itself as a buffer of bytes
-///! through UDP.
-use std::{net::SocketAddr, time::Duration};
 
 use log::warn;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 
-const UDP_MAX_PAYLOAD: usize = 508;
-type UdpPayload = [u8; UDP_MAX_PAYLOAD];
+/// Default maximum payload of a datagram, small enough
+/// for it to be delivered over any link.
+pub const UDP_MAX_PAYLOAD: usize = 508;
+/// Read timeout used by [Receiver::new].
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+/// Size of [FragmentHeader] on the wire.
+const FRAGMENT_HEADER_LEN: usize = 8;
+/// Size of sequence number prepended to datagrams when sequencing is enabled.
+const SEQUENCE_LEN: usize = 4;
+/// Size of checksum appended to payload when checksums are enabled.
+const CHECKSUM_LEN: usize = 4;
+/// Size of item count at the start of a batch.
+const BATCH_COUNT_LEN: usize = 2;
+/// Size of length prefix of every item in a batch.
+const BATCH_LENGTH_LEN: usize = 2;
+/// Smallest maximum payload that leaves room for a byte of data
+/// with every header enabled at once.
+const MIN_PAYLOAD: usize =
+    SEQUENCE_LEN + FRAGMENT_HEADER_LEN + CHECKSUM_LEN + BATCH_COUNT_LEN + BATCH_LENGTH_LEN + 1;
+/// Escapes delimiter bytes, and itself, inside delimited items.
+pub const DELIMITER_ESCAPE: u8 = b'\\';
+/// Size of acknowledgement, which holds a sequence number.
+const ACK_LEN: usize = SEQUENCE_LEN;
+/// Longest wait between two items replayed by [Sender::send_timed],
+/// so a bogus timestamp doesn't stall replay.
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(10);
+/// How many partially received messages we keep around
+/// before dropping the oldest one.
+const MAX_PENDING_MESSAGES: usize = 64;
 
+/// Item yielded by [Receiver::next_with_source].
+pub type WithSource<T> = Result<(T, SocketAddr), Error<<T as FromUdpSource>::Error>>;
+
+/// Reasons [Receiver] fails to yield an item, with `T` being
+/// the error of parsing item from payload.
 #[derive(Debug)]
 pub enum Error<T> {
+    /// Socket failed to read a datagram.
     Io(std::io::Error),
-    ParseError(T),
+    /// Item couldn't be parsed from payload, which is
+    /// kept in `raw` for diagnostics along with its sender.
+    ParseError {
+        /// Why item couldn't be parsed.
+        source: T,
+        /// Payload item was parsed from.
+        raw: Vec<u8>,
+        /// Address payload came from.
+        src: SocketAddr,
+    },
+    /// Fragmentation is enabled, but datagram doesn't
+    /// carry a valid fragment header.
+    BadFragment,
+    /// Checksums are enabled, and payload doesn't match its checksum.
+    ChecksumMismatch,
+    /// Sequencing is enabled, but datagram is too short
+    /// to carry a sequence number.
+    MissingSequence,
+    /// Batching is enabled, but payload isn't a valid batch.
+    BadBatch,
+}
+
+impl<T: fmt::Display> fmt::Display for Error<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::ParseError { source, raw, src } => write!(
+                f,
+                "couldn't parse {} byte payload from {}: {}",
+                raw.len(),
+                src,
+                source
+            ),
+            Error::BadFragment => write!(f, "datagram has no valid fragment header"),
+            Error::ChecksumMismatch => write!(f, "payload doesn't match its checksum"),
+            Error::MissingSequence => write!(f, "datagram is too short for a sequence number"),
+            Error::BadBatch => write!(f, "payload isn't a valid batch"),
+        }
+    }
+}
+
+impl<T> From<std::io::Error> for Error<T> {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
 }
 
+impl<T> std::error::Error for Error<T>
+where
+    T: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::ParseError { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Items [Receiver] is able to parse from payloads.
 pub trait FromUdp: Sized {
+    /// Why payload doesn't hold a valid item.
     type Error;
+    /// Parses item from a whole payload.
     fn from_udp(buf: &[u8]) -> Result<Self, Self::Error>;
 }
 
+/// Same as [FromUdp], for items that also take note of the address
+/// they came from. Every [FromUdp] item is one, ignoring it.
 pub trait FromUdpSource: Sized {
+    /// Why payload doesn't hold a valid item.
     type Error;
+    /// Parses item from a whole payload that came from `source`.
     fn from_udp_source(buf: &[u8], source: SocketAddr) -> Result<Self, Self::Error>;
 }
 
@@ -35,151 +139,3991 @@ where
     }
 }
 
+/// Items [Sender] is able to send.
 pub trait ToUdp {
+    /// Serializes item into a payload.
     fn to_udp(&self) -> Vec<u8>;
+
+    /// Appends serialized item to `buf`, which lets [Sender] reuse
+    /// a single buffer for all items instead of allocating one each.
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_udp());
+    }
+
+    /// Id of item, if it has one, to point at it in error messages.
+    fn item_id(&self) -> Option<u64> {
+        None
+    }
 }
 
-pub struct Receiver<T> {
-    sock: UdpSocket,
-    buf: UdpPayload,
-    phantom: PhantomData<T>,
+/// Raw bytes are received as is.
+impl FromUdp for Vec<u8> {
+    type Error = std::convert::Infallible;
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        Ok(buf.into())
+    }
 }
 
-impl<T> Receiver<T> {
-    pub fn new<A: std::net::ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
-        let sock = UdpSocket::bind(addr)?;
-        sock.set_read_timeout(Some(Duration::from_millis(100)))?;
-        Ok(Self {
-            sock,
-            buf: [0_u8; UDP_MAX_PAYLOAD],
-            phantom: PhantomData,
-        })
+/// Item that only knows length of its payload, for receivers
+/// counting traffic without paying for copying or parsing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadLen(pub usize);
+
+impl FromUdp for PayloadLen {
+    type Error = std::convert::Infallible;
+    fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
+        Ok(PayloadLen(buf.len()))
     }
 }
 
-impl<T> Iterator for Receiver<T>
+/// Raw bytes are sent as is.
+impl ToUdp for Vec<u8> {
+    fn to_udp(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+}
+
+impl<T> ToUdp for &T
 where
-    T: FromUdpSource,
+    T: ToUdp + ?Sized,
 {
-    type Item = Result<T, Error<T::Error>>;
+    fn to_udp(&self) -> Vec<u8> {
+        (**self).to_udp()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.sock.recv_from(&mut self.buf) {
-            Ok((len, src)) => {
-                let val =
-                    T::from_udp_source(&self.buf[..len], src).map_err(|e| Error::ParseError(e));
-                Some(val)
-            }
-            Err(e) => Some(Err(Error::Io(e))),
+    fn to_udp_into(&self, buf: &mut Vec<u8>) {
+        (**self).to_udp_into(buf)
+    }
+
+    fn item_id(&self) -> Option<u64> {
+        (**self).item_id()
+    }
+}
+
+/// Prepended to every datagram when fragmentation is enabled,
+/// so that payloads larger than a single datagram can be split
+/// across several datagrams and put back together on receiving end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FragmentHeader {
+    /// Identifies the message this fragment belongs to.
+    message: u32,
+    /// Position of this fragment in the message.
+    index: u16,
+    /// Total number of fragments in the message.
+    total: u16,
+}
+
+impl FragmentHeader {
+    fn to_bytes(self) -> [u8; FRAGMENT_HEADER_LEN] {
+        let mut bytes = [0_u8; FRAGMENT_HEADER_LEN];
+        bytes[..4].copy_from_slice(&self.message.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.index.to_le_bytes());
+        bytes[6..].copy_from_slice(&self.total.to_le_bytes());
+        bytes
+    }
+
+    /// Splits datagram into header and fragment data.
+    /// Returns `None` if header is missing or inconsistent.
+    fn parse(buf: &[u8]) -> Option<(Self, &[u8])> {
+        if buf.len() < FRAGMENT_HEADER_LEN {
+            return None;
         }
+        let header = Self {
+            message: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            index: u16::from_le_bytes([buf[4], buf[5]]),
+            total: u16::from_le_bytes([buf[6], buf[7]]),
+        };
+        if header.index >= header.total {
+            return None;
+        }
+        Some((header, &buf[FRAGMENT_HEADER_LEN..]))
     }
 }
 
-pub struct Sender {
-    sock: UdpSocket,
+/// Fails if datagrams of `max_payload` bytes can't fit all headers
+/// along with at least a byte of data.
+fn check_max_payload(max_payload: usize) -> std::io::Result<()> {
+    if max_payload < MIN_PAYLOAD {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Max payload of {} bytes is too small, must be at least {}",
+                max_payload, MIN_PAYLOAD
+            ),
+        ));
+    }
+    Ok(())
 }
 
-impl<'a> Sender {
-    pub fn new<A>(addr: A) -> std::io::Result<Self>
-    where
-        A: std::net::ToSocketAddrs,
-    {
-        Ok(Self {
-            sock: UdpSocket::bind(addr)?,
+/// Fails if `delimiter` is the byte escaping it within items.
+fn check_delimiter(delimiter: Option<u8>) -> std::io::Result<()> {
+    if delimiter == Some(DELIMITER_ESCAPE) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Delimiter can't be the escape byte",
+        ));
+    }
+    Ok(())
+}
+
+/// Splits `payload` into datagrams no larger than `max_len`,
+/// each prefixed with a [FragmentHeader].
+fn fragment(payload: &[u8], message: u32, max_len: usize) -> std::io::Result<Vec<Vec<u8>>> {
+    let chunk_len = max_len - FRAGMENT_HEADER_LEN;
+    let chunks: Vec<&[u8]> = if payload.is_empty() {
+        vec![payload]
+    } else {
+        payload.chunks(chunk_len).collect()
+    };
+    if chunks.len() > u16::MAX as usize {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Item too large to fragment",
+        ));
+    }
+
+    let total = chunks.len() as u16;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let header = FragmentHeader {
+                message,
+                index: index as u16,
+                total,
+            };
+            [&header.to_bytes()[..], chunk].concat()
         })
+        .collect())
+}
+
+/// Whether `e` means datagram is larger than path to destination
+/// allows, i.e. `EMSGSIZE`, which std doesn't have a kind for.
+fn is_message_too_long(e: &std::io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    const EMSGSIZE: i32 = libc::EMSGSIZE;
+    #[cfg(windows)]
+    const EMSGSIZE: i32 = 10040;
+    // BSDs and macOS
+    #[cfg(not(any(target_os = "linux", windows)))]
+    const EMSGSIZE: i32 = 40;
+    e.raw_os_error() == Some(EMSGSIZE)
+}
+
+/// Points at item that didn't fit into a datagram, by its id
+/// or else its position in send, and suggests what to do about it.
+/// Other errors are returned as is.
+fn explain_too_long(
+    e: std::io::Error,
+    id: Option<u64>,
+    index: usize,
+    len: usize,
+) -> std::io::Error {
+    if !is_message_too_long(&e) {
+        return e;
     }
+    let item = match id {
+        Some(id) => format!("Record {}", id),
+        None => format!("Item #{}", index),
+    };
+    std::io::Error::new(
+        e.kind(),
+        format!(
+            "{} takes {} bytes, more than network path allows ({}). \
+             Enable fragmentation or lower max payload",
+            item, len, e
+        ),
+    )
+}
 
-    pub fn send<I, T: 'a, A>(&mut self, iter: I, dest: A) -> std::io::Result<()>
-    where
-        I: Iterator<Item = &'a T>,
-        T: ToUdp,
-        A: std::net::ToSocketAddrs,
-    {
-        self.sock.connect(dest)?;
-        for item in iter {
-            let item = item.to_udp();
-            if item.len() > UDP_MAX_PAYLOAD {
-                warn!("Item too large, truncated");
-                self.sock.send(&item[..UDP_MAX_PAYLOAD])?;
-            } else {
-                self.sock.send(&item)?;
-            }
+/// Binds to the first address `addr` resolves to that can be bound,
+/// with `SO_REUSEADDR` set so that other sockets doing the same
+/// may share the port.
+fn bind_reusable<A: ToSocketAddrs>(addr: A) -> std::io::Result<UdpSocket> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        let sock = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        sock.set_reuse_address(true)?;
+        match sock.bind(&addr.into()) {
+            Ok(()) => return Ok(sock.into()),
+            Err(e) => last_error = Some(e),
         }
-        Ok(())
     }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "No address to bind to")
+    }))
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::udp::*;
-    use rand::{thread_rng, Rng};
-    use std::thread;
+/// Resolves `dest` to an address a socket bound to `local` can reach.
+/// Addresses of the same family as `local` are preferred; an IPv4-only
+/// destination is mapped into IPv6 space for IPv6 sockets.
+pub(crate) fn resolve_for<A: ToSocketAddrs>(
+    local: SocketAddr,
+    dest: A,
+) -> std::io::Result<SocketAddr> {
+    let mut fallback = None;
+    for addr in dest.to_socket_addrs()? {
+        if addr.is_ipv4() == local.is_ipv4() {
+            return Ok(addr);
+        }
+        fallback.get_or_insert(addr);
+    }
 
-    type DummyData = Vec<u8>;
+    match fallback {
+        Some(SocketAddr::V4(addr)) => {
+            let ip = IpAddr::V6(addr.ip().to_ipv6_mapped());
+            Ok(SocketAddr::new(ip, addr.port()))
+        }
+        Some(addr) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Can't send to {} from IPv4 address {}", addr, local),
+        )),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "No address to send to",
+        )),
+    }
+}
+
+/// Whether [Receiver::set_drop_tracking] works on this platform.
+pub const OS_DROPS_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Kernel count of datagrams dropped because socket receive buffer
+/// was full, reported with every datagram once `SO_RXQ_OVFL` is set.
+#[cfg(target_os = "linux")]
+mod overflow {
+    use std::{
+        io, mem,
+        net::{SocketAddr, UdpSocket},
+        os::unix::io::AsRawFd,
+        ptr,
+    };
 
-    impl FromUdp for DummyData {
-        type Error = ();
-        fn from_udp(buf: &[u8]) -> Result<Self, Self::Error> {
-            Ok(buf.into())
+    use socket2::SockAddr;
+
+    /// Turns reporting of dropped datagrams on or off.
+    pub fn enable(sock: &UdpSocket, on: bool) -> io::Result<()> {
+        let on = on as libc::c_int;
+        // Safety: option value is a valid int of given size
+        let res = unsafe {
+            libc::setsockopt(
+                sock.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RXQ_OVFL,
+                &on as *const libc::c_int as *const libc::c_void,
+                mem::size_of_val(&on) as libc::socklen_t,
+            )
+        };
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
         }
     }
 
-    impl ToUdp for DummyData {
-        fn to_udp(&self) -> Vec<u8> {
-            self.clone()
+    /// Same as [UdpSocket::recv_from], but also returns the number of
+    /// datagrams dropped since socket was created, if kernel reported it.
+    pub fn recv_from(
+        sock: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // Made of u64s to keep control messages aligned
+        let mut control = [0_u64; 8];
+        let mut dropped = None;
+        // Safety: every pointer in `msg` outlives the call, and control
+        // messages are only read within length kernel filled in
+        let (len, addr) = unsafe {
+            SockAddr::init(|storage, storage_len| {
+                let mut msg: libc::msghdr = mem::zeroed();
+                msg.msg_name = storage as *mut libc::c_void;
+                msg.msg_namelen = *storage_len;
+                msg.msg_iov = &mut iov;
+                msg.msg_iovlen = 1;
+                msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                msg.msg_controllen = mem::size_of_val(&control) as _;
+                let len = libc::recvmsg(sock.as_raw_fd(), &mut msg, 0);
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                *storage_len = msg.msg_namelen;
+                let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+                while !cmsg.is_null() {
+                    if (*cmsg).cmsg_level == libc::SOL_SOCKET
+                        && (*cmsg).cmsg_type == libc::SO_RXQ_OVFL
+                    {
+                        dropped = Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const u32));
+                    }
+                    cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+                }
+                Ok(len as usize)
+            })?
+        };
+        let addr = addr.as_socket().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Datagram from unknown address family",
+            )
+        })?;
+        Ok((len, addr, dropped))
+    }
+}
+
+/// Stand-in for platforms that don't report dropped datagrams.
+#[cfg(not(target_os = "linux"))]
+mod overflow {
+    use std::{
+        io,
+        net::{SocketAddr, UdpSocket},
+    };
+
+    pub fn enable(_sock: &UdpSocket, on: bool) -> io::Result<()> {
+        if on {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Dropped datagrams aren't reported on this platform",
+            ))
+        } else {
+            Ok(())
         }
     }
 
-    fn construct_dummy_data() -> Vec<DummyData> {
-        let mut rng = thread_rng();
-        vec![
-            vec![],
-            vec![42, 1],
-            vec![0; UDP_MAX_PAYLOAD],
-            vec![u8::MAX; UDP_MAX_PAYLOAD],
-            (0..UDP_MAX_PAYLOAD).map(|_| rng.gen()).collect(),
-        ]
+    pub fn recv_from(
+        sock: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+        sock.recv_from(buf).map(|(len, src)| (len, src, None))
     }
+}
 
-    #[test]
-    // Basic Sender test
-    fn sender() {
-        let rx_sock = UdpSocket::bind("0.0.0.0:8567").unwrap();
-        let mut sender = Sender::new("0.0.0.0:8568").unwrap();
+/// Appends CRC32 of `payload` to it.
+fn append_checksum(payload: &mut Vec<u8>) {
+    let crc = crc32fast::hash(payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+}
 
-        let data = construct_dummy_data();
-        let copy = data.clone();
+/// Strips checksum appended by [append_checksum], returning
+/// the payload if it matches.
+fn verify_checksum(message: &[u8]) -> Option<&[u8]> {
+    if message.len() < CHECKSUM_LEN {
+        return None;
+    }
+    let (payload, crc) = message.split_at(message.len() - CHECKSUM_LEN);
+    if crc32fast::hash(payload).to_le_bytes() == crc {
+        Some(payload)
+    } else {
+        None
+    }
+}
 
-        let _t = thread::spawn(move || {
-            sender.send(copy.iter(), "127.0.0.1:8567").unwrap();
-        });
+/// Running CRC32 over a sequence of serialized items, to tell
+/// whether what was received is exactly what was sent. Every item
+/// is hashed with its length, so items split differently don't
+/// produce the same digest.
+#[derive(Clone, Default)]
+pub struct Digest {
+    hasher: crc32fast::Hasher,
+    items: u64,
+}
 
-        for packet in data.iter() {
-            let mut buf = vec![0_u8; UDP_MAX_PAYLOAD];
-            let (len, _) = rx_sock.recv_from(&mut buf).unwrap();
-            assert_eq!(packet, &buf[..len]);
+impl Digest {
+    /// Adds serialized item to the digest.
+    pub fn update(&mut self, payload: &[u8]) {
+        self.hasher.update(&(payload.len() as u32).to_le_bytes());
+        self.hasher.update(payload);
+        self.items += 1;
+    }
+
+    /// CRC32 of items added so far.
+    pub fn value(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+
+    /// Number of items added so far.
+    pub fn items(&self) -> u64 {
+        self.items
+    }
+}
+
+impl PartialEq for Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items && self.value() == other.value()
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Digest")
+            .field("value", &format_args!("{:08x}", self.value()))
+            .field("items", &self.items)
+            .finish()
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x} over {} items", self.value(), self.items)
+    }
+}
+
+/// Gap information about the latest item yielded by [Receiver].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SequenceInfo {
+    /// Number of datagrams missing between the previously
+    /// received one and this one.
+    pub lost_since_last: u32,
+    /// Whether a datagram arrived after one sent later than it.
+    pub out_of_order: bool,
+}
+
+/// Remembers the last sequence number seen from every source.
+#[derive(Default)]
+struct SequenceTracker {
+    last: HashMap<SocketAddr, u32>,
+}
+
+impl SequenceTracker {
+    fn track(&mut self, source: SocketAddr, seq: u32) -> SequenceInfo {
+        let mut info = SequenceInfo::default();
+        match self.last.get(&source) {
+            Some(&last) => {
+                let ahead = seq.wrapping_sub(last);
+                if ahead == 0 || ahead > u32::MAX / 2 {
+                    // Duplicate or a straggler; don't move backwards.
+                    info.out_of_order = true;
+                } else {
+                    info.lost_since_last = ahead - 1;
+                    self.last.insert(source, seq);
+                }
+            }
+            None => {
+                self.last.insert(source, seq);
+            }
         }
+        info
     }
 
-    #[test]
-    // Basic receiver test
-    fn receiver() {
-        let mut receiver: Receiver<DummyData> = Receiver::new("0.0.0.0:8569").unwrap();
-        let tx_sock = UdpSocket::bind("0.0.0.0:8570").unwrap();
-        tx_sock.connect("127.0.0.1:8569").unwrap();
+    /// Tracks datagram `seq` from `src` once it proved intact, adding
+    /// gaps to `info`, and acknowledges it through `sock` if `acks` is set.
+    fn accept(
+        &mut self,
+        sock: &UdpSocket,
+        acks: bool,
+        src: SocketAddr,
+        seq: u32,
+        info: &mut SequenceInfo,
+    ) {
+        if acks {
+            acknowledge(sock, src, seq);
+        }
+        let tracked = self.track(src, seq);
+        info.lost_since_last += tracked.lost_since_last;
+        info.out_of_order |= tracked.out_of_order;
+    }
+}
 
-        let data = construct_dummy_data();
-        let copy = data.clone();
+/// Sends sequence number `seq` back to `src`, as [Sender::send_reliable]
+/// expects. Failures are only logged, since sender will retransmit.
+fn acknowledge(sock: &UdpSocket, src: SocketAddr, seq: u32) {
+    if let Err(e) = sock.send_to(&seq.to_le_bytes(), src) {
+        warn!(
+            src:% = src, error:% = e, kind:? = e.kind();
+            "Couldn't acknowledge datagram to {}: {}", src, e
+        );
+    }
+}
 
-        let _t = thread::spawn(move || {
-            for packet in copy.iter() {
-                tx_sock.send(packet).unwrap();
+/// A message we received some, but not all fragments of.
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+/// Collects fragments until a message is complete.
+#[derive(Default)]
+struct Reassembler {
+    pending: HashMap<(SocketAddr, u32), Partial>,
+    /// Keys of `pending` in arrival order, used for eviction.
+    order: VecDeque<(SocketAddr, u32)>,
+}
+
+impl Reassembler {
+    /// Stores a fragment, returning whole message once
+    /// all of its fragments have arrived.
+    fn push(&mut self, source: SocketAddr, header: FragmentHeader, data: &[u8]) -> Option<Vec<u8>> {
+        let key = (source, header.message);
+        let total = header.total as usize;
+
+        match self.pending.get(&key) {
+            Some(partial) if partial.fragments.len() == total => {}
+            _ => {
+                // Either a new message, or an id reused with different
+                // fragment count; start over in both cases.
+                if self.pending.remove(&key).is_none() {
+                    self.order.push_back(key);
+                }
+                self.pending.insert(
+                    key,
+                    Partial {
+                        fragments: vec![None; total],
+                        missing: total,
+                    },
+                );
             }
-        });
+        }
 
-        for packet in data.iter() {
-            let recv = receiver.next().unwrap().unwrap();
-            assert_eq!(packet, &recv);
+        let partial = self.pending.get_mut(&key).expect("Inserted above");
+        let slot = &mut partial.fragments[header.index as usize];
+        if slot.is_none() {
+            *slot = Some(data.to_vec());
+            partial.missing -= 1;
+        }
+
+        if partial.missing == 0 {
+            let partial = self.pending.remove(&key).expect("Checked above");
+            self.order.retain(|k| *k != key);
+            return Some(partial.fragments.into_iter().flatten().flatten().collect());
+        }
+
+        while self.order.len() > MAX_PENDING_MESSAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                warn!(
+                    message = oldest.1, src:% = oldest.0;
+                    "Dropping incomplete message {} from {}", oldest.1, oldest.0
+                );
+                self.pending.remove(&oldest);
+            }
+        }
+        None
+    }
+}
+
+/// Items waiting to be sent together, encoded as they're added:
+/// `[count:2]` followed by `[len:2][item:len]` for every item,
+/// all big-endian.
+#[derive(Default)]
+struct Batch {
+    /// Room for item count, followed by items added so far.
+    payload: Vec<u8>,
+    items: u16,
+}
+
+impl Batch {
+    /// Appends `item`, which must be at most `u16::MAX` bytes long.
+    fn push(&mut self, item: &[u8]) {
+        if self.payload.is_empty() {
+            self.payload.extend_from_slice(&[0; BATCH_COUNT_LEN]);
+        }
+        self.payload
+            .extend_from_slice(&(item.len() as u16).to_be_bytes());
+        self.payload.extend_from_slice(item);
+        self.items += 1;
+    }
+
+    /// Size of the batch once encoded.
+    fn len(&self) -> usize {
+        self.payload.len().max(BATCH_COUNT_LEN)
+    }
+
+    /// Fills in item count and returns the encoded batch, which
+    /// should be handed back to [Batch::reset] after it's sent.
+    fn encode(&mut self) -> Vec<u8> {
+        self.payload.resize(self.len(), 0);
+        self.payload[..BATCH_COUNT_LEN].copy_from_slice(&self.items.to_be_bytes());
+        std::mem::take(&mut self.payload)
+    }
+
+    /// Empties the batch, reusing `payload` returned by [Batch::encode].
+    fn reset(&mut self, payload: Vec<u8>) {
+        self.payload = payload;
+        self.clear();
+    }
+
+    /// Drops items added so far.
+    fn clear(&mut self) {
+        self.payload.clear();
+        self.items = 0;
+    }
+}
+
+/// Joins items with `delimiter`, escaping any delimiter or
+/// [DELIMITER_ESCAPE] bytes inside them with [DELIMITER_ESCAPE].
+pub fn join_delimited<I>(items: I, delimiter: u8) -> Vec<u8>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let mut joined = Vec::new();
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            joined.push(delimiter);
+        }
+        for &byte in item.as_ref() {
+            if byte == delimiter || byte == DELIMITER_ESCAPE {
+                joined.push(DELIMITER_ESCAPE);
+            }
+            joined.push(byte);
+        }
+    }
+    joined
+}
+
+/// Splits payload joined by [join_delimited] back into items.
+/// Trailing delimiter doesn't start another item, and neither
+/// does an empty payload. Escape at the very end is taken as is.
+pub fn split_delimited(payload: &[u8], delimiter: u8) -> Vec<Vec<u8>> {
+    let mut items = Vec::new();
+    let mut item = Vec::new();
+    let mut bytes = payload.iter().copied();
+    while let Some(byte) = bytes.next() {
+        if byte == DELIMITER_ESCAPE {
+            item.push(bytes.next().unwrap_or(DELIMITER_ESCAPE));
+        } else if byte == delimiter {
+            items.push(std::mem::take(&mut item));
+        } else {
+            item.push(byte);
+        }
+    }
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+/// Splits batch into items, or returns `None` if it's malformed.
+fn decode_batch(mut batch: &[u8]) -> Option<Vec<&[u8]>> {
+    if batch.len() < BATCH_COUNT_LEN {
+        return None;
+    }
+    let count = u16::from_be_bytes([batch[0], batch[1]]) as usize;
+    batch = &batch[BATCH_COUNT_LEN..];
+
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        if batch.len() < BATCH_LENGTH_LEN {
+            return None;
+        }
+        let len = u16::from_be_bytes([batch[0], batch[1]]) as usize;
+        batch = &batch[BATCH_LENGTH_LEN..];
+        if batch.len() < len {
+            return None;
+        }
+        let (item, rest) = batch.split_at(len);
+        items.push(item);
+        batch = rest;
+    }
+    // Trailing garbage means count is wrong
+    if batch.is_empty() {
+        Some(items)
+    } else {
+        None
+    }
+}
+
+/// Collects options of a [Receiver] to apply once it's bound,
+/// starting with the same ones [Receiver::new] uses.
+#[derive(Debug, Clone)]
+pub struct ReceiverBuilder {
+    timeout: Option<Duration>,
+    max_payload: usize,
+    reuse_addr: bool,
+    source_filter: Option<IpAddr>,
+    delimiter: Option<u8>,
+    recv_buffer_size: Option<usize>,
+    /// IPv4 groups to join, along with interfaces.
+    multicast_v4: Vec<(Ipv4Addr, Ipv4Addr)>,
+    /// IPv6 groups to join, along with interface indices.
+    multicast_v6: Vec<(Ipv6Addr, u32)>,
+}
+
+impl Default for ReceiverBuilder {
+    fn default() -> Self {
+        Self {
+            timeout: Some(DEFAULT_TIMEOUT),
+            max_payload: UDP_MAX_PAYLOAD,
+            reuse_addr: false,
+            source_filter: None,
+            delimiter: None,
+            recv_buffer_size: None,
+            multicast_v4: Vec::new(),
+            multicast_v6: Vec::new(),
+        }
+    }
+}
+
+impl ReceiverBuilder {
+    /// Starts with the same options as [Receiver::new].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [Receiver::set_timeout].
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Size of the largest datagram receiver is able to read,
+    /// see [Receiver::set_max_payload]. If it's too small to fit
+    /// any headers, [ReceiverBuilder::bind] fails.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.max_payload = size;
+        self
+    }
+
+    /// Whether to set `SO_REUSEADDR`, see [Receiver::with_reuse_addr].
+    pub fn reuse_addr(mut self, on: bool) -> Self {
+        self.reuse_addr = on;
+        self
+    }
+
+    /// See [Receiver::set_source_filter].
+    pub fn source_filter(mut self, source: Option<IpAddr>) -> Self {
+        self.source_filter = source;
+        self
+    }
+
+    /// See [Receiver::set_delimiter]. If it's [DELIMITER_ESCAPE],
+    /// [ReceiverBuilder::bind] fails.
+    pub fn delimiter(mut self, delimiter: Option<u8>) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// See [Receiver::set_recv_buffer_size]. OS default is kept if
+    /// it's never called.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Joins IPv4 group once bound, see [Receiver::join_multicast_v4].
+    /// May be called several times to join several groups.
+    pub fn join_multicast_v4(mut self, multiaddr: Ipv4Addr, interface: Ipv4Addr) -> Self {
+        self.multicast_v4.push((multiaddr, interface));
+        self
+    }
+
+    /// Joins IPv6 group once bound, see [Receiver::join_multicast_v6].
+    pub fn join_multicast_v6(mut self, multiaddr: Ipv6Addr, interface: u32) -> Self {
+        self.multicast_v6.push((multiaddr, interface));
+        self
+    }
+
+    /// Binds to `addr` and applies all options. Builder is left intact,
+    /// so it can be used to try another address if this one fails.
+    /// Invalid options are reported as [std::io::ErrorKind::InvalidInput]
+    /// before anything is bound.
+    pub fn bind<T, A: ToSocketAddrs>(&self, addr: A) -> std::io::Result<Receiver<T>> {
+        check_max_payload(self.max_payload)?;
+        check_delimiter(self.delimiter)?;
+        let sock = if self.reuse_addr {
+            bind_reusable(addr)?
+        } else {
+            UdpSocket::bind(addr)?
+        };
+        let mut receiver = Receiver::from_socket(sock);
+        receiver.set_timeout(self.timeout)?;
+        receiver.set_max_payload(self.max_payload)?;
+        receiver.set_source_filter(self.source_filter);
+        receiver.set_delimiter(self.delimiter)?;
+        if let Some(size) = self.recv_buffer_size {
+            receiver.set_recv_buffer_size(size)?;
+        }
+        for (multiaddr, interface) in self.multicast_v4.iter() {
+            receiver.join_multicast_v4(*multiaddr, *interface)?;
+        }
+        for (multiaddr, interface) in self.multicast_v6.iter() {
+            receiver.join_multicast_v6(*multiaddr, *interface)?;
+        }
+        Ok(receiver)
+    }
+}
+
+/// Yields items parsed from datagrams arriving on a bound socket,
+/// undoing whatever framing [Sender] on the other side applied.
+pub struct Receiver<T> {
+    sock: UdpSocket,
+    /// Holds a single datagram, sized to maximum payload.
+    buf: Vec<u8>,
+    /// Present if fragmentation is enabled.
+    reassembler: Option<Reassembler>,
+    /// Whether payloads carry a checksum to validate.
+    checksum: bool,
+    /// Present if sequencing is enabled.
+    sequence: Option<SequenceTracker>,
+    /// Gaps detected while receiving the latest item.
+    last_sequence: SequenceInfo,
+    /// Whether payloads are batches of items.
+    batching: bool,
+    /// If set, payloads are items separated by this byte.
+    delimiter: Option<u8>,
+    /// Items of the latest batch or delimited payload that weren't yielded yet.
+    batched: VecDeque<(Vec<u8>, SocketAddr)>,
+    /// Whether to acknowledge every datagram to its sender.
+    acks: bool,
+    /// Whether to send every datagram back to its sender as is.
+    echo: bool,
+    /// If set, datagrams from other addresses are skipped.
+    source_filter: Option<IpAddr>,
+    /// Number of datagrams skipped because of source filter.
+    filtered: u64,
+    /// Whether kernel reports datagrams it dropped.
+    track_drops: bool,
+    /// Latest number of dropped datagrams reported by kernel.
+    os_dropped: u32,
+    /// Digest of every payload handed over for parsing.
+    digest: Digest,
+    phantom: PhantomData<T>,
+}
+
+// By hand, so that items don't have to be Debug themselves
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("sock", &self.sock)
+            .field("max_payload", &self.buf.len())
+            .field("fragmentation", &self.reassembler.is_some())
+            .field("checksum", &self.checksum)
+            .field("sequencing", &self.sequence.is_some())
+            .field("batching", &self.batching)
+            .field("delimiter", &self.delimiter)
+            .field("acks", &self.acks)
+            .field("echo", &self.echo)
+            .field("source_filter", &self.source_filter)
+            .field("filtered", &self.filtered)
+            .field("digest", &self.digest)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Binds to `addr` with default options, see [ReceiverBuilder]
+    /// for configuring them upfront.
+    pub fn new<A: std::net::ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        ReceiverBuilder::new().bind(addr)
+    }
+
+    /// Binds to `addr` with the given read timeout, after which
+    /// [Iterator::next] returns `None`. `None` disables the timeout,
+    /// so it blocks until a datagram arrives.
+    pub fn with_timeout<A: std::net::ToSocketAddrs>(
+        addr: A,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<Self> {
+        ReceiverBuilder::new().timeout(timeout).bind(addr)
+    }
+
+    /// Same as [Receiver::new], but sets `SO_REUSEADDR` on socket,
+    /// so that it can bind to a port in use by other such sockets.
+    pub fn with_reuse_addr<A: std::net::ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        ReceiverBuilder::new().reuse_addr(true).bind(addr)
+    }
+
+    /// Takes over an already bound socket, such as one with custom
+    /// options or inherited from parent process. Its read timeout
+    /// is kept, see [Receiver::set_timeout].
+    pub fn from_socket(sock: UdpSocket) -> Self {
+        Self {
+            sock,
+            buf: vec![0_u8; UDP_MAX_PAYLOAD],
+            reassembler: None,
+            checksum: false,
+            sequence: None,
+            last_sequence: SequenceInfo::default(),
+            batching: false,
+            delimiter: None,
+            batched: VecDeque::new(),
+            acks: false,
+            echo: false,
+            source_filter: None,
+            filtered: 0,
+            track_drops: false,
+            os_dropped: 0,
+            digest: Digest::default(),
+            phantom: PhantomData,
         }
     }
+
+    /// Turns receiver into one yielding another type of items,
+    /// keeping socket, settings and partially received data.
+    pub fn with_items<U>(self) -> Receiver<U> {
+        Receiver {
+            sock: self.sock,
+            buf: self.buf,
+            reassembler: self.reassembler,
+            checksum: self.checksum,
+            sequence: self.sequence,
+            last_sequence: self.last_sequence,
+            batching: self.batching,
+            delimiter: self.delimiter,
+            batched: self.batched,
+            acks: self.acks,
+            echo: self.echo,
+            source_filter: self.source_filter,
+            filtered: self.filtered,
+            track_drops: self.track_drops,
+            os_dropped: self.os_dropped,
+            digest: self.digest,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Address the socket is actually bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    /// Sets the size of the largest datagram we are able to receive.
+    /// Larger datagrams get truncated. Fails with
+    /// [std::io::ErrorKind::InvalidInput] if `max_payload` is too small
+    /// to fit any headers, leaving previous size in place.
+    pub fn set_max_payload(&mut self, max_payload: usize) -> std::io::Result<()> {
+        check_max_payload(max_payload)?;
+        self.buf.resize(max_payload, 0);
+        Ok(())
+    }
+
+    /// Enables or disables reassembly of fragmented messages.
+    /// Must match the setting of [Sender] on the other side.
+    pub fn set_fragmentation(&mut self, on: bool) {
+        self.reassembler = if on {
+            Some(Reassembler::default())
+        } else {
+            None
+        };
+    }
+
+    /// Enables or disables checksum validation.
+    /// Must match the setting of [Sender] on the other side.
+    pub fn set_checksum(&mut self, on: bool) {
+        self.checksum = on;
+    }
+
+    /// Enables or disables splitting payloads into batches of items.
+    /// Must match the setting of [Sender] on the other side.
+    pub fn set_batching(&mut self, on: bool) {
+        self.batching = on;
+        self.batched.clear();
+    }
+
+    /// Splits every payload on `delimiter` and yields items in between,
+    /// as joined by [join_delimited], or takes payload as a single item
+    /// if it's `None`, which is the default. Ignored while batching
+    /// is enabled. Fails with [std::io::ErrorKind::InvalidInput]
+    /// if `delimiter` is [DELIMITER_ESCAPE], leaving previous one in place.
+    pub fn set_delimiter(&mut self, delimiter: Option<u8>) -> std::io::Result<()> {
+        check_delimiter(delimiter)?;
+        self.delimiter = delimiter;
+        self.batched.clear();
+        Ok(())
+    }
+
+    /// Enables or disables acknowledging every datagram by sending
+    /// its sequence number back, as expected by [Sender::send_reliable].
+    /// Repeated datagrams are acknowledged again, but otherwise ignored.
+    /// Has no effect unless sequencing is enabled.
+    pub fn set_acks(&mut self, on: bool) {
+        self.acks = on;
+    }
+
+    /// Enables or disables sending every datagram back to its sender
+    /// unchanged, headers included, so that it can measure round-trip
+    /// time. Failures to send are only logged, and the datagram is
+    /// processed either way.
+    pub fn set_echo(&mut self, on: bool) {
+        self.echo = on;
+    }
+
+    /// Enables or disables tracking of sequence numbers.
+    /// Must match the setting of [Sender] on the other side.
+    pub fn set_sequencing(&mut self, on: bool) {
+        self.sequence = if on {
+            Some(SequenceTracker::default())
+        } else {
+            None
+        };
+    }
+
+    /// Skips datagrams that don't come from `source` without yielding
+    /// them, or accepts datagrams from anywhere if it's `None`.
+    /// Skipped datagrams are counted in [Receiver::filtered].
+    pub fn set_source_filter(&mut self, source: Option<IpAddr>) {
+        self.source_filter = source;
+    }
+
+    /// Number of datagrams skipped so far because of source filter.
+    pub fn filtered(&self) -> u64 {
+        self.filtered
+    }
+
+    /// Digest of every item received so far, intact or not, to
+    /// compare with [SendReport::digest] of the sender.
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    /// Sets size of socket receive buffer, which holds datagrams that
+    /// arrived but weren't read yet. Once it's full, further datagrams
+    /// are dropped, see [Receiver::os_dropped]. OS may round `size` up,
+    /// Linux doubles it to account for bookkeeping overhead.
+    pub fn set_recv_buffer_size(&self, size: usize) -> std::io::Result<()> {
+        SockRef::from(&self.sock).set_recv_buffer_size(size)
+    }
+
+    /// Actual size of socket receive buffer.
+    pub fn recv_buffer_size(&self) -> std::io::Result<usize> {
+        SockRef::from(&self.sock).recv_buffer_size()
+    }
+
+    /// Enables or disables counting datagrams kernel dropped because
+    /// receive buffer was full, see [Receiver::os_dropped]. Fails unless
+    /// [OS_DROPS_SUPPORTED].
+    pub fn set_drop_tracking(&mut self, on: bool) -> std::io::Result<()> {
+        overflow::enable(&self.sock, on)?;
+        self.track_drops = on;
+        Ok(())
+    }
+
+    /// Number of datagrams kernel dropped since socket was created, as
+    /// reported along with the latest datagram read, so drops only show up
+    /// once another datagram arrives after them. Always 0 unless
+    /// [Receiver::set_drop_tracking] is enabled, which isn't possible
+    /// on every platform.
+    pub fn os_dropped(&self) -> u32 {
+        self.os_dropped
+    }
+
+    /// Packet loss and reordering noticed while receiving the
+    /// latest item. Always empty unless sequencing is enabled.
+    pub fn last_sequence(&self) -> SequenceInfo {
+        self.last_sequence
+    }
+
+    /// Joins IPv4 multicast group `multiaddr` on `interface`
+    /// (which may be [Ipv4Addr::UNSPECIFIED] to let OS choose).
+    /// Receiver should be bound to the port datagrams are sent to,
+    /// e.g. `0.0.0.0:<group port>`.
+    pub fn join_multicast_v4(
+        &self,
+        multiaddr: Ipv4Addr,
+        interface: Ipv4Addr,
+    ) -> std::io::Result<()> {
+        self.sock.join_multicast_v4(&multiaddr, &interface)
+    }
+
+    /// Joins IPv6 multicast group `multiaddr` on interface with
+    /// given index (0 to let OS choose).
+    /// Receiver should be bound to the port datagrams are sent to,
+    /// e.g. `[::]:<group port>`.
+    pub fn join_multicast_v6(&self, multiaddr: Ipv6Addr, interface: u32) -> std::io::Result<()> {
+        self.sock.join_multicast_v6(&multiaddr, interface)
+    }
+
+    /// Reconfigures the read timeout of underlying socket.
+    /// `None` disables the timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
+
+impl<T> Receiver<T>
+where
+    T: FromUdpSource,
+{
+    /// Same as [Receiver::next_with_source], but returns [Poll::Pending]
+    /// instead of an error if nothing arrived before timeout, or at all
+    /// if socket is non-blocking.
+    pub fn try_next_with_source(&mut self) -> Poll<WithSource<T>> {
+        let track_drops = self.track_drops;
+        let mut os_dropped = None;
+        let res = self.poll_next_with(|sock, buf| {
+            let res = if track_drops {
+                overflow::recv_from(sock, buf).map(|(len, src, dropped)| {
+                    os_dropped = dropped.or(os_dropped);
+                    (len, src)
+                })
+            } else {
+                sock.recv_from(buf)
+            };
+            match res {
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    Poll::Pending
+                }
+                res => Poll::Ready(res),
+            }
+        });
+        if let Some(dropped) = os_dropped {
+            self.os_dropped = dropped;
+        }
+        res
+    }
+
+    /// Reads datagrams with `recv` until an item is complete,
+    /// or `recv` returns [Poll::Pending]. This lets other kinds
+    /// of sockets reuse the rest of receiving logic.
+    pub(crate) fn poll_next_with<F>(&mut self, mut recv: F) -> Poll<WithSource<T>>
+    where
+        F: FnMut(&UdpSocket, &mut [u8]) -> Poll<std::io::Result<(usize, SocketAddr)>>,
+    {
+        self.last_sequence = SequenceInfo::default();
+        if let Some((item, src)) = self.batched.pop_front() {
+            return Poll::Ready(Self::parse(&mut self.digest, &item, src));
+        }
+        loop {
+            let (len, src) = match recv(&self.sock, &mut self.buf) {
+                Poll::Ready(Ok(received)) => received,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            };
+            if self.source_filter.is_some() && self.source_filter != Some(src.ip()) {
+                self.filtered += 1;
+                continue;
+            }
+            if self.echo {
+                if let Err(e) = self.sock.send_to(&self.buf[..len], src) {
+                    warn!(
+                        src:% = src, error:% = e, kind:? = e.kind();
+                        "Couldn't echo datagram to {}: {}", src, e
+                    );
+                }
+            }
+
+            // Tracked and acknowledged only once datagram proves intact,
+            // so that a corrupted one gets retransmitted
+            let mut datagram = &self.buf[..len];
+            let mut seq = None;
+            if let Some(ref tracker) = self.sequence {
+                if datagram.len() < SEQUENCE_LEN {
+                    return Poll::Ready(Err(Error::MissingSequence));
+                }
+                let (seq_bytes, rest) = datagram.split_at(SEQUENCE_LEN);
+                let num =
+                    u32::from_le_bytes([seq_bytes[0], seq_bytes[1], seq_bytes[2], seq_bytes[3]]);
+                if self.acks && tracker.last.get(&src) == Some(&num) {
+                    // Retransmitted because our ACK got lost
+                    acknowledge(&self.sock, src, num);
+                    continue;
+                }
+                seq = Some(num);
+                datagram = rest;
+            }
+
+            let reassembled;
+            let message = match self.reassembler {
+                None => datagram,
+                Some(ref mut reassembler) => match FragmentHeader::parse(datagram) {
+                    Some((header, data)) => match reassembler.push(src, header, data) {
+                        Some(message) => {
+                            reassembled = message;
+                            &reassembled[..]
+                        }
+                        None => {
+                            if let (Some(tracker), Some(seq)) = (self.sequence.as_mut(), seq) {
+                                tracker.accept(
+                                    &self.sock,
+                                    self.acks,
+                                    src,
+                                    seq,
+                                    &mut self.last_sequence,
+                                );
+                            }
+                            continue;
+                        }
+                    },
+                    None => return Poll::Ready(Err(Error::BadFragment)),
+                },
+            };
+
+            let message = if self.checksum {
+                match verify_checksum(message) {
+                    Some(payload) => payload,
+                    None => return Poll::Ready(Err(Error::ChecksumMismatch)),
+                }
+            } else {
+                message
+            };
+            if let (Some(tracker), Some(seq)) = (self.sequence.as_mut(), seq) {
+                tracker.accept(&self.sock, self.acks, src, seq, &mut self.last_sequence);
+            }
+
+            if self.batching {
+                let items = match decode_batch(message) {
+                    Some(items) => items,
+                    None => return Poll::Ready(Err(Error::BadBatch)),
+                };
+                self.batched
+                    .extend(items.into_iter().map(|item| (item.to_vec(), src)));
+                match self.batched.pop_front() {
+                    Some((item, src)) => {
+                        return Poll::Ready(Self::parse(&mut self.digest, &item, src))
+                    }
+                    // Nothing to yield from an empty batch
+                    None => continue,
+                }
+            }
+
+            if let Some(delimiter) = self.delimiter {
+                let items = split_delimited(message, delimiter);
+                self.batched
+                    .extend(items.into_iter().map(|item| (item, src)));
+                match self.batched.pop_front() {
+                    Some((item, src)) => {
+                        return Poll::Ready(Self::parse(&mut self.digest, &item, src))
+                    }
+                    None => continue,
+                }
+            }
+
+            return Poll::Ready(Self::parse(&mut self.digest, message, src));
+        }
+    }
+
+    /// Same as [Receiver::try_next_with_source], without the address.
+    pub fn try_next(&mut self) -> Poll<Result<T, Error<T::Error>>> {
+        self.try_next_with_source()
+            .map(|res| res.map(|(val, _)| val))
+    }
+
+    /// Same as [Iterator::next], but also returns the address item
+    /// came from. Payloads that fail to parse carry it in
+    /// [Error::ParseError].
+    pub fn next_with_source(&mut self) -> Option<WithSource<T>> {
+        match self.try_next_with_source() {
+            Poll::Ready(res) => Some(res),
+            Poll::Pending => None,
+        }
+    }
+
+    /// Parses item from payload that came from `src`,
+    /// adding it to `digest`.
+    fn parse(digest: &mut Digest, message: &[u8], src: SocketAddr) -> WithSource<T> {
+        digest.update(message);
+        T::from_udp_source(message, src)
+            .map(|val| (val, src))
+            .map_err(|source| Error::ParseError {
+                source,
+                raw: message.to_vec(),
+                src,
+            })
+    }
+}
+
+/// Running totals of a [Receiver] that only counts what arrives.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TrafficCounts {
+    /// Number of items received intact.
+    pub items: u64,
+    /// Bytes of their payloads, without any headers.
+    pub bytes: u64,
+    /// Number of datagrams found missing by sequence numbers.
+    pub lost: u64,
+    /// Number of datagrams that arrived out of order.
+    pub out_of_order: u64,
+    /// Number of datagrams that failed validation.
+    pub corrupted: u64,
+}
+
+impl Receiver<PayloadLen> {
+    /// Waits for the next item like [Receiver::try_next_with_source],
+    /// adding it to `counts` instead of returning it. Socket errors
+    /// are passed on, anything else just counts as corrupted.
+    pub fn try_count(&mut self, counts: &mut TrafficCounts) -> Poll<std::io::Result<()>> {
+        let res = match self.try_next_with_source() {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Poll::Pending,
+        };
+        counts.lost += u64::from(self.last_sequence.lost_since_last);
+        counts.out_of_order += u64::from(self.last_sequence.out_of_order);
+        match res {
+            Ok((PayloadLen(len), _)) => {
+                counts.items += 1;
+                counts.bytes += len as u64;
+            }
+            Err(Error::Io(e)) => return Poll::Ready(Err(e)),
+            Err(Error::ParseError { source, .. }) => match source {},
+            Err(_) => counts.corrupted += 1,
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Yields received items along with any errors, and returns `None`
+/// if nothing arrives before timeout. Timeouts are never yielded as errors.
+/// Iteration may be resumed after `None` to keep waiting.
+impl<T> Iterator for Receiver<T>
+where
+    T: FromUdpSource,
+{
+    type Item = Result<T, Error<T::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_source().map(|res| res.map(|(val, _)| val))
+    }
+}
+
+/// Summary of a [Sender::send] call.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SendReport {
+    /// Number of items transmitted.
+    pub items_sent: usize,
+    /// Number of bytes written to socket, including any headers.
+    pub bytes_sent: usize,
+    /// Number of items that didn't fit into a datagram and were truncated.
+    pub truncated: usize,
+    /// Number of items too large for any batch, which weren't sent.
+    pub dropped: usize,
+    /// Number of items lost to socket errors in best-effort mode.
+    pub failed: usize,
+    /// Socket errors skipped over in best-effort mode, in order.
+    pub errors: Vec<std::io::ErrorKind>,
+    /// Durations of socket writes, if [Sender::set_timing] is enabled.
+    pub timing: Option<SendTiming>,
+    /// What went to each destination, if sent with [Sender::send_to]
+    /// or [Sender::send_to_all], in order destinations were given.
+    pub destinations: Vec<DestinationReport>,
+    /// Digest of items as serialized, before any truncation,
+    /// whether they were sent or not.
+    pub digest: Digest,
+}
+
+/// Datagrams sent to a single destination by [Sender::send_to_all].
+/// Counts of [SendReport] include a datagram once, however many
+/// destinations it went to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DestinationReport {
+    /// Address datagrams were sent to.
+    pub addr: SocketAddr,
+    /// Number of datagrams written to socket.
+    pub datagrams: usize,
+    /// Number of bytes written to socket, including any headers.
+    pub bytes_sent: usize,
+    /// Number of datagrams that failed to send.
+    pub failed: usize,
+    /// Error of the latest failure, if any.
+    pub last_error: Option<std::io::ErrorKind>,
+}
+
+impl DestinationReport {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            datagrams: 0,
+            bytes_sent: 0,
+            failed: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// How many of the slowest writes [SendTiming] keeps.
+pub const SLOWEST_KEPT: usize = 5;
+
+/// Summary of how long socket writes took, to spot stalls.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SendTiming {
+    /// Number of writes measured.
+    pub writes: usize,
+    /// Time spent in all of them.
+    pub total: Duration,
+    /// Durations of up to [SLOWEST_KEPT] slowest writes, slowest first.
+    pub slowest: Vec<Duration>,
+}
+
+impl SendTiming {
+    /// Accounts for a write that took `elapsed`.
+    pub fn add(&mut self, elapsed: Duration) {
+        self.writes += 1;
+        self.total += elapsed;
+        let pos = self
+            .slowest
+            .iter()
+            .position(|&slow| elapsed > slow)
+            .unwrap_or(self.slowest.len());
+        if pos < SLOWEST_KEPT {
+            self.slowest.insert(pos, elapsed);
+            self.slowest.truncate(SLOWEST_KEPT);
+        }
+    }
+
+    /// Average write duration, 0 if none were measured.
+    pub fn average(&self) -> Duration {
+        if self.writes == 0 {
+            Duration::from_secs(0)
+        } else {
+            self.total / self.writes as u32
+        }
+    }
+}
+
+/// How [Sender::send_reliable] waits for acknowledgements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryConfig {
+    /// How long to wait for an ACK before retransmitting.
+    pub timeout: Duration,
+    /// How many times to retransmit a datagram before giving up.
+    pub retries: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            retries: 3,
+        }
+    }
+}
+
+/// Statistics of datagrams written by a [Sender], including
+/// sequence numbers and other headers. Unlike [SendReport],
+/// accumulates over several sends.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    /// Number of datagrams written.
+    pub datagrams: usize,
+    /// Total size of datagrams written.
+    pub bytes: usize,
+    /// Size of the smallest datagram, 0 if none were written.
+    pub min_payload: usize,
+    /// Size of the largest datagram.
+    pub max_payload: usize,
+    /// Number of items truncated to fit into a datagram.
+    pub truncated: usize,
+    /// Number of datagrams that failed to be written.
+    pub errors: usize,
+}
+
+impl Stats {
+    /// Average datagram size, 0 if none were written.
+    pub fn avg_payload(&self) -> f64 {
+        if self.datagrams == 0 {
+            0.0
+        } else {
+            self.bytes as f64 / self.datagrams as f64
+        }
+    }
+
+    fn add_datagram(&mut self, len: usize) {
+        self.min_payload = if self.datagrams == 0 {
+            len
+        } else {
+            self.min_payload.min(len)
+        };
+        self.max_payload = self.max_payload.max(len);
+        self.datagrams += 1;
+        self.bytes += len;
+    }
+}
+
+/// Given previous deadline, returns how long to wait before sending
+/// at `now`, and deadline for the datagram after that. Deadlines are
+/// spaced evenly, so time spent preparing payloads counts towards
+/// the interval.
+pub(crate) fn schedule(
+    now: Instant,
+    next_send: Option<Instant>,
+    interval: Duration,
+) -> (Duration, Instant) {
+    match next_send {
+        Some(deadline) if deadline > now => (deadline - now, deadline + interval),
+        // Don't try to catch up if we fell behind.
+        _ => (Duration::from_secs(0), now + interval),
+    }
+}
+
+/// Returns how long to wait between items timestamped `last` and `next`,
+/// in milliseconds, to replay them `speed` times faster than they came.
+/// Timestamps going backwards mean no wait, and waits are capped
+/// at [MAX_REPLAY_GAP].
+pub(crate) fn replay_gap(last: u64, next: u64, speed: f64) -> Duration {
+    let gap = next.saturating_sub(last) as f64 / 1000.0 / speed;
+    // Also takes care of speeds that aren't positive
+    Duration::from_secs_f64(gap.min(MAX_REPLAY_GAP.as_secs_f64()).max(0.0))
+}
+
+/// Stand-in for [UdpSocket::send] in tests.
+#[cfg(test)]
+type SendHook = Box<dyn FnMut(&[u8]) -> std::io::Result<usize> + Send>;
+
+/// Sends items from a bound socket, one or more per datagram,
+/// framed as configured.
+pub struct Sender {
+    sock: UdpSocket,
+    /// Size of the largest datagram we are allowed to send.
+    max_payload: usize,
+    /// Whether to split large items across several datagrams
+    /// instead of truncating them.
+    fragment: bool,
+    /// Id of the next fragmented message.
+    next_message: u32,
+    /// Whether to append a checksum to every item.
+    checksum: bool,
+    /// Sequence number of the next datagram, if sequencing is enabled.
+    sequence: Option<u32>,
+    /// Minimal interval between datagrams, if rate is limited.
+    interval: Option<Duration>,
+    /// When the next datagram is allowed to go out.
+    next_send: Option<Instant>,
+    /// How many times every datagram is written.
+    copies: u32,
+    /// Items waiting to be sent together, if batching is enabled.
+    batch: Option<Batch>,
+    /// Datagrams that would have been sent, if dry run is enabled.
+    dry_run: Option<Vec<Vec<u8>>>,
+    /// Whether to keep going after a socket error.
+    best_effort: bool,
+    /// Where to send datagrams along with what went to each,
+    /// or empty to send to connected address.
+    dests: Vec<DestinationReport>,
+    /// Present while sending with [Sender::send_reliable].
+    retry: Option<RetryConfig>,
+    /// Accumulated over all sends.
+    stats: Stats,
+    /// Reused to serialize every item.
+    scratch: Vec<u8>,
+    /// Collects durations of socket writes during a send, if enabled.
+    timing: Option<SendTiming>,
+    /// Replaces socket writes, so tests can inject failures.
+    #[cfg(test)]
+    send_hook: Option<SendHook>,
+}
+
+/// Collects socket options of a [Sender] to apply once it's bound,
+/// like [ReceiverBuilder] does for [Receiver].
+#[derive(Debug, Clone, Default)]
+pub struct SenderBuilder {
+    broadcast: bool,
+    /// OS default is kept if it's not set.
+    ttl: Option<u32>,
+    /// Minimal interval between datagrams, if rate is limited.
+    interval: Option<Duration>,
+    reuse_addr: bool,
+}
+
+impl SenderBuilder {
+    /// Starts with the same options as [Sender::new].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [Sender::set_broadcast].
+    pub fn broadcast(mut self, on: bool) -> Self {
+        self.broadcast = on;
+        self
+    }
+
+    /// See [Sender::set_ttl].
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// See [Sender::set_rate]. Replaces interval set before.
+    pub fn rate(mut self, packets_per_sec: Option<u32>) -> Self {
+        self.interval = packets_per_sec
+            .filter(|rate| *rate > 0)
+            .map(|rate| Duration::from_secs(1) / rate);
+        self
+    }
+
+    /// See [Sender::set_interval]. Replaces rate set before.
+    pub fn interval(mut self, interval: Option<Duration>) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Whether to set `SO_REUSEADDR`, see [Sender::with_reuse_addr].
+    pub fn reuse_addr(mut self, on: bool) -> Self {
+        self.reuse_addr = on;
+        self
+    }
+
+    /// Binds to `addr` and applies all options. Builder is left intact,
+    /// so it can be used to try another address if this one fails.
+    pub fn bind<A: ToSocketAddrs>(&self, addr: A) -> std::io::Result<Sender> {
+        let mut sender = if self.reuse_addr {
+            Sender::with_reuse_addr(addr)?
+        } else {
+            Sender::new(addr)?
+        };
+        sender.set_broadcast(self.broadcast)?;
+        if let Some(ttl) = self.ttl {
+            sender.set_ttl(ttl)?;
+        }
+        sender.set_interval(self.interval);
+        Ok(sender)
+    }
+
+    /// Same as [SenderBuilder::bind], on a port picked by OS,
+    /// see [Sender::new_ephemeral].
+    pub fn bind_ephemeral(&self) -> std::io::Result<Sender> {
+        self.bind((Ipv4Addr::UNSPECIFIED, 0))
+    }
+}
+
+impl fmt::Debug for Sender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("sock", &self.sock)
+            .field("max_payload", &self.max_payload)
+            .field("fragment", &self.fragment)
+            .field("checksum", &self.checksum)
+            .field("sequence", &self.sequence)
+            .field("interval", &self.interval)
+            .field("copies", &self.copies)
+            .field("batching", &self.batch.is_some())
+            .field("dry_run", &self.dry_run.is_some())
+            .field("best_effort", &self.best_effort)
+            .field("dests", &self.dests)
+            .field("retry", &self.retry)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sender {
+    /// Binds to `addr` with default options, see [SenderBuilder]
+    /// for configuring them upfront and [Sender::bind_to] for picking
+    /// the interface datagrams leave from.
+    pub fn new<A>(addr: A) -> std::io::Result<Self>
+    where
+        A: std::net::ToSocketAddrs,
+    {
+        Ok(Self::from_socket(UdpSocket::bind(addr)?))
+    }
+
+    /// Same as [Sender::new], but sets `SO_REUSEADDR` on socket,
+    /// so that it can bind to a port in use by other such sockets.
+    pub fn with_reuse_addr<A>(addr: A) -> std::io::Result<Self>
+    where
+        A: std::net::ToSocketAddrs,
+    {
+        Ok(Self::from_socket(bind_reusable(addr)?))
+    }
+
+    /// Takes over an already bound socket, such as one with custom
+    /// options or inherited from parent process.
+    pub fn from_socket(sock: UdpSocket) -> Self {
+        Self {
+            sock,
+            max_payload: UDP_MAX_PAYLOAD,
+            fragment: false,
+            next_message: 0,
+            checksum: false,
+            sequence: None,
+            interval: None,
+            next_send: None,
+            copies: 1,
+            batch: None,
+            dry_run: None,
+            best_effort: false,
+            dests: Vec::new(),
+            retry: None,
+            stats: Stats::default(),
+            scratch: Vec::new(),
+            timing: None,
+            #[cfg(test)]
+            send_hook: None,
+        }
+    }
+
+    /// Binds to `local`, so that datagrams leave from its IP. On hosts
+    /// with several interfaces, this picks the one they go out of, as
+    /// far as routing table allows. Port 0 lets OS pick a free one.
+    /// Fails with [std::io::ErrorKind::AddrNotAvailable] if IP isn't
+    /// assigned to any interface of this host.
+    pub fn bind_to(local: SocketAddr) -> std::io::Result<Self> {
+        Self::new(local)
+    }
+
+    /// Binds to all interfaces on a port picked by OS.
+    pub fn new_ephemeral() -> std::io::Result<Self> {
+        Self::new((Ipv4Addr::UNSPECIFIED, 0))
+    }
+
+    /// Address the socket is actually bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    /// Sets the size of the largest datagram we are allowed to send.
+    /// Defaults to [UDP_MAX_PAYLOAD]. Fails with
+    /// [std::io::ErrorKind::InvalidInput] if `max_payload` is too small
+    /// to fit any headers, leaving previous size in place.
+    pub fn set_max_payload(&mut self, max_payload: usize) -> std::io::Result<()> {
+        check_max_payload(max_payload)?;
+        self.max_payload = max_payload;
+        Ok(())
+    }
+
+    /// Allows or forbids sending to broadcast addresses,
+    /// such as `255.255.255.255`.
+    pub fn set_broadcast(&self, on: bool) -> std::io::Result<()> {
+        self.sock.set_broadcast(on)
+    }
+
+    /// Sets time-to-live of outgoing datagrams.
+    pub fn set_ttl(&self, ttl: u32) -> std::io::Result<()> {
+        self.sock.set_ttl(ttl)
+    }
+
+    /// Enables or disables fragmentation. When enabled, every item
+    /// is prefixed with a fragment header and items larger than
+    /// a single datagram are split instead of truncated.
+    /// The receiving side must enable it too.
+    pub fn set_fragmentation(&mut self, on: bool) {
+        self.fragment = on;
+    }
+
+    /// Enables or disables appending CRC32 checksum to every item.
+    /// The receiving side must enable it too.
+    pub fn set_checksum(&mut self, on: bool) {
+        self.checksum = on;
+    }
+
+    /// Enables or disables packing as many items as fit into a single
+    /// datagram. Batches are never split or truncated, so items too
+    /// large to fit into a datagram on their own are dropped instead.
+    /// With fragmentation enabled, every batch still goes out as
+    /// a single fragment, so that receiver can put it back together.
+    /// The receiving side must enable it too.
+    pub fn set_batching(&mut self, on: bool) {
+        self.batch = if on { Some(Batch::default()) } else { None };
+    }
+
+    /// Enables or disables dry run, in which datagrams are collected
+    /// instead of being sent, to be inspected with [Sender::take_dry_run].
+    /// Rate limit doesn't apply to them.
+    pub fn set_dry_run(&mut self, on: bool) {
+        self.dry_run = if on { Some(Vec::new()) } else { None };
+    }
+
+    /// Enables or disables best-effort mode, in which an item that
+    /// couldn't be written to socket is counted in [SendReport::failed]
+    /// and sending carries on with the next one. Otherwise the first
+    /// error aborts [Sender::send].
+    pub fn set_best_effort(&mut self, on: bool) {
+        self.best_effort = on;
+    }
+
+    /// Enables or disables measuring how long every socket write takes,
+    /// which is then summarized in [SendReport::timing].
+    pub fn set_timing(&mut self, on: bool) {
+        self.timing = if on {
+            Some(SendTiming::default())
+        } else {
+            None
+        };
+    }
+
+    /// Statistics of datagrams sent since creation or [Sender::reset_stats].
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Starts collecting statistics from scratch.
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Returns datagrams collected during dry run since the last call,
+    /// exactly as they would have been sent.
+    pub fn take_dry_run(&mut self) -> Vec<Vec<u8>> {
+        self.dry_run
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Enables or disables prefixing every datagram with an increasing
+    /// sequence number, so that receiver can detect loss and reordering.
+    /// The receiving side must enable it too.
+    pub fn set_sequencing(&mut self, on: bool) {
+        self.sequence = if on { Some(0) } else { None };
+    }
+
+    /// Limits outgoing traffic to given number of datagrams per second.
+    /// `None` or zero removes the limit.
+    pub fn set_rate(&mut self, packets_per_sec: Option<u32>) {
+        self.set_interval(
+            packets_per_sec
+                .filter(|rate| *rate > 0)
+                .map(|rate| Duration::from_secs(1) / rate),
+        );
+    }
+
+    /// Same as [Sender::set_rate], but takes delay between
+    /// consecutive datagrams. `None` or zero removes the limit.
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval.filter(|interval| *interval > Duration::from_secs(0));
+        self.next_send = None;
+    }
+
+    /// Writes every datagram `copies` times in a row, to see how
+    /// receiver copes with duplicates. Copies share sequence number
+    /// and fragment header, and each of them counts against rate limit.
+    /// Defaults to 1, as does zero.
+    pub fn set_copies(&mut self, copies: u32) {
+        self.copies = copies.max(1);
+    }
+
+    /// Sleeps until next datagram is allowed to go out.
+    fn throttle(&mut self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let (wait, deadline) = schedule(Instant::now(), self.next_send, interval);
+        if wait > Duration::from_secs(0) {
+            std::thread::sleep(wait);
+        }
+        self.next_send = Some(deadline);
+    }
+
+    /// Largest item that fits into a single datagram
+    /// alongside enabled headers.
+    fn max_item_len(&self) -> usize {
+        match self.sequence {
+            Some(_) => self.max_payload - SEQUENCE_LEN,
+            None => self.max_payload,
+        }
+    }
+
+    /// Largest batch that fits into a single datagram
+    /// alongside its checksum and fragment header.
+    fn max_batch_len(&self) -> usize {
+        let mut len = self.max_item_len();
+        if self.checksum {
+            len -= CHECKSUM_LEN;
+        }
+        if self.fragment {
+            len -= FRAGMENT_HEADER_LEN;
+        }
+        len
+    }
+
+    /// Largest serialized item that is sent whole, without
+    /// being truncated or dropped, when fragmentation is off.
+    pub fn item_capacity(&self) -> usize {
+        if self.batch.is_some() {
+            // MIN_PAYLOAD leaves room for batch headers, this only
+            // keeps a future header from wrapping capacity around
+            self.max_batch_len()
+                .saturating_sub(BATCH_COUNT_LEN + BATCH_LENGTH_LEN)
+        } else {
+            self.max_batch_len()
+        }
+    }
+
+    /// Adds encoded item to pending batch, sending the batch first
+    /// if item doesn't fit into it.
+    fn batch_item(&mut self, item: &[u8], report: &mut SendReport) -> std::io::Result<()> {
+        let max_len = self.max_batch_len();
+        if BATCH_COUNT_LEN + BATCH_LENGTH_LEN + item.len() > max_len
+            || item.len() > u16::MAX as usize
+        {
+            warn!(bytes = item.len(), max = max_len; "Item too large for any batch, dropped");
+            report.dropped += 1;
+            return Ok(());
+        }
+        let pending = self.batch.as_ref().expect("Batching is enabled");
+        if pending.len() + BATCH_LENGTH_LEN + item.len() > max_len {
+            self.flush_batch(report)?;
+        }
+        self.batch.as_mut().expect("Batching is enabled").push(item);
+        Ok(())
+    }
+
+    /// Drops items left over from a failed send.
+    pub(crate) fn discard_batch(&mut self) {
+        if let Some(ref mut pending) = self.batch {
+            pending.clear();
+        }
+    }
+
+    /// Sends pending batch, if there is one.
+    pub(crate) fn flush_batch(&mut self, report: &mut SendReport) -> std::io::Result<()> {
+        let (mut payload, items) = match self.batch {
+            Some(ref mut pending) if pending.items > 0 => {
+                (pending.encode(), pending.items as usize)
+            }
+            _ => return Ok(()),
+        };
+        if self.checksum {
+            append_checksum(&mut payload);
+        }
+        let res = if self.fragment {
+            self.transmit_fragmented(&payload)
+        } else {
+            self.transmit(&payload)
+        };
+        if let Some(ref mut pending) = self.batch {
+            pending.reset(payload);
+        }
+        if let Some(len) = self.tolerate(res, items, report)? {
+            report.bytes_sent += len;
+            report.items_sent += items;
+        }
+        Ok(())
+    }
+
+    /// In best-effort mode, records a failure of `items` items in
+    /// `report` and turns it into `None`. Otherwise passes `res` on.
+    fn tolerate<T>(
+        &self,
+        res: std::io::Result<T>,
+        items: usize,
+        report: &mut SendReport,
+    ) -> std::io::Result<Option<T>> {
+        match res {
+            Ok(val) => Ok(Some(val)),
+            Err(e) if self.best_effort => {
+                warn!(
+                    items, error:% = e, kind:? = e.kind();
+                    "Failed to send {} item(s): {}", items, e
+                );
+                report.failed += items;
+                report.errors.push(e.kind());
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends a single datagram to current destination, respecting
+    /// rate limit and prefixing it with sequence number if needed.
+    fn transmit(&mut self, datagram: &[u8]) -> std::io::Result<usize> {
+        let framed;
+        let mut sent_seq = None;
+        let datagram = match self.sequence {
+            Some(ref mut seq) => {
+                framed = [&seq.to_le_bytes()[..], datagram].concat();
+                sent_seq = Some(*seq);
+                *seq = seq.wrapping_add(1);
+                &framed[..]
+            }
+            None => datagram,
+        };
+        let mut sent = 0;
+        for _ in 0..self.copies {
+            let res = match self.dry_run {
+                Some(ref mut collected) => {
+                    collected.push(datagram.to_vec());
+                    Ok(datagram.len())
+                }
+                None => {
+                    self.throttle();
+                    match (self.retry, sent_seq) {
+                        (Some(retry), Some(seq)) => self.write_acked(datagram, seq, retry),
+                        _ => self.write(datagram),
+                    }
+                }
+            };
+            match res {
+                Ok(len) => {
+                    self.stats.add_datagram(len);
+                    sent += len;
+                }
+                Err(e) => {
+                    self.stats.errors += 1;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Writes a datagram to socket until its sequence number
+    /// is acknowledged, or retries run out.
+    fn write_acked(
+        &mut self,
+        datagram: &[u8],
+        seq: u32,
+        retry: RetryConfig,
+    ) -> std::io::Result<usize> {
+        let prev_timeout = self.sock.read_timeout()?;
+        let res = self.await_ack(datagram, seq, retry);
+        self.sock.set_read_timeout(prev_timeout)?;
+        res
+    }
+
+    /// Does the work of [Sender::write_acked], leaving
+    /// read timeout of socket for it to restore.
+    fn await_ack(
+        &mut self,
+        datagram: &[u8],
+        seq: u32,
+        retry: RetryConfig,
+    ) -> std::io::Result<usize> {
+        // Only whoever we send to may acknowledge
+        let peers: Vec<SocketAddr> = if self.dests.is_empty() {
+            vec![self.sock.peer_addr()?]
+        } else {
+            self.dests.iter().map(|dest| dest.addr).collect()
+        };
+        let mut ack = [0_u8; ACK_LEN];
+        for attempt in 0..=retry.retries {
+            if attempt > 0 {
+                warn!(seq, attempt; "No ACK for datagram {}, retransmitting", seq);
+            }
+            let len = self.write(datagram)?;
+            let deadline = Instant::now() + retry.timeout;
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                self.sock.set_read_timeout(Some(deadline - now))?;
+                match self.sock.recv_from(&mut ack) {
+                    Ok((ACK_LEN, src))
+                        if u32::from_le_bytes(ack) == seq && peers.contains(&src) =>
+                    {
+                        return Ok(len)
+                    }
+                    // ACK of an earlier datagram that came late,
+                    // or a stray datagram from someone else
+                    Ok(_) => {}
+                    Err(e)
+                        if e.kind() == std::io::ErrorKind::WouldBlock
+                            || e.kind() == std::io::ErrorKind::TimedOut =>
+                    {
+                        break
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!(
+                "No ACK for datagram {} after {} retries",
+                seq, retry.retries
+            ),
+        ))
+    }
+
+    /// Writes a datagram to socket, timing it if enabled.
+    fn write(&mut self, datagram: &[u8]) -> std::io::Result<usize> {
+        let start = Instant::now();
+        let res = self.write_socket(datagram);
+        if let Some(ref mut timing) = self.timing {
+            timing.add(start.elapsed());
+        }
+        res
+    }
+
+    fn write_socket(&mut self, datagram: &[u8]) -> std::io::Result<usize> {
+        #[cfg(test)]
+        {
+            if let Some(ref mut hook) = self.send_hook {
+                return hook(datagram);
+            }
+        }
+        if self.dests.is_empty() {
+            return self.sock.send(datagram);
+        }
+        // Datagram counts as sent if any destination got it
+        let (mut sent, mut error) = (None, None);
+        for dest in self.dests.iter_mut() {
+            match self.sock.send_to(datagram, dest.addr) {
+                Ok(len) => {
+                    dest.datagrams += 1;
+                    dest.bytes_sent += len;
+                    sent = Some(len);
+                }
+                Err(e) => {
+                    dest.failed += 1;
+                    dest.last_error = Some(e.kind());
+                    error = Some(e);
+                }
+            }
+        }
+        match (sent, error) {
+            (Some(len), _) => Ok(len),
+            (None, Some(e)) => Err(e),
+            (None, None) => unreachable!("There is at least one destination"),
+        }
+    }
+
+    /// Resolves `dest` and connects socket to it, so that only
+    /// datagrams from `dest` can be received on it, and errors reported
+    /// by `dest`'s host, such as closed port, fail subsequent sends.
+    /// Connected socket also skips route lookup for every datagram.
+    pub fn connect<A: ToSocketAddrs>(&mut self, dest: A) -> std::io::Result<()> {
+        let dest = resolve_for(self.sock.local_addr()?, dest)?;
+        self.sock.connect(dest)?;
+        self.dests.clear();
+        Ok(())
+    }
+
+    /// Checks whether `dest` appears reachable, by connecting to it,
+    /// sending an empty datagram and waiting up to `timeout` for an
+    /// ICMP error to be reported on socket. Returns `false` if `dest`'s
+    /// host refused it, which usually means nothing listens on the port.
+    ///
+    /// This is best effort only: hosts and firewalls may drop datagrams
+    /// silently, and some platforms never report ICMP errors, so `true`
+    /// doesn't mean anything will receive what's sent. The empty datagram
+    /// does reach listener if there is one, which may report it as corrupted.
+    pub fn probe<A: ToSocketAddrs>(&mut self, dest: A, timeout: Duration) -> std::io::Result<bool> {
+        self.connect(dest)?;
+        self.sock.send(&[])?;
+        let prev_timeout = self.sock.read_timeout()?;
+        self.sock.set_read_timeout(Some(timeout))?;
+        let res = self.sock.recv(&mut [0_u8; ACK_LEN]);
+        self.sock.set_read_timeout(prev_timeout)?;
+        match res {
+            // Whoever answered is there
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => Ok(false),
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                Ok(true)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends a single item to current destination,
+    /// adding its statistics to `report`.
+    pub(crate) fn send_item<T: ToUdp>(
+        &mut self,
+        item: &T,
+        report: &mut SendReport,
+    ) -> std::io::Result<()> {
+        let mut buf = std::mem::take(&mut self.scratch);
+        buf.clear();
+        item.to_udp_into(&mut buf);
+        report.digest.update(&buf);
+        let res = if self.batch.is_some() {
+            self.batch_item(&buf, report)
+        } else {
+            if self.checksum {
+                append_checksum(&mut buf);
+            }
+            let index = report.items_sent + report.failed;
+            let res = self
+                .send_single(&buf, report)
+                .map_err(|e| explain_too_long(e, item.item_id(), index, buf.len()));
+            self.tolerate(res, 1, report).map(|_| ())
+        };
+        self.scratch = buf;
+        res
+    }
+
+    /// Sends a prepared item on its own, fragmenting
+    /// or truncating it as needed.
+    fn send_single(&mut self, item: &[u8], report: &mut SendReport) -> std::io::Result<()> {
+        if self.fragment {
+            report.bytes_sent += self.transmit_fragmented(item)?;
+        } else if item.len() > self.max_item_len() {
+            warn!(
+                bytes = item.len(), max = self.max_item_len();
+                "Item too large, truncated"
+            );
+            report.bytes_sent += self.transmit(&item[..self.max_item_len()])?;
+            report.truncated += 1;
+            self.stats.truncated += 1;
+        } else {
+            report.bytes_sent += self.transmit(item)?;
+        }
+        report.items_sent += 1;
+        Ok(())
+    }
+
+    /// Sends `message` split into as many fragments as it takes.
+    fn transmit_fragmented(&mut self, message: &[u8]) -> std::io::Result<usize> {
+        let id = self.next_message;
+        self.next_message = self.next_message.wrapping_add(1);
+        let mut sent = 0;
+        for datagram in fragment(message, id, self.max_item_len())? {
+            sent += self.transmit(&datagram)?;
+        }
+        Ok(sent)
+    }
+
+    /// Sends every item of `iter`, either owned or borrowed.
+    pub fn send<I, A>(&mut self, iter: I, dest: A) -> std::io::Result<SendReport>
+    where
+        I: IntoIterator,
+        I::Item: ToUdp,
+        A: ToSocketAddrs,
+    {
+        self.connect(dest)?;
+        self.send_all(iter)
+    }
+
+    /// Same as [Sender::send], but takes items along with their timestamps
+    /// in milliseconds, and replays them with original gaps between them,
+    /// shortened `speed` times. See [replay_gap] for how odd timestamps
+    /// are dealt with. Batches only go out once full, so batching
+    /// should be off for timing to hold.
+    pub fn send_timed<I, T, A>(
+        &mut self,
+        iter: I,
+        dest: A,
+        speed: f64,
+    ) -> std::io::Result<SendReport>
+    where
+        I: IntoIterator<Item = (u64, T)>,
+        T: ToUdp,
+        A: ToSocketAddrs,
+    {
+        self.connect(dest)?;
+        let mut last = None;
+        let paced = iter.into_iter().map(|(timestamp, item)| {
+            if let Some(last) = last {
+                let gap = replay_gap(last, timestamp, speed);
+                if gap > Duration::from_secs(0) {
+                    std::thread::sleep(gap);
+                }
+            }
+            last = Some(timestamp);
+            item
+        });
+        self.send_all(paced)
+    }
+
+    /// Sends every item of `iter` to `dest`, waiting for every datagram
+    /// to be acknowledged by a [Receiver] with [Receiver::set_acks] enabled,
+    /// and retransmitting it if it's not. Turns sequencing on, since
+    /// acknowledgements refer to sequence numbers.
+    ///
+    /// This is a simple stop-and-wait scheme, not a replacement for TCP:
+    /// only one datagram is in flight at a time, there's no congestion
+    /// control, and if an ACK is lost after the last retry, the datagram
+    /// is reported as failed even though it may have been delivered.
+    ///
+    /// With checksums enabled on both ends, receiver doesn't acknowledge
+    /// a corrupted datagram, so it gets retransmitted. Fragments can only
+    /// be checked once the whole message is in, so a corrupted fragment
+    /// other than the last one still loses the message.
+    pub fn send_reliable<I, A>(
+        &mut self,
+        iter: I,
+        dest: A,
+        retry: RetryConfig,
+    ) -> std::io::Result<SendReport>
+    where
+        I: IntoIterator,
+        I::Item: ToUdp,
+        A: ToSocketAddrs,
+    {
+        if self.sequence.is_none() {
+            self.set_sequencing(true);
+        }
+        self.connect(dest)?;
+        self.retry = Some(retry);
+        let res = self.send_all(iter);
+        self.retry = None;
+        res
+    }
+
+    /// Sends every item of `iter` to `dest` without connecting socket,
+    /// so that the same [Sender] can take turns sending to several
+    /// destinations. Unlike [Sender::send], errors reported by
+    /// destination host are not noticed.
+    pub fn send_to<I, A>(&mut self, iter: I, dest: A) -> std::io::Result<SendReport>
+    where
+        I: IntoIterator,
+        I::Item: ToUdp,
+        A: ToSocketAddrs,
+    {
+        self.send_to_all(iter, std::slice::from_ref(&dest))
+    }
+
+    /// Same as [Sender::send_to], but sends every datagram to each of
+    /// `dests` in turn. Sending carries on while any destination accepts
+    /// datagrams, what went to each one is listed in
+    /// [SendReport::destinations].
+    pub fn send_to_all<I, A>(&mut self, iter: I, dests: &[A]) -> std::io::Result<SendReport>
+    where
+        I: IntoIterator,
+        I::Item: ToUdp,
+        A: ToSocketAddrs,
+    {
+        if dests.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "No destinations to send to",
+            ));
+        }
+        let local = self.sock.local_addr()?;
+        self.dests = dests
+            .iter()
+            .map(|dest| resolve_for(local, dest).map(DestinationReport::new))
+            .collect::<std::io::Result<_>>()?;
+        let res = self.send_all(iter);
+        let dests = std::mem::take(&mut self.dests);
+        res.map(|report| SendReport {
+            destinations: dests,
+            ..report
+        })
+    }
+
+    /// Sends every item of `iter` to current destination.
+    fn send_all<I>(&mut self, iter: I) -> std::io::Result<SendReport>
+    where
+        I: IntoIterator,
+        I::Item: ToUdp,
+    {
+        self.discard_batch();
+        if let Some(ref mut timing) = self.timing {
+            *timing = SendTiming::default();
+        }
+        let mut report = SendReport::default();
+        for item in iter {
+            self.send_item(&item, &mut report)?;
+        }
+        self.flush_batch(&mut report)?;
+        report.timing = self.timing.as_mut().map(std::mem::take);
+        Ok(report)
+    }
+
+    /// Sends a single item, returning number of bytes written.
+    pub fn send_one<T, A>(&mut self, item: &T, dest: A) -> std::io::Result<usize>
+    where
+        T: ToUdp,
+        A: ToSocketAddrs,
+    {
+        self.connect(dest)?;
+        self.discard_batch();
+        let mut report = SendReport::default();
+        self.send_item(item, &mut report)?;
+        self.flush_batch(&mut report)?;
+        Ok(report.bytes_sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::record::{ParseError, Record};
+    use crate::udp::*;
+    use rand::{thread_rng, Rng};
+    use std::thread;
+
+    type DummyData = Vec<u8>;
+
+    fn construct_dummy_data() -> Vec<DummyData> {
+        let mut rng = thread_rng();
+        vec![
+            vec![],
+            vec![42, 1],
+            vec![0; UDP_MAX_PAYLOAD],
+            vec![u8::MAX; UDP_MAX_PAYLOAD],
+            (0..UDP_MAX_PAYLOAD).map(|_| rng.gen()).collect(),
+        ]
+    }
+
+    #[test]
+    // Basic Sender test
+    fn sender() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dest = rx_sock.local_addr().unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+
+        let data = construct_dummy_data();
+        let copy = data.clone();
+
+        let _t = thread::spawn(move || {
+            sender.send(copy.iter(), dest).unwrap();
+        });
+
+        for packet in data.iter() {
+            let mut buf = vec![0_u8; UDP_MAX_PAYLOAD];
+            let (len, _) = rx_sock.recv_from(&mut buf).unwrap();
+            assert_eq!(packet, &buf[..len]);
+        }
+    }
+
+    #[test]
+    // Basic receiver test
+    fn receiver() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx_sock.connect(receiver.local_addr().unwrap()).unwrap();
+
+        let data = construct_dummy_data();
+        let copy = data.clone();
+
+        let _t = thread::spawn(move || {
+            for packet in copy.iter() {
+                tx_sock.send(packet).unwrap();
+            }
+        });
+
+        for packet in data.iter() {
+            let recv = receiver.next().unwrap().unwrap();
+            assert_eq!(packet, &recv);
+        }
+    }
+
+    #[test]
+    // Short timeout ends iteration instead of surfacing as an error
+    fn receiver_timeout() {
+        let mut receiver: Receiver<DummyData> =
+            Receiver::with_timeout("127.0.0.1:0", Some(Duration::from_millis(10))).unwrap();
+
+        assert!(receiver.next().is_none());
+        assert!(receiver.next_with_source().is_none());
+
+        // Iteration resumes once data arrives
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx_sock
+            .send_to(&[1, 2], receiver.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![1, 2]);
+        assert!(receiver.next().is_none());
+    }
+
+    #[test]
+    // Long timeout waits for a delayed packet
+    fn receiver_long_timeout() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let _t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            tx_sock.send_to(&[1, 2, 3], addr).unwrap();
+        });
+
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn fragment_header() {
+        let header = FragmentHeader {
+            message: 7,
+            index: 1,
+            total: 3,
+        };
+        let datagram = [&header.to_bytes()[..], &[42]].concat();
+        assert_eq!(
+            FragmentHeader::parse(&datagram),
+            Some((header, &[42_u8][..]))
+        );
+        assert_eq!(FragmentHeader::parse(&datagram[..4]), None);
+
+        let bogus = FragmentHeader {
+            message: 7,
+            index: 3,
+            total: 3,
+        };
+        assert_eq!(FragmentHeader::parse(&bogus.to_bytes()), None);
+    }
+
+    #[test]
+    // Fragments arriving out of order and duplicated
+    fn reassembler() {
+        let source: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let payload: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+        let mut datagrams = fragment(&payload, 1, UDP_MAX_PAYLOAD).unwrap();
+        assert_eq!(datagrams.len(), 4);
+        assert!(datagrams.iter().all(|d| d.len() <= UDP_MAX_PAYLOAD));
+        datagrams.reverse();
+        datagrams.insert(1, datagrams[0].clone());
+
+        let mut reassembler = Reassembler::default();
+        let mut complete = None;
+        for datagram in datagrams.iter() {
+            let (header, data) = FragmentHeader::parse(datagram).unwrap();
+            assert!(complete.is_none());
+            complete = reassembler.push(source, header, data);
+        }
+        assert_eq!(complete, Some(payload));
+        assert!(reassembler.pending.is_empty());
+    }
+
+    #[test]
+    // Large payload survives Sender -> Receiver round-trip
+    fn fragmented_round_trip() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_fragmentation(true);
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_fragmentation(true);
+
+        let mut rng = thread_rng();
+        let data: Vec<DummyData> = vec![(0..4096).map(|_| rng.gen()).collect(), vec![]];
+        let copy = data.clone();
+
+        let _t = thread::spawn(move || {
+            sender.send(copy.iter(), addr).unwrap();
+        });
+
+        for packet in data.iter() {
+            assert_eq!(&receiver.next().unwrap().unwrap(), packet);
+        }
+    }
+
+    #[test]
+    // Report counts items, bytes and truncations
+    fn send_report() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+
+        let data: Vec<DummyData> = vec![
+            vec![1, 2, 3],
+            vec![0; UDP_MAX_PAYLOAD + 1],
+            vec![],
+            vec![0; UDP_MAX_PAYLOAD * 2],
+        ];
+        let report = sender
+            .send(data.iter(), rx_sock.local_addr().unwrap())
+            .unwrap();
+
+        assert_eq!(
+            report,
+            SendReport {
+                items_sent: 4,
+                bytes_sent: 3 + UDP_MAX_PAYLOAD * 2,
+                truncated: 2,
+                dropped: 0,
+                failed: 0,
+                errors: vec![],
+                timing: None,
+                destinations: vec![],
+                digest: report.digest.clone(),
+            }
+        );
+        assert_eq!(report.digest.items(), 4);
+    }
+
+    #[test]
+    fn resolve_family() {
+        let v4: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        let v6: SocketAddr = "[::]:0".parse().unwrap();
+        let both: Vec<SocketAddr> =
+            vec!["[::1]:1".parse().unwrap(), "127.0.0.1:1".parse().unwrap()];
+
+        assert_eq!(resolve_for(v4, &both[..]).unwrap(), both[1]);
+        assert_eq!(resolve_for(v6, &both[..]).unwrap(), both[0]);
+        assert_eq!(
+            resolve_for(v6, "127.0.0.1:1").unwrap(),
+            "[::ffff:127.0.0.1]:1".parse::<SocketAddr>().unwrap()
+        );
+        assert!(resolve_for(v4, "[::1]:1").is_err());
+    }
+
+    #[test]
+    // Loopback round-trip over IPv6
+    fn ipv6_round_trip() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("[::1]:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+        let mut sender = Sender::new("[::1]:0").unwrap();
+
+        let data = construct_dummy_data();
+        let copy = data.clone();
+
+        let _t = thread::spawn(move || {
+            sender.send(copy.iter(), addr).unwrap();
+        });
+
+        for packet in data.iter() {
+            assert_eq!(&receiver.next().unwrap().unwrap(), packet);
+        }
+    }
+
+    #[test]
+    // Reported source is the sending socket
+    fn receiver_source() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx_sock
+            .send_to(&[42], receiver.sock.local_addr().unwrap())
+            .unwrap();
+
+        let (data, source) = receiver.next_with_source().unwrap().unwrap();
+        assert_eq!(data, vec![42]);
+        assert_eq!(source, tx_sock.local_addr().unwrap());
+    }
+
+    #[test]
+    // Other platforms only configure 127.0.0.1 on loopback
+    #[cfg(any(target_os = "linux", windows))]
+    fn receiver_source_filter() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let allowed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other = UdpSocket::bind("127.0.0.2:0").unwrap();
+        receiver.set_source_filter(Some(allowed.local_addr().unwrap().ip()));
+        let dest = receiver.local_addr().unwrap();
+        other.send_to(&[1], dest).unwrap();
+        allowed.send_to(&[2], dest).unwrap();
+        other.send_to(&[3], dest).unwrap();
+        allowed.send_to(&[4], dest).unwrap();
+
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![2]);
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![4]);
+        assert_eq!(receiver.filtered(), 2);
+
+        // Anything goes again once filter is removed
+        receiver.set_source_filter(None);
+        other.send_to(&[5], dest).unwrap();
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![5]);
+        assert_eq!(receiver.filtered(), 2);
+    }
+
+    #[test]
+    fn recv_buffer_size() {
+        let receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_recv_buffer_size(64 * 1024).unwrap();
+        // Linux doubles it
+        let size = receiver.recv_buffer_size().unwrap();
+        assert!(size >= 64 * 1024, "{}", size);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn os_dropped() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_drop_tracking(true).unwrap();
+        receiver.set_recv_buffer_size(4096).unwrap();
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dest = receiver.local_addr().unwrap();
+        // Loopback delivers right away, so buffer overflows before we read
+        for _ in 0..100 {
+            sock.send_to(&[0; 1000], dest).unwrap();
+        }
+        while receiver.next().is_some() {}
+        // Drops are only reported with datagrams that arrived after them
+        assert_eq!(receiver.os_dropped(), 0);
+        sock.send_to(&[0; 1000], dest).unwrap();
+        receiver.next().unwrap().unwrap();
+        assert!(receiver.os_dropped() > 0);
+
+        receiver.set_drop_tracking(false).unwrap();
+    }
+
+    #[test]
+    // Payload that failed to parse is returned with the error
+    fn receiver_parse_error() {
+        let mut receiver: Receiver<Record> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx_sock
+            .send_to(
+                &[1, 0, 0, 0, 0xc3, 0x28],
+                receiver.sock.local_addr().unwrap(),
+            )
+            .unwrap();
+
+        match receiver.next_with_source() {
+            Some(Err(Error::ParseError { source, raw, src })) => {
+                assert!(matches!(source, ParseError::Invalid { offset: 4, .. }));
+                assert_eq!(raw, vec![1, 0, 0, 0, 0xc3, 0x28]);
+                assert_eq!(src, tx_sock.local_addr().unwrap());
+            }
+            other => panic!("Expected parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batch() {
+        let mut pending = Batch::default();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending.encode(), vec![0, 0]);
+        pending.reset(Vec::new());
+        for item in [&[1, 2][..], &[], &[3]].iter() {
+            pending.push(item);
+        }
+        assert_eq!(pending.len(), 11);
+        let batch = pending.encode();
+        assert_eq!(batch, vec![0, 3, 0, 2, 1, 2, 0, 0, 0, 1, 3]);
+        pending.reset(batch.clone());
+        assert_eq!(pending.items, 0);
+        assert_eq!(pending.len(), 2);
+        assert_eq!(
+            decode_batch(&batch),
+            Some(vec![&[1, 2][..], &[][..], &[3][..]])
+        );
+        assert_eq!(decode_batch(&[0, 0]), Some(vec![]));
+
+        assert_eq!(decode_batch(&[]), None);
+        assert_eq!(decode_batch(&batch[..batch.len() - 1]), None);
+        assert_eq!(decode_batch(&[0, 1, 0, 5, 1]), None);
+        // Count says less than there is
+        assert_eq!(decode_batch(&[0, 1, 0, 1, 1, 0, 1, 2]), None);
+    }
+
+    #[test]
+    // Small items share datagrams and are yielded one by one
+    fn batched_round_trip() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        receiver.set_batching(true);
+        receiver.set_checksum(true);
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_max_payload(64).unwrap();
+        sender.set_batching(true);
+        sender.set_checksum(true);
+
+        // 10 items of 2 + 10 bytes each, 4 of which fit into 64 - 4 - 2 bytes
+        let data: Vec<DummyData> = (0..10).map(|i| vec![i; 10]).collect();
+        let mut with_oversized = data.clone();
+        with_oversized.insert(3, vec![0xff; 64]);
+        let report = sender
+            .send(with_oversized.iter(), receiver.sock.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(report.items_sent, 10);
+        assert_eq!(report.dropped, 1);
+        assert_eq!(report.truncated, 0);
+        assert_eq!(
+            report.bytes_sent,
+            3 * (BATCH_COUNT_LEN + CHECKSUM_LEN) + 10 * (BATCH_LENGTH_LEN + 10)
+        );
+
+        for item in data {
+            assert_eq!(receiver.next().unwrap().unwrap(), item);
+        }
+    }
+
+    #[test]
+    // Batches carry fragment headers, so reassembling receiver takes them
+    fn batched_fragmented_round_trip() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        receiver.set_batching(true);
+        receiver.set_fragmentation(true);
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_max_payload(64).unwrap();
+        sender.set_batching(true);
+        sender.set_fragmentation(true);
+
+        // 10 items of 2 + 10 bytes each, 4 of which fit into 64 - 8 - 2 bytes
+        let data: Vec<DummyData> = (0..10).map(|i| vec![i; 10]).collect();
+        let report = sender
+            .send(data.iter(), receiver.sock.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(report.items_sent, 10);
+        assert_eq!(report.dropped, 0);
+        assert_eq!(
+            report.bytes_sent,
+            3 * (FRAGMENT_HEADER_LEN + BATCH_COUNT_LEN) + 10 * (BATCH_LENGTH_LEN + 10)
+        );
+
+        for item in data {
+            assert_eq!(receiver.next().unwrap().unwrap(), item);
+        }
+    }
+
+    #[test]
+    // Every header still leaves room for a byte of data
+    fn min_payload_all_headers() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        receiver.set_max_payload(MIN_PAYLOAD).unwrap();
+        receiver.set_sequencing(true);
+        receiver.set_checksum(true);
+        receiver.set_fragmentation(true);
+        receiver.set_batching(true);
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_max_payload(MIN_PAYLOAD).unwrap();
+        sender.set_sequencing(true);
+        sender.set_checksum(true);
+        sender.set_fragmentation(true);
+        sender.set_batching(true);
+        assert_eq!(sender.item_capacity(), 1);
+
+        let data: Vec<DummyData> = vec![vec![1], vec![], vec![2]];
+        let mut with_oversized = data.clone();
+        with_oversized.push(vec![3, 4]);
+        let report = sender
+            .send(with_oversized.iter(), receiver.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(report.items_sent, 3);
+        assert_eq!(report.dropped, 1);
+
+        for item in data {
+            assert_eq!(receiver.next().unwrap().unwrap(), item);
+        }
+    }
+
+    #[test]
+    fn receiver_bad_batch() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        receiver.set_batching(true);
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+        tx_sock.send_to(&[0, 2, 0, 1, 42], addr).unwrap();
+        tx_sock.send_to(&[0, 1, 0, 1, 43], addr).unwrap();
+
+        assert!(matches!(receiver.next(), Some(Err(Error::BadBatch))));
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![43]);
+    }
+
+    #[test]
+    fn max_payload_too_small() {
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        let res = sender.set_max_payload(MIN_PAYLOAD - 1);
+        assert_eq!(
+            res.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+        assert_eq!(sender.max_payload, UDP_MAX_PAYLOAD);
+        sender.set_max_payload(MIN_PAYLOAD).unwrap();
+
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        assert!(receiver.set_max_payload(MIN_PAYLOAD - 1).is_err());
+        assert_eq!(receiver.buf.len(), UDP_MAX_PAYLOAD);
+    }
+
+    #[test]
+    fn dry_run() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx_sock
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_dry_run(true);
+        sender.set_sequencing(true);
+        sender.set_rate(Some(1));
+
+        let data: Vec<DummyData> = vec![vec![1, 2], vec![3]];
+        let start = Instant::now();
+        let report = sender
+            .send(data.iter(), rx_sock.local_addr().unwrap())
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(report.items_sent, 2);
+        assert_eq!(report.bytes_sent, 2 * SEQUENCE_LEN + 3);
+        assert_eq!(
+            sender.take_dry_run(),
+            vec![vec![0, 0, 0, 0, 1, 2], vec![1, 0, 0, 0, 3]]
+        );
+        assert!(sender.take_dry_run().is_empty());
+
+        let mut buf = [0_u8; 16];
+        assert!(rx_sock.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn best_effort() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dest = rx_sock.local_addr().unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = sent.clone();
+        sender.send_hook = Some(Box::new(move |datagram: &[u8]| {
+            if datagram == [2] {
+                return Err(std::io::ErrorKind::ConnectionRefused.into());
+            }
+            log.lock().unwrap().push(datagram.to_vec());
+            Ok(datagram.len())
+        }));
+        let data: Vec<DummyData> = vec![vec![1], vec![2], vec![3]];
+
+        let err = sender.send(data.iter(), dest).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(*sent.lock().unwrap(), vec![vec![1]]);
+
+        sent.lock().unwrap().clear();
+        sender.set_best_effort(true);
+        let report = sender.send(data.iter(), dest).unwrap();
+        assert_eq!(report.items_sent, 2);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.errors, vec![std::io::ErrorKind::ConnectionRefused]);
+        assert_eq!(*sent.lock().unwrap(), vec![vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn ephemeral() {
+        let sender = Sender::new_ephemeral().unwrap();
+        let addr = sender.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert!(addr.ip().is_unspecified());
+    }
+
+    #[test]
+    fn local_addr() {
+        let receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(receiver.local_addr().unwrap(), addr);
+
+        let sender = Sender::new("127.0.0.1:0").unwrap();
+        let addr = sender.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(sender.local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn try_next() {
+        let mut receiver: Receiver<Record> = Receiver::new("127.0.0.1:0").unwrap();
+        let dest = receiver.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        assert!(receiver.try_next().is_pending());
+
+        let record = Record {
+            id: 7,
+            data: "seven".to_owned(),
+        };
+        sender.send_to(&record.to_udp(), dest).unwrap();
+        match receiver.try_next() {
+            Poll::Ready(Ok(received)) => assert_eq!(received, record),
+            other => panic!("Expected record, got {:?}", other),
+        }
+
+        sender.send_to(&[1, 2], dest).unwrap();
+        match receiver.try_next() {
+            Poll::Ready(Err(Error::ParseError { raw, .. })) => assert_eq!(raw, vec![1, 2]),
+            other => panic!("Expected parse error, got {:?}", other),
+        }
+        assert!(receiver.try_next().is_pending());
+    }
+
+    #[test]
+    fn send_to() {
+        let mut first: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let mut second: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+
+        let data: Vec<DummyData> = vec![vec![1], vec![2, 3], vec![4; 100]];
+        for receiver in [&first, &second].iter() {
+            let dest = receiver.local_addr().unwrap();
+            let report = sender.send_to(data.iter(), dest).unwrap();
+            assert_eq!(report.items_sent, data.len());
+        }
+        assert!(sender.sock.peer_addr().is_err());
+
+        for receiver in [&mut first, &mut second].iter_mut() {
+            for packet in data.iter() {
+                assert_eq!(&receiver.next().unwrap().unwrap(), packet);
+            }
+            assert!(receiver.next().is_none());
+        }
+    }
+
+    #[test]
+    fn send_to_all() {
+        let mut first: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let mut second: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_sequencing(true);
+
+        let data: Vec<DummyData> = vec![vec![1], vec![2, 3], vec![4; 100]];
+        let dests = [first.local_addr().unwrap(), second.local_addr().unwrap()];
+        let report = sender.send_to_all(data.iter(), &dests).unwrap();
+        assert_eq!(report.items_sent, data.len());
+        assert_eq!(report.bytes_sent, 103 + 3 * SEQUENCE_LEN);
+        assert_eq!(report.destinations.len(), 2);
+        for (dest, addr) in report.destinations.iter().zip(dests.iter()) {
+            assert_eq!(dest.addr, *addr);
+            assert_eq!(dest.datagrams, data.len());
+            assert_eq!(dest.bytes_sent, report.bytes_sent);
+            assert_eq!(dest.failed, 0);
+        }
+
+        for receiver in [&mut first, &mut second].iter_mut() {
+            receiver.set_sequencing(true);
+            for packet in data.iter() {
+                assert_eq!(&receiver.next().unwrap().unwrap(), packet);
+                assert_eq!(receiver.last_sequence(), SequenceInfo::default());
+            }
+            assert!(receiver.next().is_none());
+        }
+
+        let none: [SocketAddr; 0] = [];
+        assert_eq!(
+            sender.send_to_all(data.iter(), &none).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    // Other platforms only share unicast ports with SO_REUSEPORT
+    #[cfg(any(target_os = "linux", windows))]
+    fn reuse_addr() {
+        let first: Receiver<DummyData> = Receiver::with_reuse_addr("127.0.0.1:0").unwrap();
+        let addr = first.local_addr().unwrap();
+        let second: Receiver<DummyData> = Receiver::with_reuse_addr(addr).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+        let sender = Sender::with_reuse_addr(addr).unwrap();
+        assert_eq!(sender.local_addr().unwrap(), addr);
+
+        assert!(Receiver::<DummyData>::new(addr).is_err());
+    }
+
+    #[test]
+    fn receiver_builder() {
+        let builder = ReceiverBuilder::new()
+            .timeout(Some(Duration::from_millis(200)))
+            .buffer_size(64)
+            .reuse_addr(true)
+            .source_filter(Some(Ipv4Addr::LOCALHOST.into()))
+            .recv_buffer_size(64 * 1024);
+        let mut receiver: Receiver<DummyData> = builder.bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        assert_eq!(
+            receiver.sock.read_timeout().unwrap(),
+            Some(Duration::from_millis(200))
+        );
+        assert!(receiver.recv_buffer_size().unwrap() >= 64 * 1024);
+
+        // Other platforms only configure 127.0.0.1 on loopback
+        #[cfg(any(target_os = "linux", windows))]
+        {
+            let other = UdpSocket::bind("127.0.0.2:0").unwrap();
+            other.send_to(&[1], addr).unwrap();
+        }
+        let allowed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        allowed.send_to(&[2; 100], addr).unwrap();
+        // Truncated to buffer size
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![2; 64]);
+        #[cfg(any(target_os = "linux", windows))]
+        assert_eq!(receiver.filtered(), 1);
+        assert!(receiver.next().is_none());
+
+        // Same builder binds again, sharing the port
+        #[cfg(any(target_os = "linux", windows))]
+        {
+            let second: Receiver<DummyData> = builder.bind(addr).unwrap();
+            assert_eq!(second.local_addr().unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn receiver_builder_defaults() {
+        let receiver: Receiver<DummyData> = ReceiverBuilder::new().bind("127.0.0.1:0").unwrap();
+        assert_eq!(receiver.sock.read_timeout().unwrap(), Some(DEFAULT_TIMEOUT));
+        assert_eq!(receiver.buf.len(), UDP_MAX_PAYLOAD);
+
+        let receiver: Receiver<DummyData> = ReceiverBuilder::new()
+            .timeout(None)
+            .bind("127.0.0.1:0")
+            .unwrap();
+        assert_eq!(receiver.sock.read_timeout().unwrap(), None);
+
+        let res: std::io::Result<Receiver<DummyData>> = ReceiverBuilder::new()
+            .buffer_size(MIN_PAYLOAD - 1)
+            .bind("127.0.0.1:0");
+        assert_eq!(
+            res.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn sender_builder() {
+        let builder = SenderBuilder::new()
+            .broadcast(true)
+            .ttl(7)
+            .rate(Some(100))
+            .reuse_addr(true);
+        let sender = builder.bind("127.0.0.1:0").unwrap();
+        assert!(sender.sock.broadcast().unwrap());
+        assert_eq!(sender.sock.ttl().unwrap(), 7);
+        assert_eq!(sender.interval, Some(Duration::from_millis(10)));
+
+        // Same builder binds again, sharing the port
+        #[cfg(any(target_os = "linux", windows))]
+        {
+            let addr = sender.local_addr().unwrap();
+            let second = builder.bind(addr).unwrap();
+            assert_eq!(second.local_addr().unwrap(), addr);
+        }
+
+        let sender = SenderBuilder::new()
+            .rate(Some(100))
+            .interval(Some(Duration::from_millis(3)))
+            .bind_ephemeral()
+            .unwrap();
+        assert!(!sender.sock.broadcast().unwrap());
+        assert_eq!(sender.interval, Some(Duration::from_millis(3)));
+        let local = sender.local_addr().unwrap();
+        assert!(local.ip().is_unspecified());
+        assert_ne!(local.port(), 0);
+
+        let sender = SenderBuilder::new()
+            .rate(Some(0))
+            .bind("127.0.0.1:0")
+            .unwrap();
+        assert_eq!(sender.interval, None);
+    }
+
+    #[test]
+    fn message_too_long() {
+        let receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let dest = receiver.local_addr().unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        // Allowed by us, but not by UDP itself
+        sender.set_max_payload(70_000).unwrap();
+        let records = [
+            Record {
+                id: 1,
+                data: "small".to_owned(),
+            },
+            Record {
+                id: 42,
+                data: "a".repeat(66_000),
+            },
+        ];
+        let e = sender.send(records.iter(), dest).unwrap_err();
+        let message = e.to_string();
+        assert!(
+            message.starts_with("Record 42 takes 66004 bytes"),
+            "{}",
+            message
+        );
+        assert!(message.contains("fragmentation"), "{}", message);
+
+        // Items without id are pointed at by position
+        let e = sender
+            .send(vec![vec![0_u8; 10], vec![0_u8; 66_000]], dest)
+            .unwrap_err();
+        assert!(
+            e.to_string().starts_with("Item #1 takes 66000 bytes"),
+            "{}",
+            e
+        );
+
+        // Other errors are left alone
+        let other = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        let e = explain_too_long(other, Some(1), 0, 10);
+        assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused);
+        assert!(e.get_ref().is_none());
+    }
+
+    #[test]
+    fn copies() {
+        let mut receiver: Receiver<Record> = Receiver::new("127.0.0.1:0").unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_copies(3);
+        let records = Record::generate(4, 10);
+        let report = sender
+            .send(records.iter(), receiver.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(report.items_sent, 4);
+        assert_eq!(report.bytes_sent, 4 * 3 * 14);
+        assert_eq!(sender.stats().datagrams, 12);
+
+        for record in records.iter() {
+            for _ in 0..3 {
+                assert_eq!(&receiver.next().unwrap().unwrap(), record);
+            }
+        }
+        assert!(receiver.next().is_none());
+
+        // Copies of a sequenced datagram keep its number
+        sender.set_copies(0);
+        assert_eq!(sender.copies, 1);
+        sender.set_copies(2);
+        sender.set_sequencing(true);
+        sender.set_dry_run(true);
+        sender
+            .send(records[..2].iter(), receiver.local_addr().unwrap())
+            .unwrap();
+        let datagrams = sender.take_dry_run();
+        let sequences: Vec<_> = datagrams.iter().map(|datagram| datagram[0]).collect();
+        assert_eq!(sequences, [0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn delimited() {
+        let records = [
+            Record {
+                id: 1,
+                data: "first".to_owned(),
+            },
+            Record {
+                id: 2,
+                data: "a\nb".to_owned(),
+            },
+            Record {
+                id: 10,
+                data: "back\\slash".to_owned(),
+            },
+        ];
+        // Id 10 is itself a newline
+        let mut payload = join_delimited(records.iter().map(ToUdp::to_udp), b'\n');
+        assert_eq!(payload.iter().filter(|&&byte| byte == b'\n').count(), 4);
+        payload.push(b'\n');
+
+        let mut receiver: Receiver<Record> = ReceiverBuilder::new()
+            .delimiter(Some(b'\n'))
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.send_to(&payload, receiver.local_addr().unwrap())
+            .unwrap();
+        for record in records.iter() {
+            assert_eq!(&receiver.next().unwrap().unwrap(), record);
+        }
+        // Trailing delimiter yields nothing more
+        assert!(receiver.next().is_none());
+
+        // Without delimiter, payload is a single record
+        receiver.set_delimiter(None).unwrap();
+        sock.send_to(b"\x01\0\0\0a\nb", receiver.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(receiver.next().unwrap().unwrap().data, "a\nb");
+
+        // Escape byte can't delimit, and is reported rather than panicking
+        assert_eq!(
+            receiver
+                .set_delimiter(Some(DELIMITER_ESCAPE))
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        let res: std::io::Result<Receiver<Record>> = ReceiverBuilder::new()
+            .delimiter(Some(DELIMITER_ESCAPE))
+            .bind("127.0.0.1:0");
+        assert_eq!(
+            res.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn split_delimited_edges() {
+        assert!(split_delimited(b"", b';').is_empty());
+        assert_eq!(split_delimited(b";", b';'), [b""]);
+        assert_eq!(split_delimited(b"a;;b;", b';'), [&b"a"[..], b"", b"b"]);
+        assert_eq!(split_delimited(b"a\\;b\\\\;c", b';'), [&b"a;b\\"[..], b"c"]);
+        // Dangling escape is kept
+        assert_eq!(split_delimited(b"a\\", b';'), [b"a\\"]);
+    }
+
+    #[test]
+    fn digest() {
+        let mut records: Vec<Record> = (0..20)
+            .map(|id| Record {
+                id,
+                data: "x".repeat(id as usize),
+            })
+            .collect();
+        let mut receiver: Receiver<Record> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_batching(true);
+        let dest = receiver.local_addr().unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_batching(true);
+
+        let sent = sender.send(records.iter(), dest).unwrap().digest;
+        for _ in records.iter() {
+            receiver.next().unwrap().unwrap();
+        }
+        assert_eq!(receiver.digest(), &sent);
+        assert_eq!(sent.items(), 20);
+
+        // Same dataset, same digest
+        sender.set_dry_run(true);
+        assert_eq!(sender.send(records.iter(), dest).unwrap().digest, sent);
+
+        records[7].data.push('y');
+        assert_ne!(sender.send(records.iter(), dest).unwrap().digest, sent);
+
+        // Item boundaries count too
+        let mut whole = Digest::default();
+        whole.update(b"ab");
+        let mut split = Digest::default();
+        split.update(b"a");
+        split.update(b"b");
+        assert_ne!(whole.value(), split.value());
+        assert_eq!(
+            whole.to_string(),
+            format!("{:08x} over 1 items", whole.value())
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_best_effort(true);
+        sender.send_hook = Some(Box::new(|datagram: &[u8]| {
+            if datagram.len() == 2 {
+                return Err(std::io::ErrorKind::ConnectionRefused.into());
+            }
+            Ok(datagram.len())
+        }));
+        assert_eq!(sender.stats(), Stats::default());
+        assert_eq!(sender.stats().avg_payload(), 0.0);
+
+        let data: Vec<DummyData> = vec![vec![1], vec![2; 2], vec![3; 3], vec![4; 600]];
+        sender.send(data.iter(), "127.0.0.1:9").unwrap();
+        let stats = sender.stats();
+        assert_eq!(
+            stats,
+            Stats {
+                datagrams: 3,
+                bytes: 1 + 3 + UDP_MAX_PAYLOAD,
+                min_payload: 1,
+                max_payload: UDP_MAX_PAYLOAD,
+                truncated: 1,
+                errors: 1,
+            }
+        );
+        assert!((stats.avg_payload() - 512.0 / 3.0).abs() < 1e-9);
+
+        sender.send(data[..1].iter(), "127.0.0.1:9").unwrap();
+        assert_eq!(sender.stats().datagrams, 4);
+        sender.reset_stats();
+        assert_eq!(sender.stats(), Stats::default());
+    }
+
+    #[test]
+    fn error_display() {
+        use std::error::Error as _;
+
+        let io: Error<ParseError> = std::io::Error::from(std::io::ErrorKind::TimedOut).into();
+        assert!(matches!(io, Error::Io(_)));
+        assert_eq!(io.to_string(), "timed out");
+        assert!(io.source().is_some());
+
+        let parse: Error<ParseError> = Error::ParseError {
+            source: ParseError::Incomplete(2),
+            raw: vec![1, 2],
+            src: "127.0.0.1:8142".parse().unwrap(),
+        };
+        assert_eq!(
+            parse.to_string(),
+            "couldn't parse 2 byte payload from 127.0.0.1:8142: \
+             got 2 bytes, fewer than header takes up"
+        );
+        assert_eq!(
+            parse.source().unwrap().to_string(),
+            ParseError::Incomplete(2).to_string()
+        );
+
+        let cases: [(Error<ParseError>, &str); 4] = [
+            (Error::BadFragment, "datagram has no valid fragment header"),
+            (
+                Error::ChecksumMismatch,
+                "payload doesn't match its checksum",
+            ),
+            (
+                Error::MissingSequence,
+                "datagram is too short for a sequence number",
+            ),
+            (Error::BadBatch, "payload isn't a valid batch"),
+        ];
+        for (e, text) in cases.iter() {
+            assert_eq!(e.to_string(), *text);
+            assert!(e.source().is_none());
+        }
+
+        // Works with `?` into a boxed error
+        let boxed =
+            || -> Result<(), Box<dyn std::error::Error>> { Err(Error::BadBatch::<ParseError>)? };
+        assert!(boxed().is_err());
+    }
+
+    #[test]
+    fn probe() {
+        let receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        let open = receiver.local_addr().unwrap();
+        let closed = {
+            let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+            sock.local_addr().unwrap()
+        };
+
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        let timeout = Duration::from_millis(50);
+        assert!(sender.probe(open, timeout).unwrap());
+        assert_eq!(sender.stats(), Stats::default());
+        let reachable = sender.probe(closed, timeout).unwrap();
+        // Only some platforms report ICMP errors on loopback
+        if cfg!(target_os = "linux") {
+            assert!(!reachable);
+        }
+
+        // Sending still works afterwards
+        let data: Vec<DummyData> = vec![vec![1, 2]];
+        sender.send(data.iter(), open).unwrap();
+    }
+
+    #[test]
+    fn timing() {
+        let mut timing = SendTiming::default();
+        assert_eq!(timing.average(), Duration::from_secs(0));
+        for &ms in [3, 9, 1, 7, 5, 8, 2, 9].iter() {
+            timing.add(Duration::from_millis(ms));
+        }
+        assert_eq!(timing.writes, 8);
+        assert_eq!(timing.total, Duration::from_millis(44));
+        assert_eq!(timing.average(), Duration::from_micros(5500));
+        let slowest: Vec<_> = [9, 9, 8, 7, 5]
+            .iter()
+            .map(|&ms| Duration::from_millis(ms))
+            .collect();
+        assert_eq!(timing.slowest, slowest);
+
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.send_hook = Some(Box::new(|datagram: &[u8]| Ok(datagram.len())));
+        let data: Vec<DummyData> = vec![vec![1], vec![2; 2], vec![3; 3]];
+        let report = sender.send(data.iter(), "127.0.0.1:9").unwrap();
+        assert_eq!(report.timing, None);
+
+        sender.set_timing(true);
+        for _ in 0..2 {
+            let report = sender.send(data.iter(), "127.0.0.1:9").unwrap();
+            let timing = report.timing.unwrap();
+            // Every send is measured on its own
+            assert_eq!(timing.writes, 3);
+            assert_eq!(timing.slowest.len(), 3);
+        }
+    }
+
+    #[test]
+    fn reliable() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_sequencing(true);
+        receiver.set_acks(true);
+        let dest = receiver.local_addr().unwrap();
+        let data: Vec<DummyData> = (0..5).map(|i| vec![i; 10]).collect();
+
+        let copy = data.clone();
+        let t = thread::spawn(move || {
+            let mut sender = Sender::new("127.0.0.1:0").unwrap();
+            sender.send_reliable(copy.iter(), dest, RetryConfig::default())
+        });
+        for packet in data.iter() {
+            assert_eq!(&receiver.next().unwrap().unwrap(), packet);
+            assert_eq!(receiver.last_sequence(), SequenceInfo::default());
+        }
+        let report = t.join().unwrap().unwrap();
+        assert_eq!(report.items_sent, data.len());
+    }
+
+    #[test]
+    fn echo() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_sequencing(true);
+        receiver.set_echo(true);
+        let addr = receiver.local_addr().unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        client.send_to(&[0, 0, 0, 0, 42, 43], addr).unwrap();
+        // Echo doesn't get in the way of receiving
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![42, 43]);
+        let mut buf = [0_u8; 16];
+        let (len, src) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0, 0, 0, 0, 42, 43]);
+        assert_eq!(src, addr);
+
+        // Even datagrams that don't parse are sent back
+        client.send_to(&[1, 0], addr).unwrap();
+        assert!(matches!(receiver.next(), Some(Err(Error::MissingSequence))));
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 0]);
+    }
+
+    #[test]
+    fn reliable_corrupted() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_sequencing(true);
+        receiver.set_checksum(true);
+        receiver.set_acks(true);
+        let rx_addr = receiver.local_addr().unwrap();
+        let data: Vec<DummyData> = (0..3).map(|i| vec![i; 10]).collect();
+
+        // Flips a byte of the first datagram on its way to receiver,
+        // passing everything else, ACKs included, along
+        let proxy = UdpSocket::bind("127.0.0.1:0").unwrap();
+        proxy
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let proxy = thread::spawn(move || {
+            let mut buf = [0_u8; 64];
+            let mut sender = None;
+            let mut datagrams = 0;
+            while let Ok((len, src)) = proxy.recv_from(&mut buf) {
+                if src == rx_addr {
+                    proxy.send_to(&buf[..len], sender.unwrap()).unwrap();
+                    continue;
+                }
+                sender = Some(src);
+                datagrams += 1;
+                if datagrams == 1 {
+                    buf[len - 1] ^= 0xff;
+                }
+                proxy.send_to(&buf[..len], rx_addr).unwrap();
+            }
+            datagrams
+        });
+
+        let copy = data.clone();
+        let t = thread::spawn(move || {
+            let mut sender = Sender::new("127.0.0.1:0").unwrap();
+            sender.set_checksum(true);
+            let retry = RetryConfig {
+                timeout: Duration::from_millis(50),
+                retries: 3,
+            };
+            sender.send_reliable(copy.iter(), proxy_addr, retry)
+        });
+        assert!(matches!(
+            receiver.next(),
+            Some(Err(Error::ChecksumMismatch))
+        ));
+        for packet in data.iter() {
+            assert_eq!(&receiver.next().unwrap().unwrap(), packet);
+        }
+        // Corrupted datagram didn't count, so there's no gap
+        assert_eq!(receiver.last_sequence(), SequenceInfo::default());
+        let report = t.join().unwrap().unwrap();
+        assert_eq!(report.items_sent, data.len());
+        assert_eq!(report.failed, 0);
+        // First datagram went twice
+        assert_eq!(proxy.join().unwrap(), data.len() + 1);
+    }
+
+    #[test]
+    fn reliable_retransmit() {
+        // Plays receiver whose first ACK got lost
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dest = rx_sock.local_addr().unwrap();
+        let t = thread::spawn(move || {
+            let mut sender = Sender::new("127.0.0.1:0").unwrap();
+            let retry = RetryConfig {
+                timeout: Duration::from_millis(50),
+                retries: 2,
+            };
+            sender.send_reliable([vec![7_u8]].iter(), dest, retry)
+        });
+        let mut buf = [0_u8; 16];
+        let (len, src) = rx_sock.recv_from(&mut buf).unwrap();
+        let first = buf[..len].to_vec();
+        let (len, _) = rx_sock.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &first[..]);
+        rx_sock.send_to(&first[..ACK_LEN], src).unwrap();
+        assert_eq!(t.join().unwrap().unwrap().items_sent, 1);
+
+        // Retransmitted datagram is acknowledged, but not yielded twice
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_sequencing(true);
+        receiver.set_acks(true);
+        let addr = receiver.local_addr().unwrap();
+        for _ in 0..2 {
+            rx_sock.send_to(&[0, 0, 0, 0, 42], addr).unwrap();
+        }
+        rx_sock.send_to(&[1, 0, 0, 0, 43], addr).unwrap();
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![42]);
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![43]);
+        assert!(receiver.next().is_none());
+        for seq in [0_u8, 0, 1].iter() {
+            let (len, _) = rx_sock.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], &[*seq, 0, 0, 0]);
+        }
+
+        // Gives up if nothing acknowledges
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        let retry = RetryConfig {
+            timeout: Duration::from_millis(10),
+            retries: 1,
+        };
+        let err = sender
+            .send_reliable([vec![1_u8]].iter(), dest, retry)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn reliable_ack_source() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dest = rx_sock.local_addr().unwrap();
+        let t = thread::spawn(move || {
+            let mut sender = Sender::new("127.0.0.1:0").unwrap();
+            let timeout = Some(Duration::from_secs(7));
+            sender.sock.set_read_timeout(timeout).unwrap();
+            let retry = RetryConfig {
+                timeout: Duration::from_millis(100),
+                retries: 0,
+            };
+            let res = sender.send_reliable([vec![7_u8]].iter(), dest, retry);
+            // Left as it was
+            assert_eq!(sender.sock.read_timeout().unwrap(), timeout);
+            res
+        });
+        let mut buf = [0_u8; 16];
+        let (_, src) = rx_sock.recv_from(&mut buf).unwrap();
+        // Right sequence number, but not from destination
+        let stray = UdpSocket::bind("127.0.0.1:0").unwrap();
+        stray.send_to(&buf[..ACK_LEN], src).unwrap();
+        let err = t.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn from_socket() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx_sock
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        let dest = rx_sock.local_addr().unwrap();
+        let mut receiver: Receiver<DummyData> = Receiver::from_socket(rx_sock);
+        assert_eq!(receiver.local_addr().unwrap(), dest);
+
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx_sock.set_ttl(7).unwrap();
+        let src = tx_sock.local_addr().unwrap();
+        let mut sender = Sender::from_socket(tx_sock);
+        assert_eq!(sender.local_addr().unwrap(), src);
+        sender.set_checksum(true);
+        receiver.set_checksum(true);
+
+        let data: Vec<DummyData> = vec![vec![1, 2, 3], vec![4]];
+        sender.send(data.iter(), dest).unwrap();
+        for packet in data.iter() {
+            let (received, from) = receiver.next_with_source().unwrap().unwrap();
+            assert_eq!(&received, packet);
+            assert_eq!(from, src);
+        }
+        // Keeps timeout of the socket it was given
+        let start = Instant::now();
+        assert!(receiver.next().is_none());
+        assert!(start.elapsed() < DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut payload = vec![1, 2, 3, 4, 5];
+        append_checksum(&mut payload);
+        assert_eq!(payload.len(), 5 + CHECKSUM_LEN);
+        assert_eq!(verify_checksum(&payload), Some(&[1, 2, 3, 4, 5][..]));
+
+        for i in 0..payload.len() {
+            let mut corrupted = payload.clone();
+            corrupted[i] ^= 0x10;
+            assert_eq!(verify_checksum(&corrupted), None);
+        }
+        assert_eq!(verify_checksum(&payload[..3]), None);
+    }
+
+    #[test]
+    // Corrupted datagram is reported as checksum mismatch
+    fn receiver_checksum() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_checksum(true);
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_checksum(true);
+        sender.send([vec![7_u8; 10]].iter(), addr).unwrap();
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![7_u8; 10]);
+
+        let mut payload = vec![7_u8; 10];
+        append_checksum(&mut payload);
+        payload[2] = 8;
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx_sock.send_to(&payload, addr).unwrap();
+        match receiver.next() {
+            Some(Err(Error::ChecksumMismatch)) => {}
+            other => panic!("Expected checksum mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sequence_tracker() {
+        let a: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let mut tracker = SequenceTracker::default();
+
+        assert_eq!(tracker.track(a, 0), SequenceInfo::default());
+        assert_eq!(tracker.track(b, 7), SequenceInfo::default());
+        assert_eq!(tracker.track(a, 1), SequenceInfo::default());
+        assert_eq!(
+            tracker.track(a, 4),
+            SequenceInfo {
+                lost_since_last: 2,
+                out_of_order: false
+            }
+        );
+        assert_eq!(
+            tracker.track(a, 3),
+            SequenceInfo {
+                lost_since_last: 0,
+                out_of_order: true
+            }
+        );
+        assert_eq!(tracker.track(b, 8), SequenceInfo::default());
+
+        // Wraps around
+        let c: SocketAddr = "127.0.0.1:3".parse().unwrap();
+        tracker.track(c, u32::MAX);
+        assert_eq!(tracker.track(c, 0), SequenceInfo::default());
+    }
+
+    #[test]
+    // Receiver notices a datagram dropped on the way
+    fn sequence_gap() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_sequencing(true);
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+
+        // Sits between sender and receiver, dropping the fifth datagram
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_sequencing(true);
+
+        let data: Vec<DummyData> = (0..10).map(|i| vec![i]).collect();
+        sender.send(data.iter(), relay_addr).unwrap();
+        for i in 0..10 {
+            let mut buf = [0_u8; UDP_MAX_PAYLOAD];
+            let (len, _) = relay.recv_from(&mut buf).unwrap();
+            if i != 4 {
+                relay.send_to(&buf[..len], addr).unwrap();
+            }
+        }
+
+        for i in (0..10).filter(|i| *i != 4) {
+            assert_eq!(receiver.next().unwrap().unwrap(), vec![i]);
+            let expected_loss = if i == 5 { 1 } else { 0 };
+            assert_eq!(receiver.last_sequence().lost_since_last, expected_loss);
+            assert!(!receiver.last_sequence().out_of_order);
+        }
+    }
+
+    #[test]
+    // Rate limit spaces datagrams evenly
+    fn rate_limit() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_rate(Some(50));
+
+        let data: Vec<DummyData> = (0..11).map(|i| vec![i]).collect();
+        let start = Instant::now();
+        sender
+            .send(data.iter(), rx_sock.local_addr().unwrap())
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        // First datagram goes out immediately, the rest are 20ms apart
+        assert!(elapsed >= Duration::from_millis(190), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+
+        sender.set_rate(None);
+        let start = Instant::now();
+        sender
+            .send(data.iter(), rx_sock.local_addr().unwrap())
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn schedule() {
+        let interval = Duration::from_millis(20);
+        let start = Instant::now();
+
+        // First datagram goes out immediately
+        let (wait, next) = super::schedule(start, None, interval);
+        assert_eq!(wait, Duration::from_secs(0));
+        assert_eq!(next, start + interval);
+
+        // Time spent since last send is subtracted from wait
+        let (wait, next) = super::schedule(start + Duration::from_millis(5), Some(next), interval);
+        assert_eq!(wait, Duration::from_millis(15));
+        assert_eq!(next, start + interval * 2);
+
+        // Running late doesn't result in a burst
+        let late = start + Duration::from_millis(100);
+        let (wait, next) = super::schedule(late, Some(next), interval);
+        assert_eq!(wait, Duration::from_secs(0));
+        assert_eq!(next, late + interval);
+    }
+
+    #[test]
+    fn bind_to() {
+        let sender = Sender::bind_to("127.0.0.1:0".parse().unwrap()).unwrap();
+        let local = sender.local_addr().unwrap();
+        assert_eq!(local.ip(), Ipv4Addr::LOCALHOST);
+        assert_ne!(local.port(), 0);
+
+        // Reserved for documentation, so no interface has it
+        let res = Sender::bind_to("192.0.2.1:0".parse().unwrap());
+        assert_eq!(
+            res.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::AddrNotAvailable)
+        );
+    }
+
+    #[test]
+    fn count_traffic() {
+        let receiver: Receiver<Vec<u8>> = Receiver::new("127.0.0.1:0").unwrap();
+        let mut receiver: Receiver<PayloadLen> = receiver.with_items();
+        receiver.set_sequencing(true);
+        let dest = receiver.local_addr().unwrap();
+        let tx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx_sock.send_to(&[0, 0, 0, 0, b'a', b'b'], dest).unwrap();
+        // Datagram 1 goes missing
+        tx_sock
+            .send_to(&[2, 0, 0, 0, b'c', b'd', b'e'], dest)
+            .unwrap();
+        tx_sock.send_to(&[3, 0], dest).unwrap();
+
+        let mut counts = TrafficCounts::default();
+        while let Poll::Ready(res) = receiver.try_count(&mut counts) {
+            res.unwrap();
+        }
+        assert_eq!(
+            counts,
+            TrafficCounts {
+                items: 2,
+                bytes: 5,
+                lost: 1,
+                out_of_order: 0,
+                corrupted: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn replay_schedule() {
+        let timestamps = [1000, 1100, 1100, 1500, 1400, 1500, 3_000_000];
+        let schedule: Vec<_> = timestamps
+            .windows(2)
+            .map(|pair| replay_gap(pair[0], pair[1], 2.0))
+            .collect();
+        let ms = Duration::from_millis;
+        assert_eq!(
+            schedule,
+            vec![ms(50), ms(0), ms(200), ms(0), ms(50), MAX_REPLAY_GAP]
+        );
+        assert_eq!(replay_gap(1000, 1100, 0.5), ms(200));
+        assert_eq!(replay_gap(1000, 1100, 0.0), MAX_REPLAY_GAP);
+        assert_eq!(replay_gap(1000, 1100, -1.0), ms(0));
+    }
+
+    #[test]
+    fn send_timed() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx_sock
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        let data: Vec<(u64, DummyData)> = (0..3).map(|i| (i * 40, vec![i as u8])).collect();
+
+        let start = Instant::now();
+        let report = sender
+            .send_timed(data, rx_sock.local_addr().unwrap(), 2.0)
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(report.items_sent, 3);
+        let mut buf = [0; 8];
+        for i in 0..3 {
+            assert_eq!(rx_sock.recv(&mut buf).unwrap(), 1);
+            assert_eq!(buf[0], i);
+        }
+    }
+
+    #[test]
+    fn zero_interval() {
+        let rx_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_interval(Some(Duration::from_secs(0)));
+        assert_eq!(sender.interval, None);
+        sender.set_rate(Some(0));
+        assert_eq!(sender.interval, None);
+
+        sender.set_interval(Some(Duration::from_millis(10)));
+        let data: Vec<DummyData> = (0..6).map(|i| vec![i]).collect();
+        let start = Instant::now();
+        sender
+            .send(data.iter(), rx_sock.local_addr().unwrap())
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    // Datagrams larger than default limit get through when allowed
+    fn large_payload() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_max_payload(1500).unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_max_payload(1500).unwrap();
+
+        let data: Vec<DummyData> = vec![vec![42; 1200]];
+        let report = sender.send(data.iter(), addr).unwrap();
+        assert_eq!(report.truncated, 0);
+        assert_eq!(receiver.next().unwrap().unwrap(), data[0]);
+    }
+
+    #[test]
+    fn send_one() {
+        let mut receiver: Receiver<DummyData> = Receiver::new("127.0.0.1:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.sock.local_addr().unwrap();
+        let mut sender = Sender::new("127.0.0.1:0").unwrap();
+
+        let written = sender.send_one(&vec![1, 2, 3], addr).unwrap();
+        assert_eq!(written, 3);
+        assert_eq!(receiver.next().unwrap().unwrap(), vec![1, 2, 3]);
+
+        let written = sender
+            .send_one(&vec![0; UDP_MAX_PAYLOAD + 10], addr)
+            .unwrap();
+        assert_eq!(written, UDP_MAX_PAYLOAD);
+        assert_eq!(receiver.next().unwrap().unwrap().len(), UDP_MAX_PAYLOAD);
+    }
+
+    #[test]
+    // Multicast delivery on loopback, if platform allows it
+    fn multicast() {
+        let group = Ipv4Addr::new(239, 0, 0, 1);
+        let mut receiver: Receiver<DummyData> = Receiver::new("0.0.0.0:0").unwrap();
+        receiver.set_timeout(Some(Duration::from_secs(1))).unwrap();
+        let port = receiver.sock.local_addr().unwrap().port();
+        if let Err(e) = receiver.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED) {
+            eprintln!("Skipping multicast test, can't join group: {}", e);
+            return;
+        }
+
+        let tx_sock = UdpSocket::bind("0.0.0.0:0").unwrap();
+        if let Err(e) = tx_sock.send_to(&[1, 2, 3], (group, port)) {
+            eprintln!("Skipping multicast test, can't send to group: {}", e);
+            return;
+        }
+
+        match receiver.next() {
+            Some(Ok(data)) => assert_eq!(data, vec![1, 2, 3]),
+            None => eprintln!("Skipping multicast test, datagram not looped back"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn broadcast() {
+        let sender = Sender::new("127.0.0.1:0").unwrap();
+        assert!(!sender.sock.broadcast().unwrap());
+        sender.set_broadcast(true).unwrap();
+        assert!(sender.sock.broadcast().unwrap());
+        sender.set_broadcast(false).unwrap();
+        assert!(!sender.sock.broadcast().unwrap());
+    }
+
+    #[test]
+    fn ttl() {
+        let sender = Sender::new("127.0.0.1:0").unwrap();
+        sender.set_ttl(5).unwrap();
+        assert_eq!(sender.sock.ttl().unwrap(), 5);
+    }
 }
@@ -0,0 +1,62 @@
+//! Lives in its own test binary, since it installs a global logger
+//! that would otherwise capture records of every other test.
+use std::sync::Mutex;
+
+use log::kv::{Error as KvError, Key, Value, VisitSource};
+use udptest::udp::{Sender, UDP_MAX_PAYLOAD};
+
+/// Message of a record along with its fields.
+type Entry = (String, Vec<(String, String)>);
+
+static CAPTURED: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        let mut fields = Fields(Vec::new());
+        record.key_values().visit(&mut fields).unwrap();
+        let entry = (record.args().to_string(), fields.0);
+        CAPTURED.lock().unwrap().push(entry);
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture;
+
+#[test]
+fn structured_log() {
+    log::set_logger(&CAPTURE).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let mut sender = Sender::new("127.0.0.1:0").unwrap();
+    sender.set_dry_run(true);
+    let data = [vec![0_u8; UDP_MAX_PAYLOAD + 1]];
+    sender.send(data.iter(), "127.0.0.1:1").unwrap();
+
+    let fields = CAPTURED
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(message, _)| message == "Item too large, truncated")
+        .map(|(_, fields)| fields.clone());
+    let expected = vec![
+        ("bytes".to_owned(), (UDP_MAX_PAYLOAD + 1).to_string()),
+        ("max".to_owned(), UDP_MAX_PAYLOAD.to_string()),
+    ];
+    assert_eq!(fields, Some(expected));
+}